@@ -0,0 +1,297 @@
+use crate::decompress::Compression;
+use crate::normalize::surt;
+use crate::warc::{WarcReader, WarcRecord};
+use serde_json::json;
+use sha1::{Digest, Sha1};
+use std::error::Error;
+use std::io::{self, BufRead, Read};
+
+/// One line of a CDXJ index: where a capture of a URL is stored in a WARC file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CdxEntry {
+    pub surt: String,
+    pub timestamp: String,
+    pub url: String,
+    pub mime: String,
+    pub status: Option<u16>,
+    pub digest: String,
+    pub offset: u64,
+    pub length: u64,
+    pub filename: String,
+}
+
+impl CdxEntry {
+    /// Serialize as a CDXJ line (without line ending), as used by Common Crawl and pywb.
+    pub fn to_cdxj(&self) -> String {
+        let mut fields = json!({
+            "url": self.url,
+            "mime": self.mime,
+            "digest": self.digest,
+            "length": self.length.to_string(),
+            "offset": self.offset.to_string(),
+            "filename": self.filename,
+        });
+        if let Some(status) = self.status {
+            fields["status"] = json!(status.to_string());
+        }
+        format!("{} {} {}", self.surt, self.timestamp, fields)
+    }
+
+    pub fn parse_cdxj(line: &str) -> Result<Self, Box<dyn Error>> {
+        let mut parts = line.splitn(3, ' ');
+        let (Some(surt), Some(timestamp), Some(fields)) =
+            (parts.next(), parts.next(), parts.next())
+        else {
+            return Err(format!("Invalid CDXJ line: {line}").into());
+        };
+
+        let fields: serde_json::Value = serde_json::from_str(fields)?;
+        let field = |name: &str| match &fields[name] {
+            serde_json::Value::String(s) => Some(s.clone()),
+            serde_json::Value::Number(n) => Some(n.to_string()),
+            _ => None,
+        };
+        let number = |name: &str| -> Result<u64, Box<dyn Error>> {
+            Ok(field(name)
+                .ok_or_else(|| format!("CDXJ line without {name}: {line}"))?
+                .parse()?)
+        };
+
+        Ok(Self {
+            surt: surt.to_string(),
+            timestamp: timestamp.to_string(),
+            url: field("url").unwrap_or_default(),
+            mime: field("mime").unwrap_or_default(),
+            status: field("status").and_then(|s| s.parse().ok()),
+            digest: field("digest").unwrap_or_default(),
+            offset: number("offset")?,
+            length: number("length")?,
+            filename: field("filename")
+                .ok_or_else(|| format!("CDXJ line without filename: {line}"))?,
+        })
+    }
+}
+
+/// Build index entries for the `response` and `revisit` records of a WARC file, sorted like a
+/// CDX index. Offsets and lengths refer to the file as stored, i.e. to the gzip member of a
+/// record in `.warc.gz` files.
+pub fn index_warc(reader: impl BufRead, filename: &str) -> Result<Vec<CdxEntry>, Box<dyn Error>> {
+    let mut reader = CountingReader {
+        inner: reader,
+        position: 0,
+    };
+    let mut entries = Vec::new();
+
+    if Compression::sniff(reader.fill_buf()?) == Some(Compression::Gzip) {
+        // each record is its own gzip member, which is what gets fetched by offset and length
+        while !reader.fill_buf()?.is_empty() {
+            let offset = reader.position;
+            let mut decoder = flate2::bufread::GzDecoder::new(reader);
+            let mut member = Vec::new();
+            decoder.read_to_end(&mut member)?;
+            reader = decoder.into_inner();
+            let length = reader.position - offset;
+
+            for record in WarcReader::new(&member[..]) {
+                entries.extend(entry(&record?, offset, length, filename));
+            }
+        }
+    } else {
+        let mut records = WarcReader::new(reader);
+        loop {
+            let offset = records.get_mut().position;
+            let Some(record) = records.next().transpose()? else {
+                break;
+            };
+            let length = records.get_mut().position - offset;
+            entries.extend(entry(&record, offset, length, filename));
+        }
+    }
+
+    entries.sort_by(|a, b| (&a.surt, &a.timestamp).cmp(&(&b.surt, &b.timestamp)));
+    Ok(entries)
+}
+
+/// Find the newest capture of `url` in CDXJ `index` contents.
+pub fn lookup(index: &str, url: &str) -> Result<Option<CdxEntry>, Box<dyn Error>> {
+    let key = surt(url).ok_or_else(|| format!("Invalid URL: {url}"))?;
+    let prefix = format!("{key} ");
+
+    let mut found = None;
+    for line in index.lines().filter(|line| line.starts_with(&prefix)) {
+        let entry = CdxEntry::parse_cdxj(line)?;
+        if found
+            .as_ref()
+            .is_none_or(|f: &CdxEntry| f.timestamp <= entry.timestamp)
+        {
+            found = Some(entry);
+        }
+    }
+
+    Ok(found)
+}
+
+fn entry(record: &WarcRecord, offset: u64, length: u64, filename: &str) -> Option<CdxEntry> {
+    let warc_type = record.record_type()?.to_ascii_lowercase();
+    if warc_type != "response" && warc_type != "revisit" {
+        return None;
+    }
+
+    let url = record.target_uri()?.to_string();
+    let response = record.http_response().ok();
+
+    let mime = if warc_type == "revisit" {
+        "warc/revisit".to_string()
+    } else {
+        response
+            .as_ref()
+            .and_then(|r| r.header("Content-Type"))
+            .and_then(|ct| ct.split(';').next())
+            .map(|mime| mime.trim().to_ascii_lowercase())
+            .unwrap_or_else(|| "unk".to_string())
+    };
+
+    let digest = match record.header("WARC-Payload-Digest") {
+        Some(digest) => digest
+            .split_once(':')
+            .map_or(digest, |(_, digest)| digest)
+            .to_string(),
+        None => base32::encode(
+            base32::Alphabet::Rfc4648 { padding: false },
+            &Sha1::digest(response.as_ref().map_or(&[][..], |r| &r.body)),
+        ),
+    };
+
+    Some(CdxEntry {
+        surt: surt(&url).unwrap_or_else(|| url.clone()),
+        timestamp: timestamp(record.date().unwrap_or_default()),
+        url,
+        mime,
+        status: response.map(|r| r.status),
+        digest,
+        offset,
+        length,
+        filename: filename.to_string(),
+    })
+}
+
+/// Turn a WARC-Date such as `2024-03-01T12:00:00Z` into a 14-digit CDX timestamp.
+fn timestamp(date: &str) -> String {
+    date.chars().filter(char::is_ascii_digit).take(14).collect()
+}
+
+/// Keeps track of how many bytes have been read from the inner reader.
+struct CountingReader<R> {
+    inner: R,
+    position: u64,
+}
+
+impl<R: Read> Read for CountingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        self.position += read as u64;
+        Ok(read)
+    }
+}
+
+impl<R: BufRead> BufRead for CountingReader<R> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        self.inner.fill_buf()
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.position += amt as u64;
+        self.inner.consume(amt);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    fn record(uri: &str, date: &str, body: &str) -> String {
+        let http =
+            format!("HTTP/1.1 200 OK\r\nContent-Type: text/html; charset=utf-8\r\n\r\n{body}");
+        format!(
+            "WARC/1.0\r\nWARC-Type: response\r\nWARC-Target-URI: {uri}\r\nWARC-Date: {date}\r\nContent-Length: {}\r\n\r\n{http}\r\n\r\n",
+            http.len()
+        )
+    }
+
+    #[test]
+    fn test_index_gzipped_warc() {
+        let records = [
+            record("https://b.example/", "2024-03-01T12:00:00Z", "<p>b</p>"),
+            record("https://a.example/", "2024-03-02T08:30:00Z", "<p>a</p>"),
+        ];
+        let mut members = Vec::new();
+        for record in &records {
+            let mut encoder =
+                flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+            encoder.write_all(record.as_bytes()).unwrap();
+            members.push(encoder.finish().unwrap());
+        }
+        let warc = members.concat();
+
+        let entries = index_warc(&warc[..], "crawl.warc.gz").unwrap();
+        assert_eq!(entries.len(), 2);
+
+        // sorted by SURT, so a.example comes first even though it's second in the file
+        assert_eq!(entries[0].surt, "example,a)/");
+        assert_eq!(entries[0].timestamp, "20240302083000");
+        assert_eq!(entries[0].offset, members[0].len() as u64);
+        assert_eq!(entries[0].length, members[1].len() as u64);
+        assert_eq!(entries[0].mime, "text/html");
+        assert_eq!(entries[0].status, Some(200));
+        assert_eq!(entries[0].digest.len(), 32);
+
+        assert_eq!(entries[1].offset, 0);
+        assert_eq!(entries[1].length, members[0].len() as u64);
+    }
+
+    #[test]
+    fn test_index_plain_warc() {
+        let first = record("https://a.example/", "2024-03-01T12:00:00Z", "<p>a</p>");
+        let second = record("https://b.example/", "2024-03-01T12:00:00Z", "<p>b</p>");
+        let warc = format!("{first}{second}");
+
+        let entries = index_warc(warc.as_bytes(), "crawl.warc").unwrap();
+        assert_eq!(entries[1].offset, first.len() as u64);
+        assert_eq!(entries[1].length, second.len() as u64);
+    }
+
+    #[test]
+    fn test_cdxj_roundtrip_and_lookup() {
+        let old = CdxEntry {
+            surt: "com,example)/jobs".to_string(),
+            timestamp: "20240101000000".to_string(),
+            url: "https://example.com/jobs".to_string(),
+            mime: "text/html".to_string(),
+            status: Some(200),
+            digest: "AAAA".to_string(),
+            offset: 10,
+            length: 20,
+            filename: "crawl.warc.gz".to_string(),
+        };
+        let new = CdxEntry {
+            timestamp: "20240201000000".to_string(),
+            offset: 30,
+            ..old.clone()
+        };
+
+        let line = old.to_cdxj();
+        assert_eq!(CdxEntry::parse_cdxj(&line).unwrap(), old);
+
+        let index = format!(
+            "{}\n{}\ncom,example)/other 20240101000000 {{}}\n",
+            old.to_cdxj(),
+            new.to_cdxj()
+        );
+        assert_eq!(
+            lookup(&index, "https://www.example.com/jobs").unwrap(),
+            Some(new)
+        );
+        assert_eq!(lookup(&index, "https://example.com/nope").unwrap(), None);
+    }
+}
@@ -0,0 +1,343 @@
+use crate::HqError;
+use encoding_rs::{Encoding, UTF_8, WINDOWS_1252};
+use std::fmt;
+
+/// How many bytes of the document the `<meta>` prescan looks at, as per the HTML spec.
+const PRESCAN_LIMIT: usize = 1024;
+
+/// Where the encoding used to decode a document came from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EncodingSource {
+    Override,
+    ByteOrderMark,
+    HttpHeader,
+    XmlDeclaration,
+    MetaTag,
+    Guess,
+}
+
+impl fmt::Display for EncodingSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Override => "override",
+            Self::ByteOrderMark => "byte order mark",
+            Self::HttpHeader => "HTTP Content-Type",
+            Self::XmlDeclaration => "<?xml?> declaration",
+            Self::MetaTag => "<meta> charset",
+            Self::Guess => "statistical guess",
+        })
+    }
+}
+
+/// A document decoded to UTF-8, along with the encoding that was chosen for it.
+#[derive(Debug, Clone)]
+pub struct Decoded {
+    pub html: String,
+    pub encoding: &'static Encoding,
+    pub source: EncodingSource,
+}
+
+/// Decode raw HTML bytes following the HTML encoding sniffing algorithm: a byte order mark,
+/// then an explicit override, then the `Content-Type` charset (if any), then the
+/// encoding of an XML declaration, then a `<meta>` prescan of the first 1024 bytes and
+/// finally a statistical guess.
+pub fn decode_html(
+    bytes: &[u8],
+    override_label: Option<&str>,
+    content_type: Option<&str>,
+) -> Result<Decoded, HqError> {
+    let override_encoding = override_label
+        .map(|label| {
+            Encoding::for_label(label.trim().as_bytes())
+                .ok_or_else(|| HqError::Encoding(format!("Unknown encoding: {label}")))
+        })
+        .transpose()?;
+
+    let (encoding, source, bom_len) = if let Some((encoding, bom_len)) = Encoding::for_bom(bytes) {
+        (encoding, EncodingSource::ByteOrderMark, bom_len)
+    } else if let Some(encoding) = override_encoding {
+        (encoding, EncodingSource::Override, 0)
+    } else if let Some(encoding) = content_type.and_then(charset_from_content_type) {
+        (encoding, EncodingSource::HttpHeader, 0)
+    } else if let Some(encoding) = xml_declaration(bytes) {
+        (encoding, EncodingSource::XmlDeclaration, 0)
+    } else if let Some(encoding) = prescan_meta(bytes) {
+        (encoding, EncodingSource::MetaTag, 0)
+    } else {
+        let mut detector = chardetng::EncodingDetector::new();
+        detector.feed(bytes, true);
+        (detector.guess(None, true), EncodingSource::Guess, 0)
+    };
+
+    let (html, _) = encoding.decode_without_bom_handling(&bytes[bom_len..]);
+
+    Ok(Decoded {
+        html: html.into_owned(),
+        encoding,
+        source,
+    })
+}
+
+/// Extract the encoding from the `charset` parameter of a `Content-Type` value.
+pub fn charset_from_content_type(content_type: &str) -> Option<&'static Encoding> {
+    let lower = content_type.to_ascii_lowercase();
+    let start = lower.find("charset")? + "charset".len();
+    let rest = lower[start..].trim_start().strip_prefix('=')?.trim_start();
+
+    let label = match rest.strip_prefix(['"', '\'']) {
+        Some(quoted) => quoted.split(['"', '\'']).next()?,
+        None => rest.split([';', ' ', '\t']).next()?,
+    };
+
+    Encoding::for_label(label.as_bytes())
+}
+
+/// The `encoding` of an `<?xml ...?>` declaration at the very start of the document.
+fn xml_declaration(bytes: &[u8]) -> Option<&'static Encoding> {
+    let declaration = bytes.strip_prefix(b"<?xml")?;
+    if !declaration.first()?.is_ascii_whitespace() {
+        return None;
+    }
+
+    let (attributes, _) = read_attributes(&declaration[..declaration.len().min(PRESCAN_LIMIT)]);
+    let (_, label) = attributes.iter().find(|(name, _)| name == "encoding")?;
+
+    // as with `<meta>`, a declaration readable as ASCII isn't really UTF-16
+    Encoding::for_label(label.as_bytes()).map(|encoding| match encoding.name() {
+        "UTF-16BE" | "UTF-16LE" => UTF_8,
+        _ => encoding,
+    })
+}
+
+/// Look for `<meta charset>` or `<meta http-equiv="content-type">` near the start of the document.
+fn prescan_meta(bytes: &[u8]) -> Option<&'static Encoding> {
+    let bytes = &bytes[..bytes.len().min(PRESCAN_LIMIT)];
+    let mut i = 0;
+
+    while i < bytes.len() {
+        let rest = &bytes[i..];
+
+        if rest.starts_with(b"<!--") {
+            i += find(&rest[4..], b"-->").map_or(rest.len(), |end| end + 4 + 3);
+        } else if starts_with_ignore_case(rest, b"<meta")
+            && rest
+                .get(5)
+                .is_some_and(|&b| b.is_ascii_whitespace() || b == b'/')
+        {
+            i += 5;
+            let (encoding, consumed) = meta_encoding(&bytes[i..]);
+            if encoding.is_some() {
+                return encoding;
+            }
+            i += consumed;
+        } else if rest.first() == Some(&b'<')
+            && rest
+                .get(1)
+                .is_some_and(|&b| b.is_ascii_alphabetic() || matches!(b, b'/' | b'!' | b'?'))
+        {
+            // skip over any other tag, including its attributes
+            i += 1;
+            let (_, consumed) = read_attributes(&bytes[i..]);
+            i += consumed;
+        } else {
+            i += 1;
+        }
+    }
+
+    None
+}
+
+fn meta_encoding(bytes: &[u8]) -> (Option<&'static Encoding>, usize) {
+    let (attributes, consumed) = read_attributes(bytes);
+
+    let mut http_equiv_content_type = false;
+    let mut charset = None;
+    let mut content_charset = None;
+
+    for (name, value) in &attributes {
+        match name.as_str() {
+            "http-equiv" => http_equiv_content_type = value.eq_ignore_ascii_case("content-type"),
+            "charset" if charset.is_none() => charset = Encoding::for_label(value.as_bytes()),
+            "content" if content_charset.is_none() => {
+                content_charset = charset_from_content_type(value);
+            }
+            _ => {}
+        }
+    }
+
+    let encoding = match (charset, content_charset) {
+        (Some(encoding), _) => Some(encoding),
+        (None, Some(encoding)) if http_equiv_content_type => Some(encoding),
+        _ => None,
+    };
+
+    // a document can't declare itself to be UTF-16 in ASCII, and x-user-defined is really 1252
+    let encoding = encoding.map(|encoding| match encoding.name() {
+        "UTF-16BE" | "UTF-16LE" => UTF_8,
+        "x-user-defined" => WINDOWS_1252,
+        _ => encoding,
+    });
+
+    (encoding, consumed)
+}
+
+/// Read attributes up to the end of the tag, returning them and the number of bytes consumed.
+fn read_attributes(bytes: &[u8]) -> (Vec<(String, String)>, usize) {
+    let mut attributes = Vec::new();
+    let mut i = 0;
+
+    loop {
+        while i < bytes.len() && (bytes[i].is_ascii_whitespace() || bytes[i] == b'/') {
+            i += 1;
+        }
+        if i >= bytes.len() || bytes[i] == b'>' {
+            return (attributes, (i + 1).min(bytes.len()));
+        }
+
+        let name_start = i;
+        while i < bytes.len() && !matches!(bytes[i], b'=' | b'>' | b'/') {
+            if bytes[i].is_ascii_whitespace() {
+                break;
+            }
+            i += 1;
+        }
+        let name = String::from_utf8_lossy(&bytes[name_start..i]).to_ascii_lowercase();
+
+        while i < bytes.len() && bytes[i].is_ascii_whitespace() {
+            i += 1;
+        }
+
+        let mut value = String::new();
+        if bytes.get(i) == Some(&b'=') {
+            i += 1;
+            while i < bytes.len() && bytes[i].is_ascii_whitespace() {
+                i += 1;
+            }
+            let value_start;
+            match bytes.get(i) {
+                Some(&quote) if quote == b'"' || quote == b'\'' => {
+                    i += 1;
+                    value_start = i;
+                    while i < bytes.len() && bytes[i] != quote {
+                        i += 1;
+                    }
+                    value = String::from_utf8_lossy(&bytes[value_start..i]).into_owned();
+                    i += 1;
+                }
+                _ => {
+                    value_start = i;
+                    while i < bytes.len() && !bytes[i].is_ascii_whitespace() && bytes[i] != b'>' {
+                        i += 1;
+                    }
+                    value = String::from_utf8_lossy(&bytes[value_start..i]).into_owned();
+                }
+            }
+        }
+
+        if name.is_empty() {
+            i += 1;
+        } else {
+            attributes.push((name, value));
+        }
+    }
+}
+
+fn starts_with_ignore_case(haystack: &[u8], needle: &[u8]) -> bool {
+    haystack.len() >= needle.len() && haystack[..needle.len()].eq_ignore_ascii_case(needle)
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).position(|w| w == needle)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use encoding_rs::{GB18030, SHIFT_JIS, UTF_16LE, WINDOWS_1251};
+
+    #[test]
+    fn test_bom() {
+        let decoded = decode_html(b"\xEF\xBB\xBF<p>caf\xC3\xA9</p>", None, None).unwrap();
+        assert_eq!(decoded.html, "<p>café</p>");
+        assert_eq!(decoded.source, EncodingSource::ByteOrderMark);
+
+        let decoded = decode_html(b"\xFF\xFE<\0p\0>\0", None, None).unwrap();
+        assert_eq!(decoded.encoding, UTF_16LE);
+        assert_eq!(decoded.html, "<p>");
+    }
+
+    #[test]
+    fn test_http_header_wins_over_meta() {
+        let (bytes, _, _) = WINDOWS_1251.encode("<meta charset=utf-8><p>Привет</p>");
+        let decoded =
+            decode_html(&bytes, None, Some("text/html; charset=\"windows-1251\"")).unwrap();
+        assert_eq!(decoded.encoding, WINDOWS_1251);
+        assert_eq!(decoded.source, EncodingSource::HttpHeader);
+        assert!(decoded.html.contains("Привет"));
+    }
+
+    #[test]
+    fn test_meta_charset() {
+        let (bytes, _, _) = SHIFT_JIS.encode(
+            "<!-- <meta charset=koi8-r> --><html><head><meta charset=\"Shift_JIS\"></head><p>求人</p>",
+        );
+        let decoded = decode_html(&bytes, None, None).unwrap();
+        assert_eq!(decoded.encoding, SHIFT_JIS);
+        assert_eq!(decoded.source, EncodingSource::MetaTag);
+        assert!(decoded.html.contains("求人"));
+    }
+
+    #[test]
+    fn test_meta_http_equiv() {
+        let html = b"<head><meta content='text/html; charset=gb18030' http-equiv=Content-Type>";
+        let decoded = decode_html(html, None, None).unwrap();
+        assert_eq!(decoded.encoding, GB18030);
+        assert_eq!(decoded.source, EncodingSource::MetaTag);
+    }
+
+    #[test]
+    fn test_xml_declaration() {
+        let (bytes, _, _) = WINDOWS_1251
+            .encode("<?xml version=\"1.0\" encoding='windows-1251'?>\n<title>Привет</title>");
+        let decoded = decode_html(&bytes, None, None).unwrap();
+        assert_eq!(decoded.encoding, WINDOWS_1251);
+        assert_eq!(decoded.source, EncodingSource::XmlDeclaration);
+        assert!(decoded.html.contains("Привет"));
+
+        let decoded = decode_html(b"<?xml version=\"1.0\"?><a/>", None, None).unwrap();
+        assert_ne!(decoded.source, EncodingSource::XmlDeclaration);
+    }
+
+    #[test]
+    fn test_meta_utf16_means_utf8() {
+        let decoded = decode_html(b"<meta charset=utf-16le>", None, None).unwrap();
+        assert_eq!(decoded.encoding, UTF_8);
+    }
+
+    #[test]
+    fn test_statistical_guess() {
+        let (bytes, _, _) =
+            WINDOWS_1251.encode("<p>Мы ищем опытного разработчика для нашей команды в Москве</p>");
+        let decoded = decode_html(&bytes, None, None).unwrap();
+        assert_eq!(decoded.encoding, WINDOWS_1251);
+        assert_eq!(decoded.source, EncodingSource::Guess);
+    }
+
+    #[test]
+    fn test_override() {
+        let decoded = decode_html(b"<p>caf\xE9</p>", Some("latin1"), None).unwrap();
+        assert_eq!(decoded.html, "<p>café</p>");
+        assert_eq!(decoded.encoding, WINDOWS_1252);
+        assert!(decode_html(b"", Some("no-such-encoding"), None).is_err());
+
+        // a byte order mark is more reliable than any label
+        let decoded = decode_html(
+            b"\xEF\xBB\xBF<p>caf\xC3\xA9</p>",
+            Some("windows-1252"),
+            None,
+        )
+        .unwrap();
+        assert_eq!(decoded.html, "<p>café</p>");
+        assert_eq!(decoded.source, EncodingSource::ByteOrderMark);
+    }
+}
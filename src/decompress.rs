@@ -0,0 +1,326 @@
+use flate2::read::MultiGzDecoder;
+use flate2::{Decompress, FlushDecompress, Status};
+use ruzstd::decoding::errors::{FrameDecoderError, ReadFrameHeaderError};
+use ruzstd::decoding::{BlockDecodingStrategy, FrameDecoder};
+use std::error::Error;
+use std::fmt;
+use std::io::{BufRead, BufReader, Cursor, Read};
+
+/// A compression format hq can transparently decompress input from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Compression {
+    Gzip,
+    Zlib,
+    Deflate,
+    Brotli,
+    Zstd,
+    Bzip2,
+    Xz,
+}
+
+impl fmt::Display for Compression {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Gzip => "gzip",
+            Self::Zlib => "zlib",
+            Self::Deflate => "deflate",
+            Self::Brotli => "brotli",
+            Self::Zstd => "zstd",
+            Self::Bzip2 => "bzip2",
+            Self::Xz => "xz",
+        })
+    }
+}
+
+impl Compression {
+    /// Detect the format from the magic bytes at the start of `data`.
+    ///
+    /// Brotli and raw deflate have no magic bytes, so they are never sniffed.
+    pub fn sniff(data: &[u8]) -> Option<Self> {
+        match data {
+            [0x1f, 0x8b, ..] => Some(Self::Gzip),
+            [0x28, 0xb5, 0x2f, 0xfd, ..] => Some(Self::Zstd),
+            [0xfd, b'7', b'z', b'X', b'Z', 0x00, ..] => Some(Self::Xz),
+            [b'B', b'Z', b'h', b'1'..=b'9', ..] => Some(Self::Bzip2),
+            // the usual compression levels; anything else is likely just text starting with "x"
+            [0x78, 0x01 | 0x5e | 0x9c | 0xda, ..] => Some(Self::Zlib),
+            _ => None,
+        }
+    }
+
+    /// Map a file extension such as `.gz` or `.br` to its format.
+    pub fn from_path(path: &str) -> Option<Self> {
+        let extension = path.rsplit_once('.')?.1.to_ascii_lowercase();
+        match extension.as_str() {
+            "gz" | "gzip" => Some(Self::Gzip),
+            "zz" => Some(Self::Zlib),
+            "br" => Some(Self::Brotli),
+            "zst" | "zstd" => Some(Self::Zstd),
+            "bz2" => Some(Self::Bzip2),
+            "xz" => Some(Self::Xz),
+            _ => None,
+        }
+    }
+
+    /// Map a single HTTP `Content-Encoding` token to its format.
+    pub fn from_content_encoding(token: &str) -> Option<Self> {
+        match token.trim().to_ascii_lowercase().as_str() {
+            "gzip" | "x-gzip" => Some(Self::Gzip),
+            // "deflate" is zlib-wrapped per RFC 9110, though some servers send it raw
+            "deflate" => Some(Self::Zlib),
+            "br" => Some(Self::Brotli),
+            "zstd" => Some(Self::Zstd),
+            "bzip2" | "x-bzip2" => Some(Self::Bzip2),
+            "xz" | "x-xz" => Some(Self::Xz),
+            _ => None,
+        }
+    }
+}
+
+/// Decompress `data` if it's compressed, detected by magic bytes or else by `hint`
+/// (e.g. from the file extension). Uncompressed data is returned as is.
+pub fn decompress(data: Vec<u8>, hint: Option<Compression>) -> Result<Vec<u8>, Box<dyn Error>> {
+    if let Some(format) = Compression::sniff(&data) {
+        return match decompress_as(&data, format) {
+            Ok(decompressed) => Ok(decompressed),
+            // zlib's magic bytes are weak, so fall back to treating the data as plain
+            Err(_) if format == Compression::Zlib => Ok(data),
+            Err(e) => Err(e),
+        };
+    }
+
+    match hint {
+        Some(format) => decompress_as(&data, format),
+        None => Ok(data),
+    }
+}
+
+/// Wrap `inner` so that reading from it yields decompressed data, like [`decompress`] but
+/// streaming for gzip (e.g. `.warc.gz`), bzip2 and brotli instead of reading everything first.
+pub fn reader<'a>(
+    mut inner: Box<dyn BufRead + 'a>,
+    hint: Option<Compression>,
+) -> Result<Box<dyn BufRead + 'a>, Box<dyn Error>> {
+    let format = Compression::sniff(inner.fill_buf()?).or(hint);
+
+    Ok(match format {
+        None => inner,
+        Some(Compression::Gzip) => {
+            Box::new(BufReader::new(flate2::bufread::MultiGzDecoder::new(inner)))
+        }
+        Some(Compression::Bzip2) => {
+            Box::new(BufReader::new(bzip2::bufread::MultiBzDecoder::new(inner)))
+        }
+        Some(Compression::Brotli) => {
+            Box::new(BufReader::new(brotli::Decompressor::new(inner, 4096)))
+        }
+        Some(_) => {
+            let mut data = Vec::new();
+            inner.read_to_end(&mut data)?;
+            Box::new(Cursor::new(decompress(data, hint)?))
+        }
+    })
+}
+
+/// Undo an HTTP `Content-Encoding`, which may list several codings in the order they were applied.
+pub fn decode_content_encoding(
+    mut data: Vec<u8>,
+    content_encoding: &str,
+) -> Result<Vec<u8>, Box<dyn Error>> {
+    for token in content_encoding.rsplit(',') {
+        let token = token.trim();
+        if token.is_empty() || token.eq_ignore_ascii_case("identity") {
+            continue;
+        }
+
+        let format = Compression::from_content_encoding(token)
+            .ok_or_else(|| format!("Unsupported Content-Encoding: {token}"))?;
+
+        data = match (format, decompress_as(&data, format)) {
+            (_, Ok(decompressed)) => decompressed,
+            (Compression::Zlib, Err(_)) => decompress_as(&data, Compression::Deflate)?,
+            (_, Err(e)) => return Err(e),
+        };
+    }
+
+    Ok(data)
+}
+
+/// Decompress `data` as the given format, reading all members/frames/streams it contains.
+pub fn decompress_as(data: &[u8], format: Compression) -> Result<Vec<u8>, Box<dyn Error>> {
+    let mut decompressed = Vec::new();
+
+    match format {
+        Compression::Gzip => {
+            MultiGzDecoder::new(data).read_to_end(&mut decompressed)?;
+        }
+        Compression::Zlib => inflate(data, true, &mut decompressed)?,
+        Compression::Deflate => inflate(data, false, &mut decompressed)?,
+        Compression::Brotli => {
+            brotli::Decompressor::new(data, 4096).read_to_end(&mut decompressed)?;
+        }
+        Compression::Zstd => decompress_zstd(data, &mut decompressed)?,
+        Compression::Bzip2 => {
+            bzip2::read::MultiBzDecoder::new(data).read_to_end(&mut decompressed)?;
+        }
+        Compression::Xz => {
+            lzma_rs::xz_decompress(&mut BufReader::new(data), &mut decompressed)?;
+        }
+    }
+
+    Ok(decompressed)
+}
+
+/// Inflate a zlib or raw deflate stream, failing unless it is complete. Unlike flate2's readers
+/// this notices truncated input, which matters for telling zlib apart from text starting with "x".
+fn inflate(data: &[u8], zlib_header: bool, output: &mut Vec<u8>) -> Result<(), Box<dyn Error>> {
+    let mut inflater = Decompress::new(zlib_header);
+    output.reserve(data.len() * 4);
+
+    loop {
+        if output.len() == output.capacity() {
+            output.reserve(output.capacity().max(4096));
+        }
+
+        let consumed = inflater.total_in() as usize;
+        let status = inflater.decompress_vec(&data[consumed..], output, FlushDecompress::Finish)?;
+
+        match status {
+            Status::StreamEnd => return Ok(()),
+            _ if inflater.total_in() as usize == data.len() && output.len() < output.capacity() => {
+                return Err("Truncated deflate stream".into());
+            }
+            _ => {}
+        }
+    }
+}
+
+fn decompress_zstd(mut data: &[u8], output: &mut Vec<u8>) -> Result<(), Box<dyn Error>> {
+    let mut decoder = FrameDecoder::new();
+
+    while !data.is_empty() {
+        match decoder.init(&mut data) {
+            Ok(()) => {}
+            Err(FrameDecoderError::ReadFrameHeaderError(ReadFrameHeaderError::SkipFrame {
+                length,
+                ..
+            })) => {
+                data = data
+                    .get(length as usize..)
+                    .ok_or("Truncated zstd skippable frame")?;
+                continue;
+            }
+            Err(e) => return Err(e.into()),
+        }
+
+        while !decoder.is_finished() {
+            decoder.decode_blocks(&mut data, BlockDecodingStrategy::UptoBlocks(1))?;
+            decoder.collect_to_writer(&mut *output)?;
+        }
+        decoder.collect_to_writer(&mut *output)?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    const HTML: &[u8] = b"<html><body><p>Hello, compressed world!</p></body></html>";
+
+    fn gzip(data: &[u8]) -> Vec<u8> {
+        let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        encoder.write_all(data).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    #[test]
+    fn test_plain_passthrough() {
+        assert_eq!(decompress(HTML.to_vec(), None).unwrap(), HTML);
+        assert_eq!(decompress(b"x^2 + 1".to_vec(), None).unwrap(), b"x^2 + 1");
+    }
+
+    #[test]
+    fn test_multi_member_gzip() {
+        let mut data = gzip(&HTML[..20]);
+        data.extend(gzip(&HTML[20..]));
+        assert_eq!(decompress(data, None).unwrap(), HTML);
+    }
+
+    #[test]
+    fn test_zlib_and_deflate() {
+        let mut encoder =
+            flate2::write::ZlibEncoder::new(Vec::new(), flate2::Compression::default());
+        encoder.write_all(HTML).unwrap();
+        assert_eq!(decompress(encoder.finish().unwrap(), None).unwrap(), HTML);
+
+        let mut encoder =
+            flate2::write::DeflateEncoder::new(Vec::new(), flate2::Compression::default());
+        encoder.write_all(HTML).unwrap();
+        let raw = encoder.finish().unwrap();
+        assert_eq!(decode_content_encoding(raw, "deflate").unwrap(), HTML);
+    }
+
+    #[test]
+    fn test_brotli_needs_hint() {
+        let mut compressed = Vec::new();
+        {
+            let mut encoder = brotli::CompressorWriter::new(&mut compressed, 4096, 9, 22);
+            encoder.write_all(HTML).unwrap();
+        }
+        assert_eq!(
+            decompress(compressed.clone(), Compression::from_path("page.html.br")).unwrap(),
+            HTML
+        );
+        assert_eq!(decode_content_encoding(compressed, "br").unwrap(), HTML);
+    }
+
+    #[test]
+    fn test_zstd() {
+        let mut data = ruzstd::encoding::compress_to_vec(
+            &HTML[..20],
+            ruzstd::encoding::CompressionLevel::Fastest,
+        );
+        data.extend(ruzstd::encoding::compress_to_vec(
+            &HTML[20..],
+            ruzstd::encoding::CompressionLevel::Fastest,
+        ));
+        assert_eq!(decompress(data, None).unwrap(), HTML);
+    }
+
+    #[test]
+    fn test_bzip2() {
+        let mut encoder = bzip2::write::BzEncoder::new(Vec::new(), bzip2::Compression::default());
+        encoder.write_all(HTML).unwrap();
+        assert_eq!(decompress(encoder.finish().unwrap(), None).unwrap(), HTML);
+    }
+
+    #[test]
+    fn test_xz() {
+        let mut compressed = Vec::new();
+        lzma_rs::xz_compress(&mut BufReader::new(HTML), &mut compressed).unwrap();
+        assert_eq!(decompress(compressed, None).unwrap(), HTML);
+    }
+
+    #[test]
+    fn test_stacked_content_encoding() {
+        let mut compressed = Vec::new();
+        {
+            let mut encoder = brotli::CompressorWriter::new(&mut compressed, 4096, 9, 22);
+            encoder.write_all(&gzip(HTML)).unwrap();
+        }
+        assert_eq!(
+            decode_content_encoding(compressed, "gzip, br").unwrap(),
+            HTML
+        );
+        assert!(decode_content_encoding(HTML.to_vec(), "compress").is_err());
+    }
+
+    #[test]
+    fn test_truncated_gzip_is_an_error() {
+        let data = gzip(HTML);
+        assert!(decompress(data[..data.len() / 2].to_vec(), None).is_err());
+    }
+}
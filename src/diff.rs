@@ -0,0 +1,774 @@
+use crate::pretty_print::{escape, is_void};
+use anstyle::{AnsiColor, Style};
+use kuchikiki::{NodeData, NodeRef};
+use serde_json::json;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet, VecDeque};
+use std::hash::{Hash, Hasher};
+use std::io;
+use std::io::Write;
+
+/// A difference between two documents. Paths are CSS selectors of the element in the document
+/// it's found in, or of its parent for text.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Change {
+    Added {
+        path: String,
+        html: String,
+    },
+    Removed {
+        path: String,
+        html: String,
+    },
+    /// An element that's unchanged itself but has moved among its siblings.
+    Moved {
+        from: String,
+        to: String,
+        html: String,
+    },
+    /// An attribute that was added (without `old`), removed (without `new`) or changed.
+    Attribute {
+        path: String,
+        name: String,
+        old: Option<String>,
+        new: Option<String>,
+    },
+    Text {
+        path: String,
+        old: String,
+        new: String,
+    },
+}
+
+impl Change {
+    pub fn to_json(&self) -> serde_json::Value {
+        match self {
+            Change::Added { path, html } => json!({"change": "added", "path": path, "html": html}),
+            Change::Removed { path, html } => {
+                json!({"change": "removed", "path": path, "html": html})
+            }
+            Change::Moved { from, to, html } => {
+                json!({"change": "moved", "from": from, "to": to, "html": html})
+            }
+            Change::Attribute {
+                path,
+                name,
+                old,
+                new,
+            } => json!({"change": "attribute", "path": path, "name": name, "old": old, "new": new}),
+            Change::Text { path, old, new } => {
+                json!({"change": "text", "path": path, "old": old, "new": new})
+            }
+        }
+    }
+}
+
+/// A node as it's compared: elements with sorted attributes and text with its whitespace
+/// collapsed. Comments and the like are left out.
+#[derive(Debug)]
+struct Node {
+    path: String,
+    /// Hash of the node and everything in it, but not of its path.
+    hash: u64,
+    kind: Kind,
+}
+
+#[derive(Debug)]
+enum Kind {
+    Element {
+        name: String,
+        attributes: BTreeMap<String, String>,
+        children: Vec<Node>,
+    },
+    Text(String),
+}
+
+fn collapse_whitespace(text: &str) -> String {
+    text.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+/// How an element is told apart from its siblings in a path: by its id, or else by its position
+/// among the siblings of the same name, if there are any.
+fn path_segment(node: &NodeRef) -> String {
+    let Some(element) = node.as_element() else {
+        return String::new();
+    };
+    let name = &*element.name.local;
+    if let Some(id) = element.attributes.borrow().get("id")
+        && !id.is_empty()
+        && !id.contains(char::is_whitespace)
+    {
+        return format!("{name}#{id}");
+    }
+
+    let same_name = |sibling: &NodeRef| {
+        sibling
+            .as_element()
+            .is_some_and(|sibling| sibling.name.local == element.name.local)
+    };
+    let before = node.preceding_siblings().filter(same_name).count();
+    let after = node.following_siblings().filter(same_name).count();
+    if before + after == 0 {
+        name.to_string()
+    } else {
+        format!("{name}:nth-of-type({})", before + 1)
+    }
+}
+
+/// The path of an element from the root of its document.
+fn element_path(node: &NodeRef) -> String {
+    let mut segments: Vec<String> = node
+        .inclusive_ancestors()
+        .filter(|node| node.as_element().is_some())
+        .map(|node| path_segment(&node))
+        .collect();
+    segments.reverse();
+    segments.join(" > ")
+}
+
+fn canonicalize(node: &NodeRef, path: String) -> Option<Node> {
+    let element = node.as_element()?;
+    let attributes: BTreeMap<String, String> = element
+        .attributes
+        .borrow()
+        .map
+        .iter()
+        .map(|(name, value)| (name.local.to_string(), collapse_whitespace(&value.value)))
+        .collect();
+
+    let mut children = Vec::new();
+    // text around left out comments is merged
+    let mut text = String::new();
+    for child in node.children() {
+        match child.data() {
+            NodeData::Text(contents) => text.push_str(&contents.borrow()),
+            NodeData::Element(_) => {
+                push_text(&mut children, &mut text, &path);
+                let child_path = format!("{path} > {}", path_segment(&child));
+                children.extend(canonicalize(&child, child_path));
+            }
+            _ => {}
+        }
+    }
+    push_text(&mut children, &mut text, &path);
+
+    let name = element.name.local.to_string();
+    let mut hasher = DefaultHasher::new();
+    name.hash(&mut hasher);
+    attributes.hash(&mut hasher);
+    for child in &children {
+        child.hash.hash(&mut hasher);
+    }
+
+    Some(Node {
+        path,
+        hash: hasher.finish(),
+        kind: Kind::Element {
+            name,
+            attributes,
+            children,
+        },
+    })
+}
+
+/// Add the text collected so far to `children`, unless it's only whitespace.
+fn push_text(children: &mut Vec<Node>, text: &mut String, path: &str) {
+    let collapsed = collapse_whitespace(&std::mem::take(text));
+    if collapsed.is_empty() {
+        return;
+    }
+    let mut hasher = DefaultHasher::new();
+    collapsed.hash(&mut hasher);
+    children.push(Node {
+        path: path.to_string(),
+        hash: hasher.finish(),
+        kind: Kind::Text(collapsed),
+    });
+}
+
+impl Node {
+    /// The canonical form of the node as HTML.
+    fn to_html(&self) -> String {
+        match &self.kind {
+            Kind::Text(text) => escape(text, false),
+            Kind::Element {
+                name,
+                attributes,
+                children,
+            } => {
+                let mut html = format!("<{name}");
+                for (name, value) in attributes {
+                    html.push_str(&format!(" {name}=\"{}\"", escape(value, true)));
+                }
+                html.push('>');
+                if !is_void(name) {
+                    for child in children {
+                        html.push_str(&child.to_html());
+                    }
+                    html.push_str(&format!("</{name}>"));
+                }
+                html
+            }
+        }
+    }
+
+    /// Nodes that aren't the same are taken to be versions of each other if this is the same:
+    /// both text, or elements of the same name with the same id.
+    fn correspondence(&self) -> (Option<&str>, Option<&str>) {
+        match &self.kind {
+            Kind::Text(_) => (None, None),
+            Kind::Element {
+                name, attributes, ..
+            } => (Some(name), attributes.get("id").map(String::as_str)),
+        }
+    }
+}
+
+/// Above this many pairs of old and new children to compare, children that are unique on both
+/// sides are matched up first, and only what's in between is compared pair by pair.
+const MAX_COMPARISONS: usize = 1 << 22;
+
+/// The longest common subsequence of two lists of hashes, as pairs of indexes. Long lists
+/// only get a long common subsequence, not necessarily the longest, see [`MAX_COMPARISONS`].
+fn longest_common_subsequence(old: &[u64], new: &[u64]) -> Vec<(usize, usize)> {
+    // the same start and end are common, whatever is in between
+    let prefix = old.iter().zip(new).take_while(|(a, b)| a == b).count();
+    let suffix = old[prefix..]
+        .iter()
+        .rev()
+        .zip(new[prefix..].iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count();
+    let (old_middle, new_middle) = (
+        &old[prefix..old.len() - suffix],
+        &new[prefix..new.len() - suffix],
+    );
+
+    let mut pairs: Vec<(usize, usize)> = (0..prefix).map(|i| (i, i)).collect();
+
+    // hashes only on one side can't be common, so compare only those on both, like the rows
+    // of a long table where most rows changed
+    let (old_hashes, new_hashes): (HashSet<u64>, HashSet<u64>) = (
+        old_middle.iter().copied().collect(),
+        new_middle.iter().copied().collect(),
+    );
+    let old_common: Vec<usize> = (0..old_middle.len())
+        .filter(|&i| new_hashes.contains(&old_middle[i]))
+        .collect();
+    let new_common: Vec<usize> = (0..new_middle.len())
+        .filter(|&j| old_hashes.contains(&new_middle[j]))
+        .collect();
+    if old_common.len() < old_middle.len() || new_common.len() < new_middle.len() {
+        let common = longest_common_subsequence(
+            &old_common
+                .iter()
+                .map(|&i| old_middle[i])
+                .collect::<Vec<_>>(),
+            &new_common
+                .iter()
+                .map(|&j| new_middle[j])
+                .collect::<Vec<_>>(),
+        );
+        pairs.extend(
+            common
+                .into_iter()
+                .map(|(i, j)| (prefix + old_common[i], prefix + new_common[j])),
+        );
+        pairs.extend((0..suffix).map(|k| (old.len() - suffix + k, new.len() - suffix + k)));
+        return pairs;
+    }
+
+    let anchors = if old_middle.len() * new_middle.len() > MAX_COMPARISONS {
+        unique_anchors(old_middle, new_middle)
+    } else {
+        Vec::new()
+    };
+    if anchors.is_empty() {
+        hirschberg(old_middle, new_middle, (prefix, prefix), &mut pairs);
+    } else {
+        let mut start = (0, 0);
+        let end = (old_middle.len(), new_middle.len());
+        for anchor in anchors.into_iter().chain([end]) {
+            let between = longest_common_subsequence(
+                &old_middle[start.0..anchor.0],
+                &new_middle[start.1..anchor.1],
+            );
+            pairs.extend(
+                between
+                    .into_iter()
+                    .map(|(i, j)| (prefix + start.0 + i, prefix + start.1 + j)),
+            );
+            if anchor != end {
+                pairs.push((prefix + anchor.0, prefix + anchor.1));
+            }
+            start = (anchor.0 + 1, anchor.1 + 1);
+        }
+    }
+    pairs.extend((0..suffix).map(|k| (old.len() - suffix + k, new.len() - suffix + k)));
+    pairs
+}
+
+/// The longest run of hashes that occur exactly once in both `old` and `new`, in the same
+/// order in both, as pairs of indexes: the anchors of a patience diff.
+fn unique_anchors(old: &[u64], new: &[u64]) -> Vec<(usize, usize)> {
+    // how often each hash occurs in `old` and `new`, and where it was last seen in each
+    let mut occurrences: HashMap<u64, [(usize, usize); 2]> = HashMap::new();
+    for (side, hashes) in [old, new].into_iter().enumerate() {
+        for (index, hash) in hashes.iter().enumerate() {
+            let seen = &mut occurrences.entry(*hash).or_default()[side];
+            *seen = (seen.0 + 1, index);
+        }
+    }
+    let mut unique: Vec<(usize, usize)> = occurrences
+        .into_values()
+        .filter(|[(old_count, _), (new_count, _)]| *old_count == 1 && *new_count == 1)
+        .map(|[(_, i), (_, j)]| (i, j))
+        .collect();
+    unique.sort_unstable();
+
+    // the longest increasing subsequence of their indexes in `new`, by patience sorting
+    let mut piles: Vec<usize> = Vec::new();
+    let mut previous: Vec<Option<usize>> = vec![None; unique.len()];
+    for (k, &(_, j)) in unique.iter().enumerate() {
+        let pile = piles.partition_point(|&top| unique[top].1 < j);
+        previous[k] = pile.checked_sub(1).map(|below| piles[below]);
+        if pile == piles.len() {
+            piles.push(k);
+        } else {
+            piles[pile] = k;
+        }
+    }
+
+    let mut anchors = Vec::new();
+    let mut next = piles.last().copied();
+    while let Some(k) = next {
+        anchors.push(unique[k]);
+        next = previous[k];
+    }
+    anchors.reverse();
+    anchors
+}
+
+/// The lengths of the longest common subsequences of `old` and each prefix of `new`, in a
+/// single row rather than the whole table.
+fn lcs_lengths(old: &[u64], new: &[u64]) -> Vec<u32> {
+    let mut previous = vec![0u32; new.len() + 1];
+    let mut current = vec![0u32; new.len() + 1];
+    for a in old {
+        for (j, b) in new.iter().enumerate() {
+            current[j + 1] = if a == b {
+                previous[j] + 1
+            } else {
+                current[j].max(previous[j + 1])
+            };
+        }
+        std::mem::swap(&mut previous, &mut current);
+    }
+    previous
+}
+
+/// Hirschberg's algorithm: the longest common subsequence in linear space, by splitting `old`
+/// in half and `new` where the halves' subsequences add up to the longest, so that pages with
+/// many thousands of changed rows don't need a table of their product. Pairs of indexes are
+/// offset by `offset` and pushed in order.
+fn hirschberg(old: &[u64], new: &[u64], offset: (usize, usize), pairs: &mut Vec<(usize, usize)>) {
+    if old.is_empty() || new.is_empty() {
+        return;
+    }
+    if let [hash] = old {
+        if let Some(j) = new.iter().position(|h| h == hash) {
+            pairs.push((offset.0, offset.1 + j));
+        }
+        return;
+    }
+
+    let middle = old.len() / 2;
+    let reversed = |hashes: &[u64]| hashes.iter().rev().copied().collect::<Vec<_>>();
+    let before = lcs_lengths(&old[..middle], new);
+    let after = lcs_lengths(&reversed(&old[middle..]), &reversed(new));
+    let split = (0..=new.len())
+        .max_by_key(|&k| (before[k] + after[new.len() - k], std::cmp::Reverse(k)))
+        .unwrap_or(0);
+
+    hirschberg(&old[..middle], &new[..split], offset, pairs);
+    hirschberg(
+        &old[middle..],
+        &new[split..],
+        (offset.0 + middle, offset.1 + split),
+        pairs,
+    );
+}
+
+fn diff_nodes(old: &Node, new: &Node, changes: &mut Vec<Change>) {
+    match (&old.kind, &new.kind) {
+        (Kind::Text(old_text), Kind::Text(new_text)) => changes.push(Change::Text {
+            path: new.path.clone(),
+            old: old_text.clone(),
+            new: new_text.clone(),
+        }),
+        (
+            Kind::Element {
+                attributes: old_attributes,
+                children: old_children,
+                ..
+            },
+            Kind::Element {
+                attributes: new_attributes,
+                children: new_children,
+                ..
+            },
+        ) => {
+            for (name, old_value) in old_attributes {
+                let new_value = new_attributes.get(name);
+                if new_value != Some(old_value) {
+                    changes.push(Change::Attribute {
+                        path: new.path.clone(),
+                        name: name.clone(),
+                        old: Some(old_value.clone()),
+                        new: new_value.cloned(),
+                    });
+                }
+            }
+            for (name, new_value) in new_attributes {
+                if !old_attributes.contains_key(name) {
+                    changes.push(Change::Attribute {
+                        path: new.path.clone(),
+                        name: name.clone(),
+                        old: None,
+                        new: Some(new_value.clone()),
+                    });
+                }
+            }
+            diff_children(old_children, new_children, changes);
+        }
+        _ => {
+            changes.push(Change::Removed {
+                path: old.path.clone(),
+                html: old.to_html(),
+            });
+            changes.push(Change::Added {
+                path: new.path.clone(),
+                html: new.to_html(),
+            });
+        }
+    }
+}
+
+/// What became of a node in the new list.
+enum Origin {
+    Unchanged(usize),
+    Added,
+    Moved(usize),
+    Changed(usize),
+}
+
+fn diff_children(old: &[Node], new: &[Node], changes: &mut Vec<Change>) {
+    let old_hashes: Vec<u64> = old.iter().map(|node| node.hash).collect();
+    let new_hashes: Vec<u64> = new.iter().map(|node| node.hash).collect();
+
+    let mut old_matched = vec![false; old.len()];
+    let mut origins: Vec<Origin> = new.iter().map(|_| Origin::Added).collect();
+    for (i, j) in longest_common_subsequence(&old_hashes, &new_hashes) {
+        old_matched[i] = true;
+        origins[j] = Origin::Unchanged(i);
+    }
+
+    // what's the same but out of order has moved
+    let mut unmatched_by_hash: HashMap<u64, VecDeque<usize>> = HashMap::new();
+    for i in (0..old.len()).filter(|&i| !old_matched[i]) {
+        unmatched_by_hash
+            .entry(old[i].hash)
+            .or_default()
+            .push_back(i);
+    }
+    for j in 0..new.len() {
+        if matches!(origins[j], Origin::Added)
+            && let Some(i) = unmatched_by_hash
+                .get_mut(&new[j].hash)
+                .and_then(VecDeque::pop_front)
+        {
+            old_matched[i] = true;
+            origins[j] = Origin::Moved(i);
+        }
+    }
+
+    // what's left is paired up in order with what corresponds to it
+    let mut unmatched_by_correspondence: HashMap<_, BTreeSet<usize>> = HashMap::new();
+    for i in (0..old.len()).filter(|&i| !old_matched[i]) {
+        unmatched_by_correspondence
+            .entry(old[i].correspondence())
+            .or_default()
+            .insert(i);
+    }
+    let mut next_old = 0;
+    for j in 0..new.len() {
+        match origins[j] {
+            // keep the pairs in order
+            Origin::Unchanged(i) => next_old = i + 1,
+            Origin::Added => {
+                if let Some(unmatched) =
+                    unmatched_by_correspondence.get_mut(&new[j].correspondence())
+                    && let Some(&i) = unmatched.range(next_old..).next()
+                {
+                    unmatched.remove(&i);
+                    old_matched[i] = true;
+                    origins[j] = Origin::Changed(i);
+                    next_old = i + 1;
+                }
+            }
+            Origin::Moved(_) | Origin::Changed(_) => {}
+        }
+    }
+
+    for (i, node) in old.iter().enumerate() {
+        if !old_matched[i] {
+            changes.push(Change::Removed {
+                path: node.path.clone(),
+                html: node.to_html(),
+            });
+        }
+    }
+    for (j, node) in new.iter().enumerate() {
+        match origins[j] {
+            Origin::Unchanged(_) => {}
+            Origin::Added => changes.push(Change::Added {
+                path: node.path.clone(),
+                html: node.to_html(),
+            }),
+            Origin::Moved(i) => changes.push(Change::Moved {
+                from: old[i].path.clone(),
+                to: node.path.clone(),
+                html: node.to_html(),
+            }),
+            Origin::Changed(i) => diff_nodes(&old[i], node, changes),
+        }
+    }
+}
+
+/// Compare the elements matched in an old and a new version of a document, after normalizing
+/// their whitespace and the order of their attributes.
+pub fn diff(old: &[NodeRef], new: &[NodeRef]) -> Vec<Change> {
+    let canonicalize_all = |nodes: &[NodeRef]| -> Vec<Node> {
+        nodes
+            .iter()
+            .filter_map(|node| canonicalize(node, element_path(node)))
+            .collect()
+    };
+    let mut changes = Vec::new();
+    diff_children(&canonicalize_all(old), &canonicalize_all(new), &mut changes);
+    changes
+}
+
+/// Write changes as lines of text, highlighted with ANSI colours if `color` is set: `-` for
+/// what was removed, `+` for what was added, `>` for what moved and `~` for what changed.
+pub fn write_changes(changes: &[Change], color: bool, output: &mut dyn Write) -> io::Result<()> {
+    let style = |color_of: AnsiColor| {
+        if color {
+            Style::new().fg_color(Some(color_of.into()))
+        } else {
+            Style::new()
+        }
+    };
+    let (removed, added, changed) = (
+        style(AnsiColor::Red),
+        style(AnsiColor::Green),
+        style(AnsiColor::Yellow),
+    );
+
+    for change in changes {
+        match change {
+            Change::Added { path, html } => {
+                writeln!(output, "{added}+ {path}: {html}{added:#}")?;
+            }
+            Change::Removed { path, html } => {
+                writeln!(output, "{removed}- {path}: {html}{removed:#}")?;
+            }
+            Change::Moved { from, to, html } => {
+                writeln!(output, "{changed}> {from} -> {to}: {html}{changed:#}")?;
+            }
+            Change::Attribute {
+                path,
+                name,
+                old: Some(old),
+                new: Some(new),
+            } => {
+                writeln!(
+                    output,
+                    "{changed}~ {path}[{name}]: {old:?} -> {new:?}{changed:#}"
+                )?;
+            }
+            Change::Attribute {
+                path,
+                name,
+                old: None,
+                new: Some(new),
+            } => {
+                writeln!(output, "{added}+ {path}[{name}={new:?}]{added:#}")?;
+            }
+            Change::Attribute {
+                path,
+                name,
+                old: Some(old),
+                new: None,
+            } => {
+                writeln!(output, "{removed}- {path}[{name}={old:?}]{removed:#}")?;
+            }
+            Change::Attribute { .. } => {}
+            Change::Text { path, old, new } => {
+                writeln!(output, "{changed}~ {path}: {old:?} -> {new:?}{changed:#}")?;
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use kuchikiki::traits::TendrilSink;
+
+    fn diff_documents(old: &str, new: &str, selector: &str) -> Vec<Change> {
+        let (old, new) = (
+            kuchikiki::parse_html().one(old),
+            kuchikiki::parse_html().one(new),
+        );
+        let select = |document: &NodeRef| -> Vec<NodeRef> {
+            document
+                .select(selector)
+                .unwrap()
+                .map(|element| element.as_node().clone())
+                .collect()
+        };
+        diff(&select(&old), &select(&new))
+    }
+
+    #[test]
+    fn test_canonical_documents_are_equal() {
+        let old = "<div class=\"job\" id=\"x\">\n  <h1>Engineer</h1>\n  <p>Some   text</p><!-- built at 10:00 -->\n</div>";
+        let new = "<div id=\"x\" class=\"job\"><h1> Engineer </h1><p>Some text</p><!-- built at 11:00 --></div>";
+        assert_eq!(diff_documents(old, new, "div"), vec![]);
+    }
+
+    #[test]
+    fn test_longest_common_subsequence() {
+        assert_eq!(
+            longest_common_subsequence(&[1, 2, 3, 4, 5], &[1, 3, 9, 4, 5]),
+            vec![(0, 0), (2, 1), (3, 3), (4, 4)]
+        );
+        assert_eq!(
+            longest_common_subsequence(&[7, 1, 2, 3, 8], &[9, 2, 3, 1, 6]),
+            vec![(2, 1), (3, 2)]
+        );
+        assert!(longest_common_subsequence(&[1, 2], &[3, 4]).is_empty());
+        assert_eq!(
+            unique_anchors(&[5, 1, 2, 1, 3, 4], &[1, 3, 2, 4, 5]),
+            vec![(4, 1), (5, 3)]
+        );
+
+        // long lists changed at both ends, which would need a table of their product
+        let old: Vec<u64> = (0..20_000).collect();
+        let new: Vec<u64> = (1..20_000).chain([0]).collect();
+        let pairs = longest_common_subsequence(&old, &new);
+        assert_eq!(pairs.len(), 19_999);
+        assert_eq!(pairs[0], (1, 0));
+
+        // long lists where most of the children changed, with nothing unique to anchor on
+        let old: Vec<u64> = (0..20_000)
+            .map(|i| if i % 100 == 0 { 1 } else { i })
+            .collect();
+        let new: Vec<u64> = (0..20_000)
+            .map(|i| if i % 100 == 0 { 1 } else { i + 1_000_000 })
+            .collect();
+        assert_eq!(longest_common_subsequence(&old, &new).len(), 200);
+    }
+
+    #[test]
+    fn test_changes() {
+        let old = r#"<div id="job"><h1 class="title">Engineer</h1>
+            <ul><li>Pension</li><li>Snacks</li><li>Gym</li></ul>
+            <p>Apply <a href="/old">here</a></p><p>Old footer</p></div>"#;
+        let new = r#"<div id="job"><h1 class="title" lang="en">Senior Engineer</h1>
+            <ul><li>Gym</li><li>Pension</li><li>Remote</li></ul>
+            <p>Apply <a href="/new">here</a></p></div>"#;
+        assert_eq!(
+            diff_documents(old, new, "#job"),
+            vec![
+                Change::Removed {
+                    path: "html > body > div#job > p:nth-of-type(2)".to_string(),
+                    html: "<p>Old footer</p>".to_string(),
+                },
+                Change::Attribute {
+                    path: "html > body > div#job > h1".to_string(),
+                    name: "lang".to_string(),
+                    old: None,
+                    new: Some("en".to_string()),
+                },
+                Change::Text {
+                    path: "html > body > div#job > h1".to_string(),
+                    old: "Engineer".to_string(),
+                    new: "Senior Engineer".to_string(),
+                },
+                Change::Removed {
+                    path: "html > body > div#job > ul > li:nth-of-type(2)".to_string(),
+                    html: "<li>Snacks</li>".to_string(),
+                },
+                Change::Moved {
+                    from: "html > body > div#job > ul > li:nth-of-type(1)".to_string(),
+                    to: "html > body > div#job > ul > li:nth-of-type(2)".to_string(),
+                    html: "<li>Pension</li>".to_string(),
+                },
+                Change::Added {
+                    path: "html > body > div#job > ul > li:nth-of-type(3)".to_string(),
+                    html: "<li>Remote</li>".to_string(),
+                },
+                Change::Attribute {
+                    path: "html > body > div#job > p > a".to_string(),
+                    name: "href".to_string(),
+                    old: Some("/old".to_string()),
+                    new: Some("/new".to_string()),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_output() {
+        let changes = vec![
+            Change::Removed {
+                path: "ul > li:nth-of-type(2)".to_string(),
+                html: "<li>Snacks</li>".to_string(),
+            },
+            Change::Attribute {
+                path: "a".to_string(),
+                name: "href".to_string(),
+                old: Some("/old".to_string()),
+                new: Some("/new".to_string()),
+            },
+            Change::Attribute {
+                path: "a".to_string(),
+                name: "target".to_string(),
+                old: None,
+                new: Some("_blank".to_string()),
+            },
+        ];
+
+        let mut output = Vec::new();
+        write_changes(&changes, false, &mut output).unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "- ul > li:nth-of-type(2): <li>Snacks</li>\n~ a[href]: \"/old\" -> \"/new\"\n+ a[target=\"_blank\"]\n"
+        );
+
+        let mut output = Vec::new();
+        write_changes(&changes[..1], true, &mut output).unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "\x1b[31m- ul > li:nth-of-type(2): <li>Snacks</li>\x1b[0m\n"
+        );
+
+        assert_eq!(
+            changes[1].to_json(),
+            json!({"change": "attribute", "path": "a", "name": "href", "old": "/old", "new": "/new"})
+        );
+    }
+}
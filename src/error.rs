@@ -0,0 +1,349 @@
+use cssparser::{BasicParseErrorKind, CowRcStr, ParseError, ParseErrorKind, SourceLocation, ToCss};
+use selectors::parser::{
+    NonTSPseudoClass, Parser, PseudoElement, SelectorImpl, SelectorList, SelectorParseErrorKind,
+};
+use std::fmt;
+use std::io;
+
+/// Everything that can go wrong when running a query.
+#[derive(Debug)]
+pub enum HqError {
+    /// A CSS selector that doesn't parse, with the character position where parsing failed.
+    InvalidSelector {
+        selector: String,
+        position: usize,
+        reason: String,
+    },
+    /// An option with an invalid value, such as a malformed base URL or JSON path.
+    InvalidArgument(String),
+    /// An unknown encoding label, or input that can't be decoded.
+    Encoding(String),
+    Io(io::Error),
+    /// Text that was supposed to be JSON but isn't.
+    Json(String),
+    /// A document that couldn't be fetched, with the upstream HTTP status if there was one.
+    Fetch {
+        status: Option<u16>,
+        message: String,
+    },
+    /// Input that isn't in the format it should be in, e.g. a truncated WARC record.
+    Input(String),
+}
+
+impl HqError {
+    /// Describe why `selector` doesn't parse, pointing at where exactly.
+    pub fn invalid_selector(selector: &str) -> Self {
+        Self::diagnose_selector(selector, DiagnosticParser { namespaces: false })
+    }
+
+    /// Like [`HqError::invalid_selector`], for a selector on XML, where namespace prefixes are
+    /// those of the document.
+    pub fn invalid_xml_selector(selector: &str) -> Self {
+        Self::diagnose_selector(selector, DiagnosticParser { namespaces: true })
+    }
+
+    fn diagnose_selector(selector: &str, diagnostic_parser: DiagnosticParser) -> Self {
+        let mut input = cssparser::ParserInput::new(selector);
+        let mut parser = cssparser::Parser::new(&mut input);
+
+        let (location, reason) = match SelectorList::parse(&diagnostic_parser, &mut parser) {
+            Ok(_) => (
+                parser.current_source_location(),
+                "invalid selector".to_string(),
+            ),
+            Err(e) => (e.location, describe(&e)),
+        };
+
+        Self::InvalidSelector {
+            selector: selector.to_string(),
+            position: char_position(selector, location),
+            reason,
+        }
+    }
+}
+
+impl fmt::Display for HqError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::InvalidSelector {
+                selector,
+                position,
+                reason,
+            } => {
+                // selectors spanning several lines are shown on one, so the caret lines up
+                let selector = selector.replace(['\n', '\r', '\t'], " ");
+                write!(
+                    f,
+                    "Invalid CSS selector: {reason}\n  {selector}\n  {}^",
+                    " ".repeat(*position)
+                )
+            }
+            Self::InvalidArgument(message)
+            | Self::Encoding(message)
+            | Self::Json(message)
+            | Self::Input(message) => f.write_str(message),
+            Self::Io(e) => e.fmt(f),
+            Self::Fetch {
+                status: Some(status),
+                message,
+            } => write!(f, "{message} (HTTP {status})"),
+            Self::Fetch {
+                status: None,
+                message,
+            } => f.write_str(message),
+        }
+    }
+}
+
+impl std::error::Error for HqError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for HqError {
+    fn from(e: io::Error) -> Self {
+        Self::Io(e)
+    }
+}
+
+/// The WARC, CDXJ and decompression helpers report malformed input as plain messages, which
+/// become [`HqError::Input`]. I/O errors among them stay I/O errors.
+impl From<Box<dyn std::error::Error>> for HqError {
+    fn from(e: Box<dyn std::error::Error>) -> Self {
+        let e = match e.downcast::<HqError>() {
+            Ok(e) => return *e,
+            Err(e) => e,
+        };
+        match e.downcast::<io::Error>() {
+            Ok(e) => Self::Io(*e),
+            Err(e) => Self::Input(e.to_string()),
+        }
+    }
+}
+
+impl From<serde_json::Error> for HqError {
+    fn from(e: serde_json::Error) -> Self {
+        Self::Json(e.to_string())
+    }
+}
+
+fn describe(error: &ParseError<'_, SelectorParseErrorKind<'_>>) -> String {
+    match &error.kind {
+        ParseErrorKind::Basic(BasicParseErrorKind::EndOfInput) => {
+            "unexpected end of selector".to_string()
+        }
+        ParseErrorKind::Basic(BasicParseErrorKind::UnexpectedToken(token)) => {
+            format!("unexpected `{}`", token.to_css_string())
+        }
+        ParseErrorKind::Basic(other) => format!("{other:?}"),
+        ParseErrorKind::Custom(kind) => match kind {
+            SelectorParseErrorKind::EmptySelector => "empty selector".to_string(),
+            SelectorParseErrorKind::DanglingCombinator => {
+                "combinator without a selector after it".to_string()
+            }
+            SelectorParseErrorKind::UnsupportedPseudoClassOrElement(name) => {
+                format!("unsupported pseudo-class or pseudo-element `{name}`")
+            }
+            SelectorParseErrorKind::ClassNeedsIdent(token) => {
+                format!("expected a class name, got `{}`", token.to_css_string())
+            }
+            SelectorParseErrorKind::UnexpectedIdent(name) => format!("unexpected `{name}`"),
+            SelectorParseErrorKind::ExpectedNamespace(prefix) => {
+                format!("unknown namespace prefix `{prefix}`")
+            }
+            SelectorParseErrorKind::EmptyNegation => "empty :not()".to_string(),
+            other => format!("{other:?}"),
+        },
+    }
+}
+
+/// Convert a parser location (1-based UTF-16 column on a 0-based line) into a character index.
+fn char_position(selector: &str, location: SourceLocation) -> usize {
+    let line_start: usize = selector
+        .split_inclusive('\n')
+        .take(location.line as usize)
+        .map(|line| line.chars().count())
+        .sum();
+
+    let line = selector
+        .split_inclusive('\n')
+        .nth(location.line as usize)
+        .unwrap_or_default();
+
+    let mut utf16 = 0;
+    let mut chars = 0;
+    for c in line.chars() {
+        if utf16 + 1 >= location.column as usize {
+            break;
+        }
+        utf16 += c.len_utf16();
+        chars += 1;
+    }
+
+    line_start + chars
+}
+
+// kuchikiki doesn't tell where a selector failed to parse, so failed selectors are parsed again
+// with a parser accepting the same syntax, which does.
+
+#[derive(Debug, Clone)]
+struct DiagnosticSelectors;
+
+impl SelectorImpl for DiagnosticSelectors {
+    type AttrValue = String;
+    type Identifier = String;
+    type ClassName = String;
+    type LocalName = String;
+    type PartName = String;
+    type NamespacePrefix = String;
+    type NamespaceUrl = String;
+    type BorrowedNamespaceUrl = String;
+    type BorrowedLocalName = String;
+
+    type NonTSPseudoClass = PseudoClass;
+    type PseudoElement = NoPseudoElement;
+
+    type ExtraMatchingData = ();
+}
+
+struct DiagnosticParser {
+    /// Whether any namespace prefix is accepted, rather than none.
+    namespaces: bool,
+}
+
+/// The pseudo-classes kuchikiki supports.
+const PSEUDO_CLASSES: [&str; 10] = [
+    "any-link",
+    "link",
+    "visited",
+    "active",
+    "focus",
+    "hover",
+    "enabled",
+    "disabled",
+    "checked",
+    "indeterminate",
+];
+
+impl<'i> Parser<'i> for DiagnosticParser {
+    type Impl = DiagnosticSelectors;
+    type Error = SelectorParseErrorKind<'i>;
+
+    fn namespace_for_prefix(&self, prefix: &String) -> Option<String> {
+        self.namespaces.then(|| prefix.clone())
+    }
+
+    fn parse_non_ts_pseudo_class(
+        &self,
+        location: SourceLocation,
+        name: CowRcStr<'i>,
+    ) -> Result<PseudoClass, ParseError<'i, SelectorParseErrorKind<'i>>> {
+        if PSEUDO_CLASSES
+            .iter()
+            .any(|class| name.eq_ignore_ascii_case(class))
+        {
+            Ok(PseudoClass(name.to_ascii_lowercase()))
+        } else {
+            Err(
+                location.new_custom_error(SelectorParseErrorKind::UnsupportedPseudoClassOrElement(
+                    name,
+                )),
+            )
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct PseudoClass(String);
+
+impl NonTSPseudoClass for PseudoClass {
+    type Impl = DiagnosticSelectors;
+
+    fn is_active_or_hover(&self) -> bool {
+        false
+    }
+
+    fn is_user_action_state(&self) -> bool {
+        false
+    }
+
+    fn has_zero_specificity(&self) -> bool {
+        false
+    }
+}
+
+impl ToCss for PseudoClass {
+    fn to_css<W: fmt::Write>(&self, dest: &mut W) -> fmt::Result {
+        write!(dest, ":{}", self.0)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum NoPseudoElement {}
+
+impl PseudoElement for NoPseudoElement {
+    type Impl = DiagnosticSelectors;
+}
+
+impl ToCss for NoPseudoElement {
+    fn to_css<W: fmt::Write>(&self, _dest: &mut W) -> fmt::Result {
+        match *self {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn position(selector: &str) -> (usize, String) {
+        match HqError::invalid_selector(selector) {
+            HqError::InvalidSelector {
+                position, reason, ..
+            } => (position, reason),
+            other => panic!("unexpected error {other:?}"),
+        }
+    }
+
+    #[test]
+    fn test_selector_error_position() {
+        assert_eq!(
+            position("div > > p"),
+            (6, "combinator without a selector after it".to_string())
+        );
+        assert_eq!(
+            position("li:nope"),
+            (
+                3,
+                "unsupported pseudo-class or pseudo-element `nope`".to_string()
+            )
+        );
+        assert_eq!(
+            position("a["),
+            (2, "unexpected end of selector".to_string())
+        );
+        assert_eq!(
+            position("ä ö, ."),
+            (6, "unexpected end of selector".to_string())
+        );
+    }
+
+    #[test]
+    fn test_selector_error_display() {
+        assert_eq!(
+            HqError::invalid_selector("div >> p").to_string(),
+            "Invalid CSS selector: combinator without a selector after it\n  div >> p\n       ^"
+        );
+        assert_eq!(
+            HqError::invalid_selector("image|loc").to_string(),
+            "Invalid CSS selector: unknown namespace prefix `image`\n  image|loc\n       ^"
+        );
+        assert!(matches!(
+            HqError::invalid_xml_selector("image|loc >"),
+            HqError::InvalidSelector { position: 11, .. }
+        ));
+    }
+}
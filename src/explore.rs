@@ -0,0 +1,425 @@
+use crate::pretty_print::pretty_print;
+use crate::{HqConfig, HqError, Query};
+use kuchikiki::NodeRef;
+use kuchikiki::iter::NodeIterator;
+use rustyline::completion::{Completer, Pair};
+use rustyline::error::ReadlineError;
+use rustyline::highlight::Highlighter;
+use rustyline::hint::Hinter;
+use rustyline::history::DefaultHistory;
+use rustyline::validate::Validator;
+use rustyline::{Context, Editor, Helper};
+use std::collections::BTreeSet;
+use std::io::{self, Write};
+use std::path::Path;
+
+pub const HELP: &str = "\
+Type a selector to see how many elements match it and a preview of them.
+  :text           preview the text of matches
+  :html           preview matches as HTML (the default)
+  :pretty         preview matches pretty printed
+  :attr NAME      preview the value of an attribute
+  :parent [N]     go to the parents of the matches, or of match N only
+  :children [N]   go to the child elements of the matches, or of match N only
+  :all            show all matches instead of the first few
+  :help           show this help
+  :quit           leave (as does Ctrl-D)";
+
+/// Commands, as they're completed.
+const COMMANDS: [&str; 9] = [
+    ":text",
+    ":html",
+    ":pretty",
+    ":attr",
+    ":parent",
+    ":children",
+    ":all",
+    ":help",
+    ":quit",
+];
+
+/// How many matches are previewed.
+const PREVIEW_MATCHES: usize = 5;
+
+/// How many characters of a match are previewed on its line.
+const PREVIEW_WIDTH: usize = 120;
+
+/// How many lines of a pretty printed match are previewed.
+const PREVIEW_LINES: usize = 10;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Mode {
+    Html,
+    Text,
+    Pretty,
+    Attribute(String),
+}
+
+/// The state of a session: the document, what's selected in it and how it's shown.
+pub struct Explorer {
+    root: NodeRef,
+    matches: Vec<NodeRef>,
+    mode: Mode,
+}
+
+/// Collapse whitespace and cut `text` off at `width` characters.
+fn one_line(text: &str, width: usize) -> String {
+    let collapsed = text.split_whitespace().collect::<Vec<_>>().join(" ");
+    match collapsed.char_indices().nth(width) {
+        Some((end, _)) => format!("{}…", &collapsed[..end]),
+        None => collapsed,
+    }
+}
+
+impl Explorer {
+    pub fn new(root: NodeRef) -> Self {
+        Explorer {
+            root,
+            matches: Vec::new(),
+            mode: Mode::Html,
+        }
+    }
+
+    /// Run a line of input, returning what to show, or `None` to end the session.
+    pub fn execute(&mut self, line: &str) -> Option<String> {
+        let line = line.trim();
+        let (command, argument) = line
+            .split_once(char::is_whitespace)
+            .map_or((line, ""), |(command, argument)| (command, argument.trim()));
+
+        match command {
+            "" => Some(String::new()),
+            ":quit" | ":q" => None,
+            ":help" => Some(HELP.to_string()),
+            ":text" => Some(self.switch_mode(Mode::Text)),
+            ":html" => Some(self.switch_mode(Mode::Html)),
+            ":pretty" => Some(self.switch_mode(Mode::Pretty)),
+            ":attr" if argument.is_empty() => Some("Which attribute? e.g. :attr href".to_string()),
+            ":attr" => Some(self.switch_mode(Mode::Attribute(argument.to_string()))),
+            ":all" => Some(self.preview(self.matches.len())),
+            ":parent" | ":children" => Some(
+                self.navigate(command == ":parent", argument)
+                    .unwrap_or_else(|message| message),
+            ),
+            _ => Some(match self.select(line) {
+                Ok(()) => self.preview(PREVIEW_MATCHES),
+                Err(e) => e.to_string(),
+            }),
+        }
+    }
+
+    fn switch_mode(&mut self, mode: Mode) -> String {
+        self.mode = mode;
+        self.preview(PREVIEW_MATCHES)
+    }
+
+    fn select(&mut self, selector: &str) -> Result<(), HqError> {
+        let query = Query::compile(&HqConfig {
+            selector: selector.to_string(),
+            ..Default::default()
+        })?;
+        self.matches = query
+            .matches(&self.root)
+            .map(|found| found.node().clone())
+            .collect();
+        Ok(())
+    }
+
+    /// Go to the parents or the children of the matches, or of the match numbered `argument`.
+    fn navigate(&mut self, parents: bool, argument: &str) -> Result<String, String> {
+        let from = if argument.is_empty() {
+            self.matches.clone()
+        } else {
+            let number: usize = argument
+                .parse()
+                .map_err(|_| format!("Not a match number: {argument}"))?;
+            let found = number
+                .checked_sub(1)
+                .and_then(|index| self.matches.get(index))
+                .ok_or_else(|| format!("No match {number}, there are {}", self.matches.len()))?;
+            vec![found.clone()]
+        };
+
+        let mut matches: Vec<NodeRef> = Vec::new();
+        for node in from {
+            let next: Vec<NodeRef> = if parents {
+                node.parent()
+                    .filter(|parent| parent.as_element().is_some())
+                    .into_iter()
+                    .collect()
+            } else {
+                node.children()
+                    .filter(|child| child.as_element().is_some())
+                    .collect()
+            };
+            for node in next {
+                // siblings share their parent
+                if !matches.contains(&node) {
+                    matches.push(node);
+                }
+            }
+        }
+        self.matches = matches;
+        Ok(self.preview(PREVIEW_MATCHES))
+    }
+
+    /// The number of matches and a preview of the first `limit` of them.
+    fn preview(&self, limit: usize) -> String {
+        let mut preview = match self.matches.len() {
+            0 => "No matches".to_string(),
+            1 => "1 match".to_string(),
+            count => format!("{count} matches"),
+        };
+
+        for (index, node) in self.matches.iter().take(limit).enumerate() {
+            let shown = match &self.mode {
+                Mode::Html => one_line(&node.to_string(), PREVIEW_WIDTH),
+                Mode::Text => one_line(&node.text_contents(), PREVIEW_WIDTH),
+                Mode::Attribute(name) => node
+                    .as_element()
+                    .and_then(|element| {
+                        element
+                            .attributes
+                            .borrow()
+                            .get(name.as_str())
+                            .map(str::to_string)
+                    })
+                    .map_or_else(
+                        || "(none)".to_string(),
+                        |value| one_line(&value, PREVIEW_WIDTH),
+                    ),
+                Mode::Pretty => {
+                    let pretty = pretty_print(node);
+                    let mut lines: Vec<&str> = pretty.lines().take(PREVIEW_LINES + 1).collect();
+                    if lines.len() > PREVIEW_LINES {
+                        lines[PREVIEW_LINES] = "…";
+                    }
+                    format!("\n{}", lines.join("\n"))
+                }
+            };
+            preview.push_str(&format!("\n[{}] {shown}", index + 1));
+        }
+        if self.matches.len() > limit {
+            preview.push_str(&format!(
+                "\n… and {} more, :all shows them",
+                self.matches.len() - limit
+            ));
+        }
+        preview
+    }
+}
+
+/// The names in a document that selectors are made of, for completion.
+#[derive(Debug, Default)]
+struct Names {
+    tags: BTreeSet<String>,
+    classes: BTreeSet<String>,
+    ids: BTreeSet<String>,
+}
+
+impl Names {
+    fn of(root: &NodeRef) -> Self {
+        let mut names = Names::default();
+        for element in root.descendants().elements() {
+            names.tags.insert(element.name.local.to_string());
+            let attributes = element.attributes.borrow();
+            if let Some(classes) = attributes.get("class") {
+                names
+                    .classes
+                    .extend(classes.split_whitespace().map(str::to_string));
+            }
+            if let Some(id) = attributes.get("id").filter(|id| !id.is_empty()) {
+                names.ids.insert(id.to_string());
+            }
+        }
+        names
+    }
+
+    /// Complete the name before `pos` in `line`: a class after `.`, an id after `#`, a tag at
+    /// the start of a compound selector and a command at the start of the line.
+    fn complete(&self, line: &str, pos: usize) -> (usize, Vec<String>) {
+        let before = &line[..pos];
+        if before.starts_with(':') && !before.contains(char::is_whitespace) {
+            let commands = COMMANDS
+                .iter()
+                .filter(|command| command.starts_with(before))
+                .map(|command| command.to_string())
+                .collect();
+            return (0, commands);
+        }
+
+        let start = before
+            .rfind(|c: char| !(c.is_alphanumeric() || c == '-' || c == '_'))
+            .map_or(0, |index| index + 1);
+        let prefix = &before[start..];
+        let candidates = match before[..start].chars().next_back() {
+            Some('.') => &self.classes,
+            Some('#') => &self.ids,
+            None | Some(' ' | '>' | '+' | '~' | ',' | '(') => &self.tags,
+            _ => return (start, Vec::new()),
+        };
+        let completions = candidates
+            .iter()
+            .filter(|name| name.starts_with(prefix))
+            .cloned()
+            .collect();
+        (start, completions)
+    }
+}
+
+struct ExploreHelper {
+    names: Names,
+}
+
+impl Completer for ExploreHelper {
+    type Candidate = Pair;
+
+    fn complete(
+        &self,
+        line: &str,
+        pos: usize,
+        _ctx: &Context<'_>,
+    ) -> rustyline::Result<(usize, Vec<Pair>)> {
+        let (start, names) = self.names.complete(line, pos);
+        let pairs = names
+            .into_iter()
+            .map(|name| Pair {
+                display: name.clone(),
+                replacement: name,
+            })
+            .collect();
+        Ok((start, pairs))
+    }
+}
+
+impl Hinter for ExploreHelper {
+    type Hint = String;
+}
+
+impl Highlighter for ExploreHelper {}
+
+impl Validator for ExploreHelper {}
+
+impl Helper for ExploreHelper {}
+
+/// Run an interactive session to try out selectors on `root`, reading lines from the terminal
+/// and writing to `output`. History is kept in `history_path` if given.
+///
+/// Each line is either a selector, whose matches are previewed right away, or one of the
+/// commands in [`HELP`].
+pub fn run(root: NodeRef, history_path: Option<&Path>, output: &mut dyn Write) -> io::Result<()> {
+    let mut editor: Editor<ExploreHelper, DefaultHistory> =
+        Editor::new().map_err(io::Error::other)?;
+    editor.set_helper(Some(ExploreHelper {
+        names: Names::of(&root),
+    }));
+    if let Some(path) = history_path {
+        // there's no history the first time
+        let _ = editor.load_history(path);
+    }
+
+    let mut explorer = Explorer::new(root);
+    loop {
+        let line = match editor.readline("hq> ") {
+            Ok(line) => line,
+            Err(ReadlineError::Interrupted) => continue,
+            Err(ReadlineError::Eof) => break,
+            Err(e) => return Err(io::Error::other(e)),
+        };
+        if !line.trim().is_empty() {
+            let _ = editor.add_history_entry(line.as_str());
+        }
+        match explorer.execute(&line) {
+            Some(shown) if shown.is_empty() => {}
+            Some(shown) => writeln!(output, "{shown}")?,
+            None => break,
+        }
+        output.flush()?;
+    }
+
+    if let Some(path) = history_path {
+        editor.save_history(path).map_err(io::Error::other)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use kuchikiki::traits::TendrilSink;
+
+    const PAGE: &str = r#"<div id="jobs"><ul class="list">
+        <li class="job"><a href="/1">Rust   engineer</a></li>
+        <li class="job featured"><a href="/2">Designer</a></li>
+        </ul></div>"#;
+
+    #[test]
+    fn test_selectors_and_modes() {
+        let mut explorer = Explorer::new(kuchikiki::parse_html().one(PAGE));
+        assert_eq!(
+            explorer.execute("li.job a").unwrap(),
+            "2 matches\n[1] <a href=\"/1\">Rust engineer</a>\n[2] <a href=\"/2\">Designer</a>"
+        );
+        assert_eq!(
+            explorer.execute(":text").unwrap(),
+            "2 matches\n[1] Rust engineer\n[2] Designer"
+        );
+        assert_eq!(
+            explorer.execute(":attr href").unwrap(),
+            "2 matches\n[1] /1\n[2] /2"
+        );
+        assert_eq!(explorer.execute("  ").unwrap(), "");
+        assert_eq!(explorer.execute("table").unwrap(), "No matches");
+        assert!(
+            explorer
+                .execute("li..job")
+                .unwrap()
+                .starts_with("Invalid CSS selector")
+        );
+        assert_eq!(explorer.execute(":quit"), None);
+    }
+
+    #[test]
+    fn test_navigation() {
+        let mut explorer = Explorer::new(kuchikiki::parse_html().one(PAGE));
+        explorer.execute(":attr class");
+        assert_eq!(
+            explorer.execute("a").unwrap(),
+            "2 matches\n[1] (none)\n[2] (none)"
+        );
+        assert_eq!(
+            explorer.execute(":parent").unwrap(),
+            "2 matches\n[1] job\n[2] job featured"
+        );
+        assert_eq!(explorer.execute(":parent 2").unwrap(), "1 match\n[1] list");
+        assert_eq!(
+            explorer.execute(":children").unwrap(),
+            "2 matches\n[1] job\n[2] job featured"
+        );
+        assert_eq!(
+            explorer.execute(":parent 3").unwrap(),
+            "No match 3, there are 2"
+        );
+
+        explorer.execute(":text");
+        explorer.execute("*");
+        let preview = explorer.execute(":children").unwrap();
+        assert!(preview.starts_with("8 matches\n[1] "));
+        assert!(preview.ends_with("… and 3 more, :all shows them"));
+        assert_eq!(explorer.execute(":all").unwrap().lines().count(), 9);
+    }
+
+    #[test]
+    fn test_completion() {
+        let names = Names::of(&kuchikiki::parse_html().one(PAGE));
+        assert_eq!(names.complete("l", 1), (0, vec!["li".to_string()]));
+        assert_eq!(names.complete("ul li.j", 7), (6, vec!["job".to_string()]));
+        assert_eq!(names.complete("div#", 4), (4, vec!["jobs".to_string()]));
+        assert_eq!(
+            names.complete(".list > ", 8).1,
+            ["a", "body", "div", "head", "html", "li", "ul"]
+        );
+        assert_eq!(names.complete(":pa", 3), (0, vec![":parent".to_string()]));
+        assert_eq!(names.complete("li:fi", 5), (3, Vec::<String>::new()));
+    }
+}
@@ -0,0 +1,253 @@
+use crate::pretty_print::is_inline;
+use kuchikiki::{NodeData, NodeRef};
+use serde_json::json;
+use sha1::{Digest, Sha1};
+
+/// How many words make up a shingle.
+const SHINGLE_SIZE: usize = 3;
+
+/// Elements whose text isn't rendered.
+const HIDDEN_ELEMENTS: [&str; 5] = ["script", "style", "template", "noscript", "head"];
+
+/// Inline elements that break lines, and so keep the words on either side apart.
+const LINE_BREAKS: [&str; 2] = ["br", "wbr"];
+
+/// The text of a node as it's rendered: without scripts and styles, and with blocks kept apart.
+pub fn rendered_text(node: &NodeRef) -> String {
+    let mut text = String::new();
+    collect_text(node, &mut text);
+    text
+}
+
+fn collect_text(node: &NodeRef, text: &mut String) {
+    match node.data() {
+        NodeData::Text(contents) => text.push_str(&contents.borrow()),
+        NodeData::Element(element) => {
+            let name = &*element.name.local;
+            if HIDDEN_ELEMENTS.contains(&name) {
+                return;
+            }
+            let separate = !is_inline(name) || LINE_BREAKS.contains(&name);
+            if separate {
+                text.push(' ');
+            }
+            for child in node.children() {
+                collect_text(&child, text);
+            }
+            if separate {
+                text.push(' ');
+            }
+        }
+        NodeData::Document(_) | NodeData::DocumentFragment => {
+            for child in node.children() {
+                collect_text(&child, text);
+            }
+        }
+        _ => {}
+    }
+}
+
+/// The words of `text`, lowercased and without punctuation, separated by single spaces.
+pub fn normalize_text(text: &str) -> String {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(str::to_lowercase)
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// A hash of a shingle that's the same on every platform and in every version: FNV-1a, with
+/// the bits mixed like SplitMix64 does, since SimHash needs every bit to be as good as any.
+fn hash_shingle(words: &[&str]) -> u64 {
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    for (i, word) in words.iter().enumerate() {
+        let separator = if i > 0 { " " } else { "" };
+        for byte in separator.bytes().chain(word.bytes()) {
+            hash ^= u64::from(byte);
+            hash = hash.wrapping_mul(0x0100_0000_01b3);
+        }
+    }
+    hash ^= hash >> 30;
+    hash = hash.wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    hash ^= hash >> 27;
+    hash = hash.wrapping_mul(0x94d0_49bb_1331_11eb);
+    hash ^ (hash >> 31)
+}
+
+/// The SimHash of the shingles of `words`. Texts shorter than a shingle are one shingle.
+fn simhash(words: &[&str]) -> u64 {
+    if words.is_empty() {
+        return 0;
+    }
+
+    let mut weights = [0i64; 64];
+    for shingle in words.windows(SHINGLE_SIZE.min(words.len())) {
+        let hash = hash_shingle(shingle);
+        for (bit, weight) in weights.iter_mut().enumerate() {
+            *weight += if hash >> bit & 1 == 1 { 1 } else { -1 };
+        }
+    }
+
+    weights
+        .iter()
+        .enumerate()
+        .filter(|(_, weight)| **weight > 0)
+        .fold(0, |simhash, (bit, _)| simhash | 1 << bit)
+}
+
+/// A fingerprint of the text of a document, to find the same content in different markup: an
+/// exact SHA-1 of the normalized text plus a 64-bit [SimHash] over shingles of three words, so
+/// that near-duplicates have fingerprints that differ in only a few bits.
+///
+/// [SimHash]: https://en.wikipedia.org/wiki/SimHash
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Fingerprint {
+    /// Hex SHA-1 of the normalized text, the same for the same words.
+    pub sha1: String,
+    /// SimHash of the normalized text, similar for similar words.
+    pub simhash: u64,
+}
+
+impl Fingerprint {
+    pub fn of_text(text: &str) -> Self {
+        let normalized = normalize_text(text);
+        let words: Vec<&str> = normalized.split(' ').filter(|w| !w.is_empty()).collect();
+        Fingerprint {
+            sha1: Sha1::digest(normalized.as_bytes())
+                .iter()
+                .map(|byte| format!("{byte:02x}"))
+                .collect(),
+            simhash: simhash(&words),
+        }
+    }
+
+    pub fn of_node(node: &NodeRef) -> Self {
+        Self::of_text(&rendered_text(node))
+    }
+
+    /// How similar the texts are, from 0 to 1 for the same words: the share of SimHash bits
+    /// that are the same.
+    pub fn similarity(&self, other: &Fingerprint) -> f64 {
+        if self.sha1 == other.sha1 {
+            return 1.0;
+        }
+        1.0 - f64::from((self.simhash ^ other.simhash).count_ones()) / 64.0
+    }
+
+    pub fn to_json(&self) -> serde_json::Value {
+        json!({
+            "sha1": self.sha1,
+            "simhash": format!("{:016x}", self.simhash),
+        })
+    }
+}
+
+/// Group fingerprints whose similarity is at least `threshold`, directly or through others.
+/// Returns the cluster of each fingerprint, numbered from 0 in the order they first appear.
+pub fn cluster(fingerprints: &[Fingerprint], threshold: f64) -> Vec<usize> {
+    // union-find, with each cluster represented by its first member
+    let mut parents: Vec<usize> = (0..fingerprints.len()).collect();
+    fn root(parents: &mut [usize], mut i: usize) -> usize {
+        while parents[i] != i {
+            parents[i] = parents[parents[i]];
+            i = parents[i];
+        }
+        i
+    }
+
+    for i in 0..fingerprints.len() {
+        for j in i + 1..fingerprints.len() {
+            if fingerprints[i].similarity(&fingerprints[j]) >= threshold {
+                let (a, b) = (root(&mut parents, i), root(&mut parents, j));
+                parents[a.max(b)] = a.min(b);
+            }
+        }
+    }
+
+    let mut numbers = vec![None; fingerprints.len()];
+    let mut next = 0;
+    (0..fingerprints.len())
+        .map(|i| {
+            let first = root(&mut parents, i);
+            *numbers[first].get_or_insert_with(|| {
+                next += 1;
+                next - 1
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use kuchikiki::traits::TendrilSink;
+
+    const POSTING: &str = "Senior Rust Engineer. We are looking for an experienced engineer to build \
+        our crawling infrastructure. You will work on parsers, storage and distributed \
+        scheduling. Remote work is possible within Europe, and we offer a pension plan, \
+        training budget and flexible hours.";
+
+    #[test]
+    fn test_rendered_text() {
+        let document = kuchikiki::parse_html().one(
+            "<head><title>Job</title><style>p {}</style></head><body><h1>Rust</h1><p>Engi<b>neer</b></p><script>track()</script></body>",
+        );
+        assert_eq!(normalize_text(&rendered_text(&document)), "rust engineer");
+    }
+
+    #[test]
+    fn test_line_breaks_separate_words() {
+        let text = |html| rendered_text(&kuchikiki::parse_html().one(html));
+        assert_eq!(
+            normalize_text(&text("<p>Engineer<br>Berlin<wbr>Remote</p>")),
+            "engineer berlin remote"
+        );
+        assert_ne!(
+            Fingerprint::of_text(&text("<p>Engineer<br>Berlin</p>")),
+            Fingerprint::of_text(&text("<p>EngineerBerlin</p>"))
+        );
+    }
+
+    #[test]
+    fn test_exact_hash() {
+        let a = Fingerprint::of_text("Senior  Engineer!\nApply now.");
+        let b = Fingerprint::of_text("senior engineer – apply NOW");
+        assert_eq!(a, b);
+        assert_eq!(a.sha1, "9125fd57d4632f1378dbfc0009e5660facbe1310");
+        assert_ne!(
+            a.sha1,
+            Fingerprint::of_text("Junior engineer, apply now").sha1
+        );
+    }
+
+    #[test]
+    fn test_similarity() {
+        let original = Fingerprint::of_text(POSTING);
+        let edited = Fingerprint::of_text(&POSTING.replace("flexible hours", "free snacks"));
+        let other = Fingerprint::of_text(
+            "Bakery assistant wanted for early shifts. Baking bread, serving customers and \
+            keeping the shop clean. Experience in retail is a plus, full training is given.",
+        );
+
+        assert!(original.similarity(&edited) >= 0.8);
+        assert!(original.similarity(&other) < 0.8);
+        assert_eq!(original.similarity(&original), 1.0);
+    }
+
+    #[test]
+    fn test_cluster() {
+        let fingerprints: Vec<Fingerprint> = [
+            "Bakery assistant wanted for early shifts, serving customers and baking bread.",
+            POSTING,
+            &POSTING.replace("Remote work", "Hybrid work"),
+            "Bakery assistant wanted for early shifts, serving customers and baking bread!",
+            "Something else entirely, about gardening tools and how to keep them sharp.",
+        ]
+        .iter()
+        .map(|text| Fingerprint::of_text(text))
+        .collect();
+
+        assert_eq!(cluster(&fingerprints, 0.8), vec![0, 1, 1, 0, 2]);
+        assert_eq!(cluster(&fingerprints, 1.0), vec![0, 1, 2, 0, 3]);
+    }
+}
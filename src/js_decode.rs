@@ -134,21 +134,9 @@ pub fn decode_js_string(input: &str) -> Result<String, Box<dyn Error>> {
     Ok(result)
 }
 
-/// Attempt to fix UTF-8 mojibake (text encoded as UTF-8 but interpreted as Latin-1)
+/// Attempt to fix UTF-8 mojibake (text encoded as UTF-8 but interpreted as Latin-1 or Windows-1252)
 pub fn fix_mojibake(input: &str) -> String {
-    // Try to re-encode as latin-1, then decode as UTF-8
-    let bytes: Vec<u8> = input
-        .chars()
-        .filter_map(|c| {
-            let code = c as u32;
-            if code <= 255 { Some(code as u8) } else { None }
-        })
-        .collect();
-
-    match String::from_utf8(bytes) {
-        Ok(fixed) => fixed,
-        Err(_) => input.to_string(),
-    }
+    crate::mojibake::fix_encoding(input)
 }
 
 #[cfg(test)]
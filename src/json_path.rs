@@ -0,0 +1,465 @@
+use serde_json::Value;
+use std::error::Error;
+use std::iter::Peekable;
+use std::str::Chars;
+
+/// A compiled path expression for querying extracted JSON.
+///
+/// Accepts both jq-like (`.jobs[].title`) and JSONPath (`$.jobs[*].title`) syntax:
+/// field access, array indices (negative counts from the end), wildcards,
+/// recursive descent (`..name`) and simple filters (`[?(@.type == "JobPosting")]`).
+#[derive(Debug, Clone, PartialEq)]
+pub struct JsonPath {
+    segments: Vec<Segment>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Segment {
+    Field(String),
+    Index(i64),
+    Wildcard,
+    Descendants,
+    Filter(Filter),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Filter {
+    path: Vec<Segment>,
+    comparison: Option<(Op, Value)>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Op {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+impl JsonPath {
+    pub fn parse(expr: &str) -> Result<Self, Box<dyn Error>> {
+        let mut chars = expr.trim().chars().peekable();
+
+        if chars.peek() == Some(&'$') {
+            chars.next();
+        }
+
+        let segments = parse_segments(&mut chars, false)?;
+
+        if let Some(c) = chars.next() {
+            return Err(format!("Unexpected '{c}' in JSON path: {expr}").into());
+        }
+
+        Ok(Self { segments })
+    }
+
+    /// Evaluate the path against `root`, returning every value it selects.
+    pub fn query<'a>(&self, root: &'a Value) -> Vec<&'a Value> {
+        evaluate(&self.segments, root)
+    }
+}
+
+fn parse_segments(
+    chars: &mut Peekable<Chars>,
+    in_filter: bool,
+) -> Result<Vec<Segment>, Box<dyn Error>> {
+    let mut segments = Vec::new();
+
+    while let Some(&c) = chars.peek() {
+        match c {
+            '.' => {
+                chars.next();
+                if chars.peek() == Some(&'.') {
+                    chars.next();
+                    segments.push(Segment::Descendants);
+                }
+                match chars.peek() {
+                    Some('*') => {
+                        chars.next();
+                        segments.push(Segment::Wildcard);
+                    }
+                    Some('"') | Some('\'') => {
+                        let quote = chars.next().unwrap_or('"');
+                        segments.push(Segment::Field(parse_quoted(chars, quote)?));
+                    }
+                    Some(&c) if is_name_char(c) => {
+                        segments.push(Segment::Field(parse_name(chars)));
+                    }
+                    // a bare `.` (identity) or `.[` is fine, anything else is handled by the caller
+                    _ => {}
+                }
+            }
+            '[' => {
+                chars.next();
+                segments.push(parse_bracket(chars)?);
+            }
+            ' ' | ')' | ']' | '=' | '!' | '<' | '>' if in_filter => break,
+            _ => return Err(format!("Unexpected '{c}' in JSON path").into()),
+        }
+    }
+
+    Ok(segments)
+}
+
+fn parse_bracket(chars: &mut Peekable<Chars>) -> Result<Segment, Box<dyn Error>> {
+    skip_spaces(chars);
+
+    let segment = match chars.peek() {
+        // jq-style `[]` iterates like a wildcard
+        Some(']') => Segment::Wildcard,
+        Some('*') => {
+            chars.next();
+            Segment::Wildcard
+        }
+        Some('"') | Some('\'') => {
+            let quote = chars.next().unwrap_or('"');
+            Segment::Field(parse_quoted(chars, quote)?)
+        }
+        Some('?') => {
+            chars.next();
+            skip_spaces(chars);
+            let parenthesized = chars.peek() == Some(&'(');
+            if parenthesized {
+                chars.next();
+            }
+            let filter = parse_filter(chars)?;
+            if parenthesized {
+                skip_spaces(chars);
+                if chars.next() != Some(')') {
+                    return Err("Unclosed '(' in JSON path filter".into());
+                }
+            }
+            Segment::Filter(filter)
+        }
+        Some(&c) if c == '-' || c.is_ascii_digit() => {
+            let mut number = String::new();
+            number.push(c);
+            chars.next();
+            while let Some(&c) = chars.peek()
+                && c.is_ascii_digit()
+            {
+                number.push(c);
+                chars.next();
+            }
+            Segment::Index(
+                number
+                    .parse()
+                    .map_err(|_| format!("Invalid array index in JSON path: {number}"))?,
+            )
+        }
+        _ => return Err("Invalid bracket expression in JSON path".into()),
+    };
+
+    skip_spaces(chars);
+    if chars.next() != Some(']') {
+        return Err("Unclosed '[' in JSON path".into());
+    }
+
+    Ok(segment)
+}
+
+fn parse_filter(chars: &mut Peekable<Chars>) -> Result<Filter, Box<dyn Error>> {
+    skip_spaces(chars);
+    if chars.next() != Some('@') {
+        return Err("JSON path filters must start with '@'".into());
+    }
+
+    let path = parse_segments(chars, true)?;
+    skip_spaces(chars);
+
+    let op = match chars.peek() {
+        Some('=') | Some('!') | Some('<') | Some('>') => {
+            let mut op = String::new();
+            while let Some(&c) = chars.peek()
+                && matches!(c, '=' | '!' | '<' | '>')
+            {
+                op.push(c);
+                chars.next();
+            }
+            Some(match op.as_str() {
+                "==" => Op::Eq,
+                "!=" => Op::Ne,
+                "<" => Op::Lt,
+                "<=" => Op::Le,
+                ">" => Op::Gt,
+                ">=" => Op::Ge,
+                _ => return Err(format!("Unknown operator in JSON path filter: {op}").into()),
+            })
+        }
+        _ => None,
+    };
+
+    let comparison = match op {
+        Some(op) => {
+            skip_spaces(chars);
+            Some((op, parse_literal(chars)?))
+        }
+        None => None,
+    };
+
+    Ok(Filter { path, comparison })
+}
+
+fn parse_literal(chars: &mut Peekable<Chars>) -> Result<Value, Box<dyn Error>> {
+    match chars.peek() {
+        Some('"') | Some('\'') => {
+            let quote = chars.next().unwrap_or('"');
+            Ok(Value::String(parse_quoted(chars, quote)?))
+        }
+        _ => {
+            let mut literal = String::new();
+            while let Some(&c) = chars.peek()
+                && !matches!(c, ' ' | ')' | ']')
+            {
+                literal.push(c);
+                chars.next();
+            }
+            serde_json::from_str(&literal)
+                .map_err(|_| format!("Invalid literal in JSON path filter: {literal}").into())
+        }
+    }
+}
+
+fn parse_quoted(chars: &mut Peekable<Chars>, quote: char) -> Result<String, Box<dyn Error>> {
+    let mut result = String::new();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => match chars.next() {
+                Some(escaped) => result.push(escaped),
+                None => break,
+            },
+            c if c == quote => return Ok(result),
+            c => result.push(c),
+        }
+    }
+
+    Err("Unterminated string in JSON path".into())
+}
+
+fn parse_name(chars: &mut Peekable<Chars>) -> String {
+    let mut name = String::new();
+    while let Some(&c) = chars.peek()
+        && is_name_char(c)
+    {
+        name.push(c);
+        chars.next();
+    }
+    name
+}
+
+fn is_name_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_' || c == '-' || c == '@' || c == '$'
+}
+
+fn skip_spaces(chars: &mut Peekable<Chars>) {
+    while chars.next_if(|c| c.is_whitespace()).is_some() {}
+}
+
+fn evaluate<'a>(segments: &[Segment], root: &'a Value) -> Vec<&'a Value> {
+    let mut current = vec![root];
+
+    for segment in segments {
+        let mut next = Vec::new();
+        for value in current {
+            apply(segment, value, &mut next);
+        }
+        current = next;
+    }
+
+    current
+}
+
+fn apply<'a>(segment: &Segment, value: &'a Value, out: &mut Vec<&'a Value>) {
+    match segment {
+        Segment::Field(name) => {
+            if let Some(child) = value.get(name) {
+                out.push(child);
+            }
+        }
+        Segment::Index(index) => {
+            if let Value::Array(items) = value {
+                let index = if *index < 0 {
+                    items.len().checked_sub(index.unsigned_abs() as usize)
+                } else {
+                    Some(*index as usize)
+                };
+                if let Some(child) = index.and_then(|i| items.get(i)) {
+                    out.push(child);
+                }
+            }
+        }
+        Segment::Wildcard => match value {
+            Value::Array(items) => out.extend(items),
+            Value::Object(map) => out.extend(map.values()),
+            _ => {}
+        },
+        Segment::Descendants => collect_descendants(value, out),
+        Segment::Filter(filter) => {
+            let children: Vec<&Value> = match value {
+                Value::Array(items) => items.iter().collect(),
+                Value::Object(map) => map.values().collect(),
+                _ => Vec::new(),
+            };
+            out.extend(children.into_iter().filter(|child| filter.matches(child)));
+        }
+    }
+}
+
+fn collect_descendants<'a>(value: &'a Value, out: &mut Vec<&'a Value>) {
+    out.push(value);
+    match value {
+        Value::Array(items) => items.iter().for_each(|v| collect_descendants(v, out)),
+        Value::Object(map) => map.values().for_each(|v| collect_descendants(v, out)),
+        _ => {}
+    }
+}
+
+impl Filter {
+    fn matches(&self, value: &Value) -> bool {
+        let found = evaluate(&self.path, value);
+
+        let Some((op, expected)) = &self.comparison else {
+            return !found.is_empty();
+        };
+
+        found.into_iter().any(|actual| match op {
+            Op::Eq => actual == expected,
+            Op::Ne => actual != expected,
+            _ => match (actual, expected) {
+                (Value::Number(a), Value::Number(b)) => compare(
+                    op,
+                    a.as_f64().unwrap_or(f64::NAN),
+                    b.as_f64().unwrap_or(f64::NAN),
+                ),
+                (Value::String(a), Value::String(b)) => compare(op, a, b),
+                _ => false,
+            },
+        })
+    }
+}
+
+fn compare<T: PartialOrd>(op: &Op, a: T, b: T) -> bool {
+    match op {
+        Op::Lt => a < b,
+        Op::Le => a <= b,
+        Op::Gt => a > b,
+        Op::Ge => a >= b,
+        Op::Eq => a == b,
+        Op::Ne => a != b,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn query(path: &str, value: &Value) -> Vec<Value> {
+        JsonPath::parse(path)
+            .unwrap()
+            .query(value)
+            .into_iter()
+            .cloned()
+            .collect()
+    }
+
+    fn job() -> Value {
+        json!({
+            "@type": "JobPosting",
+            "title": "Rust Engineer",
+            "hiringOrganization": {"@type": "Organization", "name": "Acme Corp"},
+            "jobLocation": [
+                {"address": {"addressLocality": "Berlin"}, "remote": false},
+                {"address": {"addressLocality": "Paris"}, "remote": true}
+            ],
+            "baseSalary": {"value": {"minValue": 50000, "maxValue": 80000}}
+        })
+    }
+
+    #[test]
+    fn test_field_access() {
+        assert_eq!(
+            query(".hiringOrganization.name", &job()),
+            vec![json!("Acme Corp")]
+        );
+        assert_eq!(
+            query("$.hiringOrganization.name", &job()),
+            vec![json!("Acme Corp")]
+        );
+        assert_eq!(query(".\"@type\"", &job()), vec![json!("JobPosting")]);
+        assert_eq!(query("$['title']", &job()), vec![json!("Rust Engineer")]);
+    }
+
+    #[test]
+    fn test_identity_and_missing() {
+        assert_eq!(query(".", &job()), vec![job()]);
+        assert!(query(".nope.deeper", &job()).is_empty());
+    }
+
+    #[test]
+    fn test_array_indices() {
+        assert_eq!(
+            query(".jobLocation[1].address.addressLocality", &job()),
+            vec![json!("Paris")]
+        );
+        assert_eq!(
+            query(".jobLocation[-2].address.addressLocality", &job()),
+            vec![json!("Berlin")]
+        );
+        assert!(query(".jobLocation[5]", &job()).is_empty());
+    }
+
+    #[test]
+    fn test_wildcards() {
+        let expected = vec![json!("Berlin"), json!("Paris")];
+        assert_eq!(
+            query(".jobLocation[].address.addressLocality", &job()),
+            expected
+        );
+        assert_eq!(
+            query("$.jobLocation[*].address.addressLocality", &job()),
+            expected
+        );
+        assert_eq!(query(".baseSalary.value.*", &job()).len(), 2);
+    }
+
+    #[test]
+    fn test_recursive_descent() {
+        assert_eq!(
+            query("..addressLocality", &job()),
+            vec![json!("Berlin"), json!("Paris")]
+        );
+        assert_eq!(query("$..minValue", &job()), vec![json!(50000)]);
+    }
+
+    #[test]
+    fn test_filters() {
+        assert_eq!(
+            query(
+                ".jobLocation[?(@.remote == true)].address.addressLocality",
+                &job()
+            ),
+            vec![json!("Paris")]
+        );
+        assert_eq!(
+            query("$..[?(@.name)].name", &job()),
+            vec![json!("Acme Corp")]
+        );
+        assert_eq!(
+            query("$[?(@['@type'] == 'Organization')].name", &job()),
+            vec![json!("Acme Corp")]
+        );
+        assert_eq!(query("..value[?(@ >= 60000)]", &job()), vec![json!(80000)]);
+    }
+
+    #[test]
+    fn test_invalid_paths() {
+        assert!(JsonPath::parse(".foo[").is_err());
+        assert!(JsonPath::parse(".foo[?(@.a ~ 1)]").is_err());
+        assert!(JsonPath::parse(".foo[\"bar]").is_err());
+        assert!(JsonPath::parse("foo").is_err());
+    }
+}
@@ -1,23 +1,40 @@
 use aws_config::BehaviorVersion;
 use aws_sdk_s3::Client as S3Client;
-use flate2::read::GzDecoder;
-use hq::{HqConfig, process_html};
+use encoding_rs::Encoding;
+use hq::charset::{self, Decoded};
+use hq::decompress::{self, Compression};
+use hq::minify::MinifyOptions;
+use hq::pretty_print::PrettyOptions;
+use hq::warc::{self, WarcReader};
+use hq::{HqConfig, HqError, Query};
 use lambda_http::{Body, Error, Request, RequestExt, Response, run, service_fn, tracing};
 use serde_json::json;
-use std::io::Read;
+use url::Url;
+
+/// Bucket holding the files referenced by Common Crawl's CDX `filename` column.
+const COMMON_CRAWL_BUCKET: &str = "commoncrawl";
+
+/// A fetched document, decoded to UTF-8.
+struct Fetched {
+    decoded: Decoded,
+    /// The `WARC-Target-URI` if the fetched data was a WARC record.
+    target_uri: Option<String>,
+}
 
 async fn fetch_html(
     url: &str,
     s3_client: &S3Client,
     offset: Option<u64>,
     length: Option<u64>,
-) -> Result<String, Box<dyn std::error::Error>> {
-    let data = if url.starts_with("s3://") {
+    encoding: Option<&str>,
+) -> Result<Fetched, HqError> {
+    let (data, content_type, content_encoding) = if let Some(s3_path) = url.strip_prefix("s3://") {
         // Parse S3 URL: s3://bucket/key
-        let s3_path = url.strip_prefix("s3://").ok_or("Invalid S3 URL")?;
         let parts: Vec<&str> = s3_path.splitn(2, '/').collect();
         if parts.len() != 2 {
-            return Err("Invalid S3 URL format. Expected: s3://bucket/key".into());
+            return Err(HqError::InvalidArgument(
+                "Invalid S3 URL format. Expected: s3://bucket/key".to_string(),
+            ));
         }
         let bucket = parts[0];
         let key = parts[1];
@@ -29,8 +46,17 @@ async fn fetch_html(
             req = req.range(range);
         }
 
-        let resp = req.send().await?;
-        resp.body.collect().await?.to_vec()
+        let resp = req.send().await.map_err(|e| HqError::Fetch {
+            status: e.raw_response().map(|r| r.status().as_u16()),
+            message: format!("Failed to fetch {url}: {e}"),
+        })?;
+        let content_type = resp.content_type().map(str::to_string);
+        let content_encoding = resp.content_encoding().map(str::to_string);
+        let body = resp.body.collect().await.map_err(|e| HqError::Fetch {
+            status: None,
+            message: format!("Failed to read {url}: {e}"),
+        })?;
+        (body.to_vec(), content_type, content_encoding)
     } else if url.starts_with("http://") || url.starts_with("https://") {
         let client = reqwest::Client::new();
         let mut req = client.get(url);
@@ -40,32 +66,97 @@ async fn fetch_html(
             req = req.header("Range", range);
         }
 
-        let resp = req.send().await?;
-        resp.bytes().await?.to_vec()
+        let resp = req.send().await.map_err(|e| HqError::Fetch {
+            status: e.status().map(|s| s.as_u16()),
+            message: format!("Failed to fetch {url}: {e}"),
+        })?;
+        if !resp.status().is_success() {
+            return Err(HqError::Fetch {
+                status: Some(resp.status().as_u16()),
+                message: format!("Failed to fetch {url}"),
+            });
+        }
+        let headers = resp.headers();
+        let content_type = headers
+            .get(reqwest::header::CONTENT_TYPE)
+            .and_then(|v| v.to_str().ok())
+            .map(str::to_string);
+        let content_encoding = headers
+            .get(reqwest::header::CONTENT_ENCODING)
+            .and_then(|v| v.to_str().ok())
+            .map(str::to_string);
+        let body = resp.bytes().await.map_err(|e| HqError::Fetch {
+            status: None,
+            message: format!("Failed to read {url}: {e}"),
+        })?;
+        (body.to_vec(), content_type, content_encoding)
     } else {
-        return Err("URL must start with http://, https://, or s3://".into());
+        return Err(HqError::InvalidArgument(
+            "URL must start with http://, https://, or s3://".to_string(),
+        ));
     };
 
-    // Check if content is gzipped and decompress
-    let html = if data.len() >= 2 && data[0] == 0x1f && data[1] == 0x8b {
-        // Gzip magic bytes detected
-        let mut decoder = GzDecoder::new(&data[..]);
-        let mut decompressed = String::new();
-        decoder.read_to_string(&mut decompressed)?;
-        decompressed
-    } else {
-        String::from_utf8(data)?
+    // Undo any HTTP Content-Encoding, then decompress compressed files themselves
+    let data = match content_encoding {
+        Some(content_encoding) => decompress::decode_content_encoding(data, &content_encoding)?,
+        None => data,
     };
+    let data = decompress::decompress(data, Compression::from_path(file_path(url)))?;
+
+    // A ranged fetch from a WARC file: strip the WARC and HTTP headers, and decode the payload
+    // according to the HTTP headers rather than those of the fetch itself
+    if warc::is_warc(&data) {
+        let record = WarcReader::new(&data[..])
+            .next()
+            .ok_or_else(|| HqError::Input("Empty WARC record".to_string()))??;
+
+        if !record.is_response() {
+            return Err(HqError::Input(format!(
+                "WARC record is a '{}' record, not a response",
+                record.record_type().unwrap_or("unknown")
+            )));
+        }
+
+        return Ok(Fetched {
+            decoded: record.http_response()?.decode_body(encoding)?,
+            target_uri: record.target_uri().map(str::to_string),
+        });
+    }
 
-    Ok(html)
+    Ok(Fetched {
+        decoded: charset::decode_html(&data, encoding, content_type.as_deref())?,
+        target_uri: None,
+    })
+}
+
+/// The part of a URL that names the file, without the query string and fragment of HTTP URLs,
+/// e.g. for `https://host/sitemap.xml.br?sig=...`. S3 keys are taken as they are.
+fn file_path(url: &str) -> &str {
+    if url.starts_with("s3://") {
+        return url;
+    }
+    url.split(['?', '#']).next().unwrap_or(url)
 }
 
 async fn function_handler(event: Request, s3_client: &S3Client) -> Result<Response<Body>, Error> {
     let query_params = event.query_string_parameters();
 
-    let url = query_params
-        .first("url")
-        .ok_or("Missing 'url' query parameter")?;
+    // `filename`, `offset` and `length` can be passed straight from a Common Crawl CDX index row
+    let url = match (query_params.first("url"), query_params.first("filename")) {
+        (Some(url), _) => url.to_string(),
+        (None, Some(filename)) => format!(
+            "s3://{COMMON_CRAWL_BUCKET}/{}",
+            filename.trim_start_matches('/')
+        ),
+        (None, None) => {
+            return error_response(
+                "Invalid request",
+                &HqError::InvalidArgument(
+                    "Missing 'url' or 'filename' query parameter".to_string(),
+                ),
+            );
+        }
+    };
 
     let selector = query_params.first("selector").unwrap_or(":root");
 
@@ -79,6 +170,20 @@ async fn function_handler(event: Request, s3_client: &S3Client) -> Result<Respon
         .map(|v| v == "true" || v == "1")
         .unwrap_or(false);
 
+    let pretty_options = PrettyOptions {
+        indent: query_params
+            .first("indent")
+            .and_then(|s| s.parse::<usize>().ok())
+            .unwrap_or(2),
+        tabs: query_params
+            .first("tabs")
+            .map(|v| v == "true" || v == "1")
+            .unwrap_or(false),
+        wrap_attributes: query_params
+            .first("wrap_attributes")
+            .and_then(|s| s.parse::<usize>().ok()),
+    };
+
     let attributes: Vec<String> = query_params
         .all("attribute")
         .unwrap_or_default()
@@ -99,53 +204,132 @@ async fn function_handler(event: Request, s3_client: &S3Client) -> Result<Respon
         .map(|v| v == "true" || v == "1")
         .unwrap_or(false);
 
+    let flag = |name: &str| {
+        query_params
+            .first(name)
+            .is_some_and(|v| v == "true" || v == "1")
+    };
+    let minify_options = MinifyOptions {
+        remove_comments: flag("remove_comments"),
+        omit_end_tags: flag("omit_end_tags"),
+        unquote_attributes: flag("unquote_attributes"),
+        collapse_boolean_attributes: flag("collapse_boolean_attributes"),
+    };
+
+    let fingerprint = flag("fingerprint");
+
+    let absolutize = flag("absolutize");
+
+    let outline = flag("outline");
+    let outline_depth = query_params
+        .first("depth")
+        .and_then(|s| s.parse::<usize>().ok());
+
+    let json_path = query_params.first("json_path").map(|s| s.to_string());
+
+    let xml = query_params
+        .first("xml")
+        .map(|v| v == "true" || v == "1")
+        .unwrap_or(false);
+
+    let encoding = query_params.first("encoding");
+
+    let base = query_params.first("base").map(|s| s.to_string());
+
+    // links in WARC records are relative to the URL they were crawled from, which is only
+    // known once fetched, others to the URL they were fetched from
     let config = HqConfig {
         selector: selector.to_string(),
+        base,
+        url: (url.starts_with("http://") || url.starts_with("https://")).then(|| url.clone()),
+        absolutize,
         text_only,
         pretty_print: pretty,
+        pretty_options,
         attributes,
         compact,
+        minify_options,
+        json_path,
+        fingerprint,
+        outline,
+        outline_depth,
+        xml,
         ..Default::default()
     };
 
-    match fetch_html(url, s3_client, offset, length).await {
-        Ok(html) => match process_html(&html, &config) {
-            Ok(result) => {
-                let resp = Response::builder()
-                    .status(200)
-                    .header("content-type", "text/plain")
-                    .body(result.into())
-                    .map_err(Box::new)?;
-                Ok(resp)
-            }
-            Err(e) => {
-                let error_body = json!({
-                    "error": "HTML processing failed",
-                    "message": e.to_string()
-                });
-                let resp = Response::builder()
-                    .status(400)
-                    .header("content-type", "application/json")
-                    .body(error_body.to_string().into())
-                    .map_err(Box::new)?;
-                Ok(resp)
+    // reject invalid parameters before fetching anything
+    let query = match Query::compile(&config) {
+        Ok(query) => query,
+        Err(e) => return error_response("Invalid query", &e),
+    };
+    if let Some(label) = encoding
+        && Encoding::for_label(label.trim().as_bytes()).is_none()
+    {
+        let e = HqError::Encoding(format!("Unknown encoding: {label}"));
+        return error_response("Invalid query", &e);
+    }
+
+    match fetch_html(&url, s3_client, offset, length, encoding).await {
+        Ok(Fetched {
+            decoded,
+            target_uri,
+        }) => {
+            let result = match target_uri.as_deref().map(Url::parse) {
+                Some(Ok(target_uri)) => query.run_at(&decoded.html, &target_uri),
+                Some(Err(e)) => Err(HqError::Input(format!("Invalid WARC-Target-URI: {e}"))),
+                None => query.run(&decoded.html),
+            };
+
+            match result {
+                Ok(result) => {
+                    let resp = Response::builder()
+                        .status(200)
+                        .header("content-type", "text/plain; charset=utf-8")
+                        .header("x-hq-encoding", decoded.encoding.name())
+                        .header("x-hq-encoding-source", decoded.source.to_string())
+                        .body(result.into())
+                        .map_err(Box::new)?;
+                    Ok(resp)
+                }
+                Err(e) => error_response("HTML processing failed", &e),
             }
-        },
-        Err(e) => {
-            let error_body = json!({
-                "error": "Failed to fetch URL",
-                "message": e.to_string()
-            });
-            let resp = Response::builder()
-                .status(400)
-                .header("content-type", "application/json")
-                .body(error_body.to_string().into())
-                .map_err(Box::new)?;
-            Ok(resp)
         }
+        Err(e) => error_response("Failed to fetch URL", &e),
+    }
+}
+
+/// The HTTP status to answer with for an error: the client's fault (400), a document that
+/// isn't what it should be (422), or an upstream failure, passing on 404s (502).
+fn status_code(error: &HqError) -> u16 {
+    match error {
+        HqError::InvalidSelector { .. } | HqError::InvalidArgument(_) | HqError::Encoding(_) => 400,
+        HqError::Input(_) | HqError::Json(_) => 422,
+        HqError::Fetch {
+            status: Some(404 | 410),
+            ..
+        } => 404,
+        HqError::Fetch { .. } => 502,
+        HqError::Io(_) => 500,
     }
 }
 
+fn error_response(title: &str, error: &HqError) -> Result<Response<Body>, Error> {
+    let mut error_body = json!({
+        "error": title,
+        "message": error.to_string()
+    });
+    if let HqError::InvalidSelector { position, .. } = error {
+        error_body["position"] = json!(position);
+    }
+
+    let resp = Response::builder()
+        .status(status_code(error))
+        .header("content-type", "application/json")
+        .body(error_body.to_string().into())
+        .map_err(Box::new)?;
+    Ok(resp)
+}
+
 #[tokio::main]
 async fn main() -> Result<(), Error> {
     tracing_subscriber::fmt()
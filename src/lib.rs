@@ -1,10 +1,31 @@
+pub mod cdx;
+pub mod charset;
+pub mod decompress;
+pub mod diff;
+pub mod error;
+pub mod explore;
+pub mod fingerprint;
 pub mod js_decode;
+pub mod json_path;
 pub mod link;
+pub mod minify;
+pub mod mojibake;
+pub mod normalize;
+pub mod outline;
 pub mod pretty_print;
+pub mod stream;
+pub mod suggest;
+pub mod warc;
+pub mod xml;
 
-use kuchikiki::NodeRef;
+pub use error::HqError;
+use json_path::JsonPath;
 use kuchikiki::traits::{NodeIterator, TendrilSink};
-use std::error::Error;
+use kuchikiki::{NodeRef, Selectors};
+use link::{LinkFilter, UrlRewrite};
+use normalize::Normalizer;
+use regex::Regex;
+use std::collections::HashSet;
 use std::io::{self, Write};
 use url::Url;
 
@@ -13,12 +34,55 @@ pub struct HqConfig {
     pub selector: String,
     pub base: Option<String>,
     pub detect_base: bool,
+    /// The URL the document was fetched from, to resolve relative links and `<base>` against.
+    /// On its own it doesn't rewrite anything, see `absolutize`.
+    pub url: Option<String>,
+    /// Resolve relative URLs in matches against `url` and the document's `<base>`. A `base`,
+    /// `detect_base`, `links` or `normalize_urls` do so too.
+    pub absolutize: bool,
     pub text_only: bool,
     pub ignore_whitespace: bool,
     pub pretty_print: bool,
+    /// How to lay out the output of `pretty_print`.
+    pub pretty_options: pretty_print::PrettyOptions,
+    /// Highlight HTML output with ANSI colours. Ignored when the output is processed further
+    /// with `compact` or `json_path`.
+    pub color: bool,
     pub remove_nodes: Vec<String>,
     pub attributes: Vec<String>,
     pub compact: bool,
+    /// Which optional steps to take when minifying HTML output with `compact`.
+    pub minify_options: minify::MinifyOptions,
+    pub json_path: Option<String>,
+    pub fix_encoding: bool,
+    pub first: bool,
+    /// Output the hyperlinks in the matched elements as JSON lines instead of the elements.
+    pub links: bool,
+    /// Output each link only once, by its URL without the fragment (normalized with
+    /// `normalize_urls`).
+    pub dedupe_links: bool,
+    /// Output only links to the document's own host.
+    pub same_host: bool,
+    /// Output only links whose path starts with this.
+    pub path_prefix: Option<String>,
+    /// Output only links whose URL matches this regular expression.
+    pub link_pattern: Option<String>,
+    /// Normalize rewritten and extracted URLs, see [`normalize::Normalizer`].
+    pub normalize_urls: bool,
+    /// Query parameters to strip when normalizing, on top of the known tracking parameters.
+    pub strip_params: Vec<String>,
+    /// Add the SURT form of each link's URL to the output of `links`.
+    pub surt: bool,
+    /// Output a fingerprint of the text of each match as JSON instead of the match, see
+    /// [`fingerprint::Fingerprint`].
+    pub fingerprint: bool,
+    /// Output an outline of the elements in each match instead of the match, see
+    /// [`outline::write_outline`].
+    pub outline: bool,
+    /// How many levels below each match to outline, all of them if `None`.
+    pub outline_depth: Option<usize>,
+    /// Parse documents as XML rather than HTML, and serialize matches as XML, see [`xml::parse`].
+    pub xml: bool,
 }
 
 impl Default for HqConfig {
@@ -27,36 +91,71 @@ impl Default for HqConfig {
             selector: ":root".to_string(),
             base: None,
             detect_base: false,
+            url: None,
+            absolutize: false,
             text_only: false,
             ignore_whitespace: false,
             pretty_print: false,
+            pretty_options: pretty_print::PrettyOptions::default(),
+            color: false,
             remove_nodes: Vec::new(),
             attributes: Vec::new(),
             compact: false,
+            minify_options: minify::MinifyOptions::default(),
+            json_path: None,
+            fix_encoding: false,
+            first: false,
+            links: false,
+            dedupe_links: false,
+            same_host: false,
+            path_prefix: None,
+            link_pattern: None,
+            normalize_urls: false,
+            strip_params: Vec::new(),
+            surt: false,
+            fingerprint: false,
+            outline: false,
+            outline_depth: None,
+            xml: false,
         }
     }
 }
 
-fn select_attributes(node: &NodeRef, attributes: &[String], output: &mut dyn io::Write) {
+fn select_attributes(
+    node: &NodeRef,
+    attributes: &[String],
+    fix_encoding: bool,
+    output: &mut dyn io::Write,
+) -> io::Result<()> {
     if let Some(as_element) = node.as_element() {
         for attr in attributes {
             if let Ok(elem_atts) = as_element.attributes.try_borrow()
                 && let Some(val) = elem_atts.get(attr.as_str())
             {
-                writeln!(output, "{val}").ok();
+                if fix_encoding {
+                    writeln!(output, "{}", mojibake::fix_encoding(val))?;
+                } else {
+                    writeln!(output, "{val}")?;
+                }
             }
         }
     }
+
+    Ok(())
 }
 
-fn serialize_text(node: &NodeRef, ignore_whitespace: bool) -> String {
+fn serialize_text(node: &NodeRef, ignore_whitespace: bool, fix_encoding: bool) -> String {
     let mut result = String::new();
     for text_node in node.inclusive_descendants().text_nodes() {
         if ignore_whitespace && text_node.borrow().trim().is_empty() {
             continue;
         }
 
-        result.push_str(&text_node.borrow());
+        if fix_encoding {
+            result.push_str(&mojibake::fix_encoding(&text_node.borrow()));
+        } else {
+            result.push_str(&text_node.borrow());
+        }
 
         if ignore_whitespace {
             result.push('\n');
@@ -66,121 +165,774 @@ fn serialize_text(node: &NodeRef, ignore_whitespace: bool) -> String {
     result
 }
 
-pub fn process_html(html: &str, config: &HqConfig) -> Result<String, Box<dyn Error>> {
-    let document = kuchikiki::parse_html().one(html);
+pub fn process_html(html: &str, config: &HqConfig) -> Result<String, HqError> {
+    Query::compile(config)?.run(html)
+}
 
-    let base: Option<Url> = match (&config.base, &config.detect_base) {
-        (Some(base), true) => link::detect_base(&document).or(Url::parse(base).ok()),
-        (Some(base), false) => Url::parse(base).ok(),
-        (None, true) => link::detect_base(&document),
-        _ => None,
-    };
+/// A [`HqConfig`] with its selectors, base URL and JSON path parsed once, to run on many
+/// documents. Invalid parts of the config are reported by [`Query::compile`] instead of
+/// when processing each document.
+#[derive(Debug)]
+pub struct Query {
+    config: HqConfig,
+    selectors: Selectors,
+    remove_nodes: Option<Selectors>,
+    base: Option<Url>,
+    url: Option<Url>,
+    json_path: Option<JsonPath>,
+    link_filter: LinkFilter,
+    normalizer: Option<Normalizer>,
+}
+
+impl Query {
+    pub fn compile(config: &HqConfig) -> Result<Self, HqError> {
+        let selectors = compile_selector(&config.selector, config.xml)?;
+
+        let remove_nodes = match config.remove_nodes.as_slice() {
+            [] => None,
+            remove_nodes => {
+                // report which selector is broken rather than pointing into the joined list
+                for selector in remove_nodes {
+                    compile_selector(selector, config.xml)?;
+                }
+                Some(compile_selector(&remove_nodes.join(","), config.xml)?)
+            }
+        };
+
+        let base = config
+            .base
+            .as_deref()
+            .map(|base| {
+                Url::parse(base)
+                    .map_err(|e| HqError::InvalidArgument(format!("Invalid base URL {base}: {e}")))
+            })
+            .transpose()?;
+
+        let url = config
+            .url
+            .as_deref()
+            .map(|url| {
+                Url::parse(url).map_err(|e| {
+                    HqError::InvalidArgument(format!("Invalid document URL {url}: {e}"))
+                })
+            })
+            .transpose()?;
+
+        let link_filter = LinkFilter {
+            same_host: config.same_host,
+            path_prefix: config.path_prefix.clone(),
+            pattern: config
+                .link_pattern
+                .as_deref()
+                .map(|pattern| {
+                    Regex::new(pattern).map_err(|e| {
+                        HqError::InvalidArgument(format!("Invalid link pattern {pattern}: {e}"))
+                    })
+                })
+                .transpose()?,
+        };
+
+        let json_path = config
+            .json_path
+            .as_deref()
+            .map(|path| JsonPath::parse(path).map_err(|e| HqError::InvalidArgument(e.to_string())))
+            .transpose()?;
+
+        Ok(Self {
+            config: config.clone(),
+            selectors,
+            remove_nodes,
+            base,
+            url,
+            json_path,
+            link_filter,
+            normalizer: config
+                .normalize_urls
+                .then(|| Normalizer::new(&config.strip_params)),
+        })
+    }
+
+    pub fn config(&self) -> &HqConfig {
+        &self.config
+    }
+
+    /// Parse a document as HTML, or as XML if [`HqConfig::xml`] is set.
+    pub fn parse(&self, text: &str) -> Result<NodeRef, HqError> {
+        if self.config.xml {
+            xml::parse(text)
+        } else {
+            Ok(kuchikiki::parse_html().one(text))
+        }
+    }
+
+    /// Parse `html` as a document and run the query on it.
+    pub fn run(&self, html: &str) -> Result<String, HqError> {
+        self.run_on(&self.parse(html)?)
+    }
+
+    /// Like [`Query::run`], for a document fetched from `url` rather than [`HqConfig::url`].
+    pub fn run_at(&self, html: &str, url: &Url) -> Result<String, HqError> {
+        self.run_on_at(&self.parse(html)?, Some(url))
+    }
+
+    /// Run the query on an already parsed document or subtree.
+    pub fn run_on(&self, root: &NodeRef) -> Result<String, HqError> {
+        self.run_on_at(root, None)
+    }
+
+    fn run_on_at(&self, root: &NodeRef, url: Option<&Url>) -> Result<String, HqError> {
+        let mut output = Vec::new();
+        self.write_matches(root, url, &mut output)?;
+
+        let mut result = String::from_utf8(output).map_err(|e| HqError::Encoding(e.to_string()))?;
+
+        // Query the extracted JSON if requested, one line per selected value
+        if let Some(path) = &self.json_path {
+            let values = decode_json_values(&result).map_err(|e| {
+                HqError::Json(format!(
+                    "Output is not valid JSON, cannot apply JSON path: {e}"
+                ))
+            })?;
+
+            result = String::new();
+            for value in &values {
+                for selected in path.query(value) {
+                    match selected {
+                        serde_json::Value::String(s) => result.push_str(s),
+                        other => result.push_str(&serde_json::to_string(other)?),
+                    }
+                    result.push('\n');
+                }
+            }
+
+            return Ok(result);
+        }
+
+        // Compact output if requested
+        if self.config.compact {
+            if let Ok(mut values) = decode_json_values(&result)
+                && values.len() == 1
+            {
+                // If it's valid JSON, serialize it compactly
+                // This preserves spaces within text values while removing structural whitespace
+                result = serde_json::to_string(&values.remove(0))?;
+            } else if !self.writes_minified_html() {
+                // HTML was minified as it was written, other output only loses leading whitespace
+                result = result.trim_start().to_string();
+            }
+        }
+
+        Ok(result)
+    }
+
+    /// Like [`Query::run`], but write the output to `output` as each match is found.
+    pub fn run_into(&self, html: &str, output: &mut dyn Write) -> Result<(), HqError> {
+        self.run_on_into(&self.parse(html)?, output)
+    }
+
+    /// Like [`Query::run_at`], but write the output to `output` as each match is found.
+    pub fn run_at_into(
+        &self,
+        html: &str,
+        url: &Url,
+        output: &mut dyn Write,
+    ) -> Result<(), HqError> {
+        self.run_on_at_into(&self.parse(html)?, Some(url), output)
+    }
+
+    /// Like [`Query::run_on`], but write the output to `output` as each match is found.
+    pub fn run_on_into(&self, root: &NodeRef, output: &mut dyn Write) -> Result<(), HqError> {
+        self.run_on_at_into(root, None, output)
+    }
+
+    fn run_on_at_into(
+        &self,
+        root: &NodeRef,
+        url: Option<&Url>,
+        output: &mut dyn Write,
+    ) -> Result<(), HqError> {
+        // these work on the output as a whole
+        if self.json_path.is_some() || self.config.compact {
+            output.write_all(self.run_on_at(root, url)?.as_bytes())?;
+            return Ok(());
+        }
+
+        self.write_matches(root, url, output)
+    }
+
+    /// Iterate over the elements below `root` matching the selector, in document order.
+    ///
+    /// Each match has `remove_nodes` detached and its links absolutized before it's returned,
+    /// but nothing is serialized until asked for.
+    pub fn matches<'a>(&'a self, root: &NodeRef) -> impl Iterator<Item = Match> + 'a {
+        self.matches_at(root, None)
+    }
+
+    fn matches_at<'a>(
+        &'a self,
+        root: &NodeRef,
+        url: Option<&Url>,
+    ) -> impl Iterator<Item = Match> + 'a {
+        let base = self
+            .rewrites_urls()
+            .then(|| self.base_for(root, url))
+            .flatten();
+        let limit = if self.config.first { 1 } else { usize::MAX };
+
+        self.selectors
+            .filter(root.inclusive_descendants().elements())
+            .take(limit)
+            .map(move |element| {
+                let node = element.as_node().clone();
+                self.prepare(&node, base.as_ref());
+                Match { node }
+            })
+    }
+
+    /// The base URL for links in `document`, fetched from `url` or else [`HqConfig::url`].
+    ///
+    /// Like in a browser, that's the document's `<base>` resolved against its URL, or else its
+    /// URL. A configured base is used instead, unless `detect_base` prefers the `<base>`.
+    fn base_for(&self, document: &NodeRef, url: Option<&Url>) -> Option<Url> {
+        let url = url.or(self.url.as_ref());
+        if !self.uses_base_element(url) {
+            return self.base.clone();
+        }
+
+        link::detect_base(document, url.or(self.base.as_ref()))
+            .or_else(|| self.base.clone())
+            .or_else(|| url.cloned())
+    }
 
-    let mut output = Vec::new();
+    /// Whether relative URLs in matches are resolved, which the document's URL alone doesn't
+    /// ask for.
+    pub(crate) fn rewrites_urls(&self) -> bool {
+        let config = &self.config;
+        self.base.is_some()
+            || config.detect_base
+            || config.absolutize
+            || config.links
+            || config.normalize_urls
+    }
 
-    for node in document
-        .select(&config.selector)
-        .map_err(|_| "Failed to parse CSS selector")?
-    {
-        let node = node.as_node();
+    /// Whether a `<base>` in a document from `url` is looked for, see [`Query::base_for`].
+    fn uses_base_element(&self, url: Option<&Url>) -> bool {
+        self.config.detect_base || (self.base.is_none() && url.is_some())
+    }
 
+    /// Apply `remove_nodes` and link rewriting to a matched node.
+    fn prepare(&self, node: &NodeRef, base: Option<&Url>) {
         // detach those nodes that should be removed
-        if let Ok(targets) = node.select(&config.remove_nodes.join(",")) {
+        if let Some(remove_nodes) = &self.remove_nodes {
+            let targets: Vec<_> = remove_nodes
+                .filter(node.inclusive_descendants().elements())
+                .collect();
             for target in targets {
                 target.as_node().detach();
             }
         }
 
-        if let Some(base) = &base {
-            link::rewrite_relative_url(node, base);
+        if base.is_some() || self.normalizer.is_some() {
+            link::rewrite_urls(node, self.url_rewrite(base));
+        }
+    }
+
+    fn url_rewrite<'a>(&'a self, base: Option<&'a Url>) -> UrlRewrite<'a> {
+        UrlRewrite {
+            base,
+            normalizer: self.normalizer.as_ref(),
+        }
+    }
+
+    /// Write the output for all matches below `root`, in a document fetched from `url`.
+    fn write_matches(
+        &self,
+        root: &NodeRef,
+        url: Option<&Url>,
+        output: &mut dyn Write,
+    ) -> Result<(), HqError> {
+        if !self.config.links {
+            for found in self.matches_at(root, url) {
+                self.write_node(found.node(), output)?;
+            }
+            return Ok(());
+        }
+
+        // links are classified relative to where the document is from, or pretends to be from
+        let base = self.base_for(root, url);
+        let document_url = url.or(self.url.as_ref()).or(base.as_ref());
+
+        let mut seen = HashSet::new();
+        let default_normalizer = Normalizer::default();
+        let normalizer = self.normalizer.as_ref().unwrap_or(&default_normalizer);
+        for found in self.matches_at(root, url) {
+            for mut link in
+                link::extract_links(found.node(), self.url_rewrite(base.as_ref()), document_url)
+            {
+                if !self.link_filter.accepts(&link)
+                    || (self.config.dedupe_links && !seen.insert(link.dedupe_key(normalizer)))
+                {
+                    continue;
+                }
+                if self.config.surt {
+                    link.surt = normalize::surt(&link.url);
+                }
+                writeln!(output, "{}", link.to_json())?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Whether matches are written as minified HTML, one right after the other.
+    fn writes_minified_html(&self) -> bool {
+        let config = &self.config;
+        config.compact
+            && config.attributes.is_empty()
+            && !config.text_only
+            && !config.xml
+            && !config.fingerprint
+            && !config.outline
+    }
+
+    /// Write the output for a single matched node.
+    fn write_node(&self, node: &NodeRef, output: &mut dyn Write) -> io::Result<()> {
+        let config = &self.config;
+
+        if config.fingerprint {
+            return writeln!(
+                output,
+                "{}",
+                fingerprint::Fingerprint::of_node(node).to_json()
+            );
+        }
+
+        let color = config.color && !config.compact && self.json_path.is_none();
+        if config.outline {
+            let palette = color.then(pretty_print::Palette::default);
+            return outline::write_outline(node, config.outline_depth, palette, output);
         }
 
         if !config.attributes.is_empty() {
-            select_attributes(node, &config.attributes, &mut output);
-            continue;
+            return select_attributes(node, &config.attributes, config.fix_encoding, output);
         }
 
         if config.text_only {
-            writeln!(output, "{}", serialize_text(node, config.ignore_whitespace)).ok();
-            continue;
+            return writeln!(
+                output,
+                "{}",
+                serialize_text(node, config.ignore_whitespace, config.fix_encoding)
+            );
         }
 
-        if config.pretty_print {
-            writeln!(output, "{}", pretty_print::pretty_print(node)).ok();
-            continue;
+        if config.xml {
+            xml::serialize(node, output)?;
+            return writeln!(output);
         }
 
-        writeln!(output, "{}", node.to_string()).ok();
+        if self.writes_minified_html() {
+            return minify::write_minified(node, config.minify_options, output);
+        }
+
+        if config.pretty_print || color {
+            pretty_print::write_html(
+                node,
+                config.pretty_print.then_some(config.pretty_options),
+                color.then(pretty_print::Palette::default),
+                output,
+            )?;
+            return writeln!(output);
+        }
+
+        writeln!(output, "{}", node.to_string())
     }
+}
 
-    let mut result = String::from_utf8(output)?;
+/// An element matched by a [`Query`]. It's only serialized when one of the accessors is called.
+#[derive(Debug, Clone)]
+pub struct Match {
+    node: NodeRef,
+}
 
-    // Compact output if requested
-    if config.compact {
-        // Try to parse as JSON first (trim whitespace before parsing)
-        let trimmed = result.trim();
+impl Match {
+    /// The matched element in the parsed document.
+    pub fn node(&self) -> &NodeRef {
+        &self.node
+    }
 
-        // Decode HTML entities first
-        let unescaped = match htmlescape::decode_html(trimmed) {
-            Ok(decoded) => decoded,
-            Err(_) => trimmed.to_string(),
-        };
+    /// The element's tag name, e.g. `div`.
+    pub fn name(&self) -> String {
+        self.node
+            .as_element()
+            .map(|element| element.name.local.to_string())
+            .unwrap_or_default()
+    }
 
-        // Try direct parse first
-        let json_value = serde_json::from_str::<serde_json::Value>(&unescaped).or_else(|_| {
-            // If it fails, try fixing malformed JSON by escaping control chars inside strings
-            let mut fixed = String::with_capacity(unescaped.len() * 2);
-            let mut in_string = false;
-            let mut escape_next = false;
-
-            for c in unescaped.chars() {
-                if escape_next {
-                    fixed.push(c);
-                    escape_next = false;
-                    continue;
-                }
+    /// The HTML of the element itself and its contents.
+    pub fn outer_html(&self) -> String {
+        self.node.to_string()
+    }
 
-                if c == '\\' {
-                    fixed.push(c);
-                    escape_next = true;
-                    continue;
-                }
+    /// The HTML of the element's contents.
+    pub fn inner_html(&self) -> String {
+        self.node
+            .children()
+            .map(|child| child.to_string())
+            .collect()
+    }
 
-                if c == '"' {
-                    in_string = !in_string;
-                    fixed.push(c);
-                    continue;
-                }
+    /// The text of all text nodes below the element, concatenated.
+    pub fn text(&self) -> String {
+        self.node.text_contents()
+    }
 
-                // Only escape control characters when inside strings
-                if in_string && c.is_control() {
-                    match c {
-                        '\n' => fixed.push_str("\\n"),
-                        '\r' => fixed.push_str("\\r"),
-                        '\t' => fixed.push_str("\\t"),
-                        _ => {} // Skip other control chars
-                    }
-                } else {
-                    fixed.push(c);
-                }
+    pub fn attribute(&self, name: &str) -> Option<String> {
+        let element = self.node.as_element()?;
+        element.attributes.borrow().get(name).map(str::to_string)
+    }
+
+    /// All attributes of the element as `(name, value)` pairs, in source order.
+    pub fn attributes(&self) -> Vec<(String, String)> {
+        self.node
+            .as_element()
+            .map(|element| {
+                element
+                    .attributes
+                    .borrow()
+                    .map
+                    .iter()
+                    .map(|(name, attribute)| (name.local.to_string(), attribute.value.clone()))
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+}
+
+/// Compile a selector, on XML with namespace prefixes as in [`xml::qualify_selector`].
+fn compile_selector(selector: &str, xml: bool) -> Result<Selectors, HqError> {
+    if xml {
+        return Selectors::compile(&xml::qualify_selector(selector))
+            .map_err(|()| HqError::invalid_xml_selector(selector));
+    }
+    Selectors::compile(selector).map_err(|()| HqError::invalid_selector(selector))
+}
+
+/// Parse every JSON value in `text`, tolerating HTML entities and raw control characters
+/// inside strings, as commonly found in `<script>` blocks.
+fn decode_json_values(text: &str) -> Result<Vec<serde_json::Value>, serde_json::Error> {
+    // Try to parse as JSON first (trim whitespace before parsing)
+    let trimmed = text.trim();
+
+    // Decode HTML entities first
+    let unescaped = match htmlescape::decode_html(trimmed) {
+        Ok(decoded) => decoded,
+        Err(_) => trimmed.to_string(),
+    };
+
+    // Try direct parse first
+    parse_json_stream(&unescaped).or_else(|_| {
+        // If it fails, try fixing malformed JSON by escaping control chars inside strings
+        let mut fixed = String::with_capacity(unescaped.len() * 2);
+        let mut in_string = false;
+        let mut escape_next = false;
+
+        for c in unescaped.chars() {
+            if escape_next {
+                fixed.push(c);
+                escape_next = false;
+                continue;
             }
 
-            serde_json::from_str::<serde_json::Value>(&fixed)
-        });
+            if c == '\\' {
+                fixed.push(c);
+                escape_next = true;
+                continue;
+            }
 
-        if let Ok(json_value) = json_value {
-            // If it's valid JSON, serialize it compactly
-            // This preserves spaces within text values while removing structural whitespace
-            result = serde_json::to_string(&json_value)?;
-        } else {
-            // If not JSON, minify HTML by removing whitespace between tags
-            result = result
-                .split('>')
-                .map(|s| s.trim_start())
-                .collect::<Vec<_>>()
-                .join(">");
+            if c == '"' {
+                in_string = !in_string;
+                fixed.push(c);
+                continue;
+            }
+
+            // Only escape control characters when inside strings
+            if in_string && c.is_control() {
+                match c {
+                    '\n' => fixed.push_str("\\n"),
+                    '\r' => fixed.push_str("\\r"),
+                    '\t' => fixed.push_str("\\t"),
+                    _ => {} // Skip other control chars
+                }
+            } else {
+                fixed.push(c);
+            }
         }
+
+        parse_json_stream(&fixed)
+    })
+}
+
+fn parse_json_stream(text: &str) -> Result<Vec<serde_json::Value>, serde_json::Error> {
+    serde_json::Deserializer::from_str(text)
+        .into_iter::<serde_json::Value>()
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_query_runs_on_many_documents() {
+        let query = Query::compile(&HqConfig {
+            selector: "a".to_string(),
+            base: Some("https://example.com/jobs/".to_string()),
+            remove_nodes: vec!["span".to_string()],
+            ..HqConfig::default()
+        })
+        .unwrap();
+
+        assert_eq!(
+            query.run("<a href=1>One<span>!</span></a>").unwrap(),
+            "<a href=\"https://example.com/jobs/1\">One</a>\n"
+        );
+        assert_eq!(
+            query.run("<a href=/2>Two</a>").unwrap(),
+            "<a href=\"https://example.com/2\">Two</a>\n"
+        );
+    }
+
+    #[test]
+    fn test_document_url() {
+        let html = "<head><base href=/jobs/></head><a href=1>One</a>";
+        let links = |config: HqConfig| {
+            Query::compile(&HqConfig {
+                selector: "a".to_string(),
+                attributes: vec!["href".to_string()],
+                ..config
+            })
+            .unwrap()
+            .run(html)
+            .unwrap()
+        };
+        let url = Some("https://example.com/about".to_string());
+        let base = Some("https://example.org/".to_string());
+
+        assert_eq!(links(HqConfig::default()), "1\n");
+        // the URL is only context, unless asked to absolutize
+        assert_eq!(
+            links(HqConfig {
+                url: url.clone(),
+                ..HqConfig::default()
+            }),
+            "1\n"
+        );
+        assert_eq!(
+            links(HqConfig {
+                url: url.clone(),
+                absolutize: true,
+                ..HqConfig::default()
+            }),
+            "https://example.com/jobs/1\n"
+        );
+        assert_eq!(
+            links(HqConfig {
+                url: url.clone(),
+                base: base.clone(),
+                ..HqConfig::default()
+            }),
+            "https://example.org/1\n"
+        );
+        assert_eq!(
+            links(HqConfig {
+                url,
+                base,
+                detect_base: true,
+                ..HqConfig::default()
+            }),
+            "https://example.com/jobs/1\n"
+        );
+
+        let query = Query::compile(&HqConfig {
+            selector: "a".to_string(),
+            url: Some("https://example.com/".to_string()),
+            absolutize: true,
+            ..HqConfig::default()
+        })
+        .unwrap();
+        let url = Url::parse("https://example.net/a/b").unwrap();
+        assert_eq!(
+            query.run_at("<a href=c>C</a>", &url).unwrap(),
+            "<a href=\"https://example.net/a/c\">C</a>\n"
+        );
     }
 
-    Ok(result)
+    #[test]
+    fn test_normalize_urls() {
+        let query = Query::compile(&HqConfig {
+            selector: "body".to_string(),
+            base: Some("https://Example.com/jobs/".to_string()),
+            normalize_urls: true,
+            strip_params: vec!["ref".to_string()],
+            ..HqConfig::default()
+        })
+        .unwrap();
+        assert_eq!(
+            query
+                .run("<a href=\"42/?utm_source=feed&ref=x&b=2&a=1#apply\">42</a><img src=\"//cdn.example.com/a.png?gclid=1\">")
+                .unwrap(),
+            "<body><a href=\"https://example.com/jobs/42?a=1&amp;b=2\">42</a><img src=\"https://cdn.example.com/a.png\"></body>\n"
+        );
+
+        // links are deduplicated by their normalized URL
+        let query = Query::compile(&HqConfig {
+            links: true,
+            dedupe_links: true,
+            normalize_urls: true,
+            surt: true,
+            attributes: Vec::new(),
+            json_path: Some(".surt".to_string()),
+            ..HqConfig::default()
+        })
+        .unwrap();
+        assert_eq!(
+            query
+                .run("<a href=\"https://www.example.com/jobs/?utm_medium=x\">A</a><a href=\"https://www.example.com/jobs\">B</a>")
+                .unwrap(),
+            "com,example)/jobs\n"
+        );
+    }
+
+    #[test]
+    fn test_query_run_on_subtree() {
+        let query = Query::compile(&HqConfig {
+            selector: "li".to_string(),
+            text_only: true,
+            ..HqConfig::default()
+        })
+        .unwrap();
+
+        let document = kuchikiki::parse_html().one("<ul><li>A</li></ul><ol><li>B</li></ol>");
+        let list = document.select_first("ol").unwrap();
+        assert_eq!(query.run_on(list.as_node()).unwrap(), "B\n");
+    }
+
+    #[test]
+    fn test_matches() {
+        let query = Query::compile(&HqConfig {
+            selector: ".job".to_string(),
+            base: Some("https://example.com/".to_string()),
+            ..HqConfig::default()
+        })
+        .unwrap();
+
+        let document = kuchikiki::parse_html()
+            .one("<a class=job href=/1 data-id=1>Line\none</a><p class=job>Two <b>2</b></p>");
+        let matches: Vec<_> = query.matches(&document).collect();
+        assert_eq!(matches.len(), 2);
+
+        assert_eq!(matches[0].name(), "a");
+        assert_eq!(matches[0].text(), "Line\none");
+        assert_eq!(
+            matches[0].attribute("href").as_deref(),
+            Some("https://example.com/1")
+        );
+        assert_eq!(
+            matches[0].attributes(),
+            [
+                ("class".to_string(), "job".to_string()),
+                ("href".to_string(), "https://example.com/1".to_string()),
+                ("data-id".to_string(), "1".to_string()),
+            ]
+        );
+
+        assert_eq!(matches[1].inner_html(), "Two <b>2</b>");
+        assert_eq!(matches[1].outer_html(), "<p class=\"job\">Two <b>2</b></p>");
+    }
+
+    #[test]
+    fn test_run_into() {
+        let query = Query::compile(&HqConfig {
+            selector: "p".to_string(),
+            text_only: true,
+            ..HqConfig::default()
+        })
+        .unwrap();
+
+        let mut output = Vec::new();
+        query.run_into("<p>One</p><p>Two</p>", &mut output).unwrap();
+        assert_eq!(output, b"One\nTwo\n");
+    }
+
+    #[test]
+    fn test_xml() {
+        let feed = r#"<rss xmlns:dc="http://purl.org/dc/elements/1.1/"><channel>
+<item><title><![CDATA[Fish & <Chips>]]></title><dc:creator>Ann</dc:creator><guid/></item>
+</channel></rss>"#;
+        let run = |config: HqConfig| Query::compile(&config).unwrap().run(feed).unwrap();
+
+        assert_eq!(
+            run(HqConfig {
+                selector: "item > title, dc|creator".to_string(),
+                text_only: true,
+                xml: true,
+                ..HqConfig::default()
+            }),
+            "Fish & <Chips>\nAnn\n"
+        );
+        assert_eq!(
+            run(HqConfig {
+                selector: "item".to_string(),
+                remove_nodes: vec!["dc|creator".to_string()],
+                xml: true,
+                ..HqConfig::default()
+            }),
+            "<item xmlns:dc=\"http://purl.org/dc/elements/1.1/\"><title>Fish &amp; &lt;Chips&gt;</title><guid/></item>\n"
+        );
+
+        // XML syntax isn't HTML syntax
+        assert!(matches!(
+            Query::compile(&HqConfig {
+                xml: true,
+                ..HqConfig::default()
+            })
+            .unwrap()
+            .run("<p>unclosed"),
+            Err(HqError::Input(_))
+        ));
+    }
+
+    #[test]
+    fn test_compile_errors() {
+        let error = |config: HqConfig| Query::compile(&config).unwrap_err();
+
+        assert!(matches!(
+            error(HqConfig {
+                selector: "a[".to_string(),
+                ..HqConfig::default()
+            }),
+            HqError::InvalidSelector { position: 2, .. }
+        ));
+        assert!(matches!(
+            error(HqConfig {
+                remove_nodes: vec!["p".to_string(), "::".to_string()],
+                ..HqConfig::default()
+            }),
+            HqError::InvalidSelector { selector, .. } if selector == "::"
+        ));
+        assert!(matches!(
+            error(HqConfig {
+                base: Some("not a url".to_string()),
+                ..HqConfig::default()
+            }),
+            HqError::InvalidArgument(_)
+        ));
+        assert!(matches!(
+            error(HqConfig {
+                json_path: Some(".[".to_string()),
+                ..HqConfig::default()
+            }),
+            HqError::InvalidArgument(_)
+        ));
+    }
 }
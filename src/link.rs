@@ -1,47 +1,361 @@
-use html5ever::local_name;
+use crate::normalize::Normalizer;
 use kuchikiki::NodeRef;
+use kuchikiki::traits::NodeIterator;
+use regex::Regex;
+use serde_json::json;
 use url::Url;
 
-pub fn rewrite_relative_url(node: &NodeRef, base: &Url) {
+/// The attributes holding a URL, and the elements they do so on.
+const URL_ATTRIBUTES: [(&str, &[&str]); 11] = [
+    ("href", &["a", "area", "link"]),
+    (
+        "src",
+        &[
+            "audio", "embed", "frame", "iframe", "img", "input", "script", "source", "track",
+            "video",
+        ],
+    ),
+    ("srcset", &["img", "source"]),
+    ("action", &["form"]),
+    ("formaction", &["button", "input"]),
+    ("poster", &["video"]),
+    ("data", &["object"]),
+    ("cite", &["blockquote", "del", "ins", "q"]),
+    ("background", &["body", "table", "td", "th"]),
+    ("longdesc", &["frame", "iframe", "img"]),
+    ("manifest", &["html"]),
+];
+
+/// What to do to URLs: resolve them against a base, normalize them, or both.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct UrlRewrite<'a> {
+    pub base: Option<&'a Url>,
+    pub normalizer: Option<&'a Normalizer>,
+}
+
+impl UrlRewrite<'_> {
+    /// Rewrite a single URL, or leave it as is if it can't be parsed.
+    pub fn apply(&self, url: &str) -> String {
+        let parsed = match self.base {
+            Some(base) => join(base, url),
+            None => Url::parse(url),
+        };
+
+        match parsed {
+            Ok(mut parsed) => {
+                if let Some(normalizer) = self.normalizer {
+                    normalizer.normalize(&mut parsed);
+                }
+                parsed.to_string()
+            }
+            Err(_) => url.to_string(),
+        }
+    }
+}
+
+/// Rewrite the URLs in all elements below and including `root`.
+pub fn rewrite_urls(root: &NodeRef, rewrite: UrlRewrite) {
+    for element in root.inclusive_descendants().elements() {
+        rewrite_element_urls(element.as_node(), rewrite);
+    }
+}
+
+/// Rewrite the URLs in the attributes of a single element, including the candidates of a
+/// `srcset` and `url()`s in its `style`.
+fn rewrite_element_urls(node: &NodeRef, rewrite: UrlRewrite) {
     let Some(elem) = node.as_element() else {
         return;
     };
-    if !(local_name!("a") == elem.name.local
-        || local_name!("link") == elem.name.local
-        || local_name!("area") == elem.name.local)
+    let mut attrs = elem.attributes.borrow_mut();
+
+    for (attribute, elements) in URL_ATTRIBUTES {
+        if !elements.contains(&&*elem.name.local) {
+            continue;
+        }
+        let Some(value) = attrs.get_mut(attribute) else {
+            continue;
+        };
+
+        *value = if attribute == "srcset" {
+            rewrite_srcset(value, rewrite)
+        } else {
+            rewrite.apply(value)
+        };
+    }
+
+    if let Some(style) = attrs.get_mut("style") {
+        *style = rewrite_style_urls(style, rewrite);
+    }
+}
+
+/// Resolve `url` against `base` like a browser would.
+fn join(base: &Url, url: &str) -> Result<Url, url::ParseError> {
+    // browsers skip any number of slashes before the host of http(s) and similar URLs, while
+    // the url crate rejects more than two
+    let trimmed = url.trim_matches(|c: char| c.is_ascii_whitespace() || c.is_ascii_control());
+    let host_start = trimmed.trim_start_matches(['/', '\\']);
+    if trimmed.len() - host_start.len() > 2
+        && matches!(base.scheme(), "http" | "https" | "ws" | "wss" | "ftp")
     {
-        return;
+        base.join(&format!("//{host_start}"))
+    } else {
+        base.join(url)
     }
-    let mut attrs = elem.attributes.borrow_mut();
+}
 
-    if attrs.contains("href") {
-        let Some(url) = attrs.get_mut("href") else {
-            return;
+/// Rewrite each image candidate in a `srcset`, keeping their descriptors.
+///
+/// Candidates are split like the HTML spec says: URLs can contain commas, but not whitespace,
+/// and descriptors run up to the next comma outside of parentheses.
+fn rewrite_srcset(srcset: &str, rewrite: UrlRewrite) -> String {
+    let mut candidates = Vec::new();
+    let mut rest = srcset;
+
+    loop {
+        rest = rest.trim_start_matches(|c: char| c.is_ascii_whitespace() || c == ',');
+        if rest.is_empty() {
+            break;
+        }
+
+        let url_end = rest
+            .find(|c: char| c.is_ascii_whitespace())
+            .unwrap_or(rest.len());
+        let (url, after) = rest.split_at(url_end);
+
+        // a URL ending in commas ends the candidate, without any descriptors
+        let trimmed = url.trim_end_matches(',');
+        let descriptors = if trimmed.len() < url.len() {
+            rest = after;
+            ""
+        } else {
+            let mut depth = 0_usize;
+            let end = after
+                .find(|c| {
+                    match c {
+                        '(' => depth += 1,
+                        ')' => depth = depth.saturating_sub(1),
+                        ',' if depth == 0 => return true,
+                        _ => {}
+                    }
+                    false
+                })
+                .unwrap_or(after.len());
+            rest = &after[end..];
+            after[..end].trim()
         };
-        if url.starts_with("////") {
-            *url = url.trim_start_matches('/').to_string();
-            return;
+
+        let url = rewrite.apply(trimmed);
+        candidates.push(if descriptors.is_empty() {
+            url
+        } else {
+            format!("{url} {descriptors}")
+        });
+    }
+
+    candidates.join(", ")
+}
+
+/// Rewrite the `url()`s in inline CSS, keeping their quotes.
+fn rewrite_style_urls(style: &str, rewrite: UrlRewrite) -> String {
+    let mut result = String::with_capacity(style.len());
+    let mut rest = style;
+
+    while let Some(start) = find_url_function(rest) {
+        let (before, after) = rest.split_at(start + "url(".len());
+        result.push_str(before);
+
+        rest = after.trim_start();
+        result.push_str(&after[..after.len() - rest.len()]);
+
+        let (quote, value) = match rest.chars().next() {
+            Some(quote @ ('"' | '\'')) => (Some(quote), &rest[1..]),
+            _ => (None, rest),
+        };
+        // unterminated, so it's not a URL after all
+        let Some(end) = value.find(quote.unwrap_or(')')) else {
+            break;
+        };
+
+        let url = &value[..end];
+        if let Some(quote) = quote {
+            result.push(quote);
         }
-        let new_url = base.join(url).ok().unwrap_or_else(|| base.to_owned());
-        attrs.insert("href", new_url.to_string());
+        let trimmed = url.trim_end();
+        result.push_str(&rewrite.apply(trimmed));
+        result.push_str(&url[trimmed.len()..]);
+        rest = &value[end..];
     }
+
+    result.push_str(rest);
+    result
 }
 
-pub fn detect_base(document: &NodeRef) -> Option<Url> {
-    let Ok(node) = document.select_first("base") else {
-        return None;
-    };
+/// The position of the next `url(` function in CSS, ignoring case and names merely ending in it.
+fn find_url_function(css: &str) -> Option<usize> {
+    let lower = css.to_ascii_lowercase();
+    let mut from = 0;
+    while let Some(found) = lower[from..].find("url(") {
+        let start = from + found;
+        let preceded_by_name = lower[..start]
+            .chars()
+            .next_back()
+            .is_some_and(|c| c.is_alphanumeric() || c == '-' || c == '_');
+        if !preceded_by_name {
+            return Some(start);
+        }
+        from = start + "url(".len();
+    }
+    None
+}
 
+/// The URL in the document's first `<base href>`, resolved against the document's own `url`.
+pub fn detect_base(document: &NodeRef, url: Option<&Url>) -> Option<Url> {
+    let node = document.select_first("base[href]").ok()?;
     let attrs = node.attributes.borrow();
+    let href = attrs.get("href")?;
 
-    if attrs.contains("href") {
-        let href = attrs
-            .get("href")
-            .expect("should have retrieved href from node attributes");
-        return Url::parse(href).ok();
+    match url {
+        Some(url) => url.join(href).ok(),
+        None => Url::parse(href).ok(),
     }
+}
 
-    None
+/// A hyperlink found in a document, as output by `--links`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Link {
+    /// The absolute URL, or the `href` as is if there's nothing to resolve it against.
+    pub url: String,
+    /// The link's text with whitespace collapsed, or the `alt` of an `<area>`.
+    pub text: String,
+    pub title: Option<String>,
+    pub rel: Vec<String>,
+    /// Whether the link points to the same host as the document.
+    pub internal: bool,
+    pub nofollow: bool,
+    /// The URL's SURT form, if asked for.
+    pub surt: Option<String>,
+}
+
+impl Link {
+    pub fn to_json(&self) -> serde_json::Value {
+        let mut json = json!({
+            "url": self.url,
+            "text": self.text,
+            "title": self.title,
+            "rel": self.rel,
+            "internal": self.internal,
+            "nofollow": self.nofollow,
+        });
+        if let Some(surt) = &self.surt {
+            json["surt"] = json!(surt);
+        }
+        json
+    }
+
+    /// The key links are deduplicated by: their URL as normalized by `normalizer`, which drops
+    /// the fragment among others, whether or not the output is normalized.
+    pub fn dedupe_key(&self, normalizer: &Normalizer) -> String {
+        match Url::parse(&self.url) {
+            Ok(mut url) => {
+                normalizer.normalize(&mut url);
+                url.to_string()
+            }
+            Err(_) => self
+                .url
+                .split_once('#')
+                .map_or(&*self.url, |(url, _)| url)
+                .to_string(),
+        }
+    }
+}
+
+/// Collect the hyperlinks (`<a href>` and `<area href>`) below and including `root`, rewritten
+/// and classified relative to `document_url`.
+pub fn extract_links(root: &NodeRef, rewrite: UrlRewrite, document_url: Option<&Url>) -> Vec<Link> {
+    let mut links = Vec::new();
+
+    for element in root.inclusive_descendants().elements() {
+        let name = &*element.name.local;
+        if name != "a" && name != "area" {
+            continue;
+        }
+        let attrs = element.attributes.borrow();
+        let Some(href) = attrs.get("href") else {
+            continue;
+        };
+
+        let resolved = Url::parse(&rewrite.apply(href)).ok();
+        let internal = match (&resolved, document_url) {
+            (Some(url), Some(document_url)) => url.host() == document_url.host(),
+            // an unresolved relative link stays on the same host, wherever that is
+            (None, _) => true,
+            (Some(_), None) => false,
+        };
+
+        let rel: Vec<String> = attrs
+            .get("rel")
+            .unwrap_or_default()
+            .split_ascii_whitespace()
+            .map(str::to_ascii_lowercase)
+            .collect();
+
+        let text = if name == "area" {
+            attrs.get("alt").unwrap_or_default().to_string()
+        } else {
+            element.as_node().text_contents()
+        };
+
+        links.push(Link {
+            url: resolved.map_or_else(|| href.to_string(), |url| url.to_string()),
+            text: text.split_whitespace().collect::<Vec<_>>().join(" "),
+            title: attrs.get("title").map(str::to_string),
+            nofollow: rel.iter().any(|rel| rel == "nofollow"),
+            rel,
+            internal,
+            surt: None,
+        });
+    }
+
+    links
+}
+
+/// Which links `--links` outputs.
+#[derive(Debug, Clone, Default)]
+pub struct LinkFilter {
+    /// Only links to the document's own host.
+    pub same_host: bool,
+    /// Only links whose path starts with this.
+    pub path_prefix: Option<String>,
+    /// Only links whose URL matches this.
+    pub pattern: Option<Regex>,
+}
+
+impl LinkFilter {
+    pub fn accepts(&self, link: &Link) -> bool {
+        if self.same_host && !link.internal {
+            return false;
+        }
+
+        if let Some(prefix) = &self.path_prefix {
+            let path = match Url::parse(&link.url) {
+                Ok(url) => url.path().to_string(),
+                // relative links that couldn't be resolved
+                Err(_) => link
+                    .url
+                    .split(['?', '#'])
+                    .next()
+                    .unwrap_or_default()
+                    .to_string(),
+            };
+            if !path.starts_with(prefix.as_str()) {
+                return false;
+            }
+        }
+
+        self.pattern
+            .as_ref()
+            .is_none_or(|pattern| pattern.is_match(&link.url))
+    }
 }
 
 #[cfg(test)]
@@ -58,13 +372,13 @@ mod tests {
                 let (mut input, expected) = $value;
                 let base = Url::parse("https://mgdm.net").unwrap();
                 let doc = make_doc(&mut input);
-                for css_match in doc
-                    .select("a, area, link")
-                    .expect("Failed to parse CSS selector while doing link rewriting")
-                {
-                    let node = css_match.as_node();
-                    rewrite_relative_url(&node, &base);
-                }
+                rewrite_urls(
+                    &doc,
+                    UrlRewrite {
+                        base: Some(&base),
+                        ..Default::default()
+                    },
+                );
 
                 let result = serialize_doc(&doc);
                 assert_eq!(expected, result);
@@ -80,7 +394,7 @@ mod tests {
             fn $name() {
                 let (mut input, expected) = $value;
                 let doc = make_doc(&mut input);
-                let result = detect_base(&doc);
+                let result = detect_base(&doc, None);
                 assert_eq!(expected, result);
             }
         )*
@@ -113,12 +427,138 @@ mod tests {
             "<html><head></head><body><map name=\"primary\"><area coords=\"75,75,75\" href=\"left.html\" shape=\"circle\"></map></body></html>".to_string(),
             "<html><head></head><body><map name=\"primary\"><area coords=\"75,75,75\" href=\"https://mgdm.net/left.html\" shape=\"circle\"></map></body></html>".to_string()
         ),
+        rewrite_nested_urls: (
+            "<html><head></head><body><form action=\"search\"><button formaction=\"/go\">Go</button></form><video poster=\"p.jpg\"><source src=\"v.mp4\"></video><blockquote cite=\"/quote\"></blockquote><object data=\"o.swf\"></object><table background=\"bg.png\"><tbody><tr><td>x</td></tr></tbody></table></body></html>".to_string(),
+            "<html><head></head><body><form action=\"https://mgdm.net/search\"><button formaction=\"https://mgdm.net/go\">Go</button></form><video poster=\"https://mgdm.net/p.jpg\"><source src=\"https://mgdm.net/v.mp4\"></video><blockquote cite=\"https://mgdm.net/quote\"></blockquote><object data=\"https://mgdm.net/o.swf\"></object><table background=\"https://mgdm.net/bg.png\"><tbody><tr><td>x</td></tr></tbody></table></body></html>".to_string(),
+        ),
+        rewrite_img_src_and_srcset: (
+            "<html><head></head><body><img longdesc=\"d.html\" src=\"a.jpg\" srcset=\"a.jpg 1x,b,c.jpg 2x,  /d.jpg (max-width: 1px, 2px) 3x ,e.jpg,\"></body></html>".to_string(),
+            "<html><head></head><body><img longdesc=\"https://mgdm.net/d.html\" src=\"https://mgdm.net/a.jpg\" srcset=\"https://mgdm.net/a.jpg 1x, https://mgdm.net/b,c.jpg 2x, https://mgdm.net/d.jpg (max-width: 1px, 2px) 3x, https://mgdm.net/e.jpg\"></body></html>".to_string(),
+        ),
+        rewrite_style_urls: (
+            "<html><head></head><body><div style=\"background: URL( 'a.png' ) no-repeat, url(/b.png); --x: myurl(c); mask: url(&quot;d.svg&quot;)\"></div></body></html>".to_string(),
+            "<html><head></head><body><div style=\"background: URL( 'https://mgdm.net/a.png' ) no-repeat, url(https://mgdm.net/b.png); --x: myurl(c); mask: url(&quot;https://mgdm.net/d.svg&quot;)\"></div></body></html>".to_string(),
+        ),
+        rewrite_protocol_relative_url: (
+            "<html><head></head><body><a href=\"//example.org/foo\">A</a><a href=\"////example.org/bar\">B</a></body></html>".to_string(),
+            "<html><head></head><body><a href=\"https://example.org/foo\">A</a><a href=\"https://example.org/bar\">B</a></body></html>".to_string(),
+        ),
+        do_not_rewrite_unparseable_url: (
+            "<html><head></head><body><a href=\"http://[::1\">A</a><img src=\"data:image/gif;base64,R0lG\"></body></html>".to_string(),
+            "<html><head></head><body><a href=\"http://[::1\">A</a><img src=\"data:image/gif;base64,R0lG\"></body></html>".to_string(),
+        ),
         do_not_rewrite_absolute_url: (
             "<html><head></head><body><a href=\"https://example.org/foo/bar\">Hello</a></body></html>".to_string(),
             "<html><head></head><body><a href=\"https://example.org/foo/bar\">Hello</a></body></html>".to_string(),
         ),
     }
 
+    #[test]
+    fn detect_relative_base() {
+        let doc = make_doc(
+            &mut "<base target=_blank><base href=\"/jobs/\"><base href=\"/x/\">".to_string(),
+        );
+        let url = Url::parse("https://example.org/about/team").unwrap();
+
+        assert_eq!(
+            detect_base(&doc, Some(&url)),
+            Some(Url::parse("https://example.org/jobs/").unwrap())
+        );
+        assert_eq!(detect_base(&doc, None), None);
+    }
+
+    #[test]
+    fn rewrite_and_normalize_urls() {
+        let doc = make_doc(
+            &mut "<a href=\"https://Example.com:443/a/?utm_source=x#top\">A</a><img srcset=\"/b.png?fbclid=1 2x\">".to_string(),
+        );
+        let normalizer = Normalizer::default();
+
+        rewrite_urls(
+            &doc,
+            UrlRewrite {
+                base: None,
+                normalizer: Some(&normalizer),
+            },
+        );
+        assert_eq!(
+            serialize_doc(&doc),
+            "<html><head></head><body><a href=\"https://example.com/a\">A</a><img srcset=\"/b.png?fbclid=1 2x\"></body></html>"
+        );
+    }
+
+    #[test]
+    fn extract_and_filter_links() {
+        let doc = make_doc(
+            &mut "<a href=\"/jobs/1\" rel=\"NoFollow ugc\" title=\"First\"> Job\n  one </a>\
+                  <a href=\"https://other.example/jobs/2\">Two</a>\
+                  <map><area href=\"about\" alt=\"About\"></map><a name=\"anchor\">x</a>"
+                .to_string(),
+        );
+        let url = Url::parse("https://example.com/team/").unwrap();
+
+        let rewrite = UrlRewrite {
+            base: Some(&url),
+            normalizer: None,
+        };
+        let links = extract_links(&doc, rewrite, Some(&url));
+        assert_eq!(
+            links[0],
+            Link {
+                url: "https://example.com/jobs/1".to_string(),
+                text: "Job one".to_string(),
+                title: Some("First".to_string()),
+                rel: vec!["nofollow".to_string(), "ugc".to_string()],
+                internal: true,
+                nofollow: true,
+                surt: None,
+            }
+        );
+        assert!(!links[1].internal);
+        assert_eq!(links[2].url, "https://example.com/team/about");
+        assert_eq!(links[2].text, "About");
+        assert_eq!(links.len(), 3);
+
+        let urls = |filter: LinkFilter| -> Vec<_> {
+            links
+                .iter()
+                .filter(|link| filter.accepts(link))
+                .map(|link| link.url.as_str())
+                .collect()
+        };
+        assert_eq!(
+            urls(LinkFilter {
+                same_host: true,
+                ..LinkFilter::default()
+            }),
+            [
+                "https://example.com/jobs/1",
+                "https://example.com/team/about"
+            ]
+        );
+        assert_eq!(
+            urls(LinkFilter {
+                path_prefix: Some("/jobs/".to_string()),
+                ..LinkFilter::default()
+            }),
+            ["https://example.com/jobs/1", "https://other.example/jobs/2"]
+        );
+        assert_eq!(
+            urls(LinkFilter {
+                pattern: Some(Regex::new(r"/\d+$").unwrap()),
+                same_host: true,
+                ..LinkFilter::default()
+            }),
+            ["https://example.com/jobs/1"]
+        );
+
+        // without a URL to resolve against, relative links are kept as they are
+        let links = extract_links(&doc, UrlRewrite::default(), None);
+        assert_eq!(links[0].url, "/jobs/1");
+        assert!(links[0].internal);
+        assert!(!links[1].internal);
+    }
+
     detect_base_tests! {
         base_ok: (
             "<html><head><base href=\"https://example.org\"></head><body><a href=\"https://example.org/foo/bar\">Hello</a></body></html>".to_string(),
@@ -1,29 +1,92 @@
-use clap::Parser;
-use hq::{HqConfig, process_html};
-use std::error::Error;
-use std::fs::File;
-use std::io::Read;
+use clap::{ColorChoice, Parser, Subcommand};
+use hq::cdx::{self, CdxEntry};
+use hq::charset;
+use hq::decompress::{self, Compression};
+use hq::diff;
+use hq::explore;
+use hq::fingerprint::{self, Fingerprint};
+use hq::minify::MinifyOptions;
+use hq::pretty_print::PrettyOptions;
+use hq::stream::{self, Streamed};
+use hq::suggest;
+use hq::warc::{WarcReader, WarcRecord};
+use hq::{HqConfig, HqError, Query};
+use kuchikiki::NodeRef;
+use rayon::prelude::*;
+use serde_json::json;
+use std::fs::{self, File};
+use std::io::{
+    self, BufRead, BufReader, BufWriter, Cursor, IsTerminal, Read, Seek, SeekFrom, Write,
+};
+use std::path::Path;
+use std::process::ExitCode;
+use url::Url;
+
+/// How many inputs are processed in parallel before their output is written, in input order.
+const BATCH_SIZE: usize = 1024;
+
+const EXIT_STATUS: &str = "\
+Exit status:
+  0  Success
+  1  Nothing was output, with --exit-status
+  2  Invalid arguments, e.g. an unknown encoding or an invalid base URL
+  3  Input or output failed, e.g. a missing file or a malformed WARC record
+  4  Invalid CSS selector";
 
 #[derive(Debug, Clone, Parser)]
-#[command(version, author, about)]
+#[command(
+    version,
+    author,
+    about,
+    args_conflicts_with_subcommands = true,
+    after_help = EXIT_STATUS
+)]
 #[expect(clippy::struct_excessive_bools)] // ok since it's a "central point" for options
 struct Config {
+    #[command(subcommand)]
+    command: Option<Command>,
+
     /// What CSS selector to filter with.
     #[arg(default_value = ":root")]
     selector: String,
 
-    /// Where to read HTML input from.
-    #[arg(short = 'f', long = "filename", default_value = "-")]
-    input_path: String,
+    /// Files, directories or glob patterns to read HTML input from. Defaults to stdin (-).
+    files: Vec<String>,
+
+    /// Where to read HTML input from. Can be given multiple times.
+    #[arg(short = 'f', long = "filename")]
+    input_paths: Vec<String>,
+
+    /// Read all files below directories given as input.
+    #[arg(short = 'R', long)]
+    recursive: bool,
+
+    /// Prefix each output line with the input's filename. The default with multiple inputs.
+    #[arg(short = 'H', long, conflicts_with = "no_filename")]
+    with_filename: bool,
+
+    /// Never prefix output lines with the input's filename.
+    #[arg(long)]
+    no_filename: bool,
 
     /// Where to write the filtered HTML to.
     #[arg(short = 'o', long = "output", default_value = "-")]
     output_path: String,
 
-    /// What URL to prepend to links without an origin, i.e. starting with a slash (/).
+    /// What URL to resolve relative links, image sources and other URLs in the output against.
     #[arg(short, long)]
     base: Option<String>,
 
+    /// The URL the input was fetched from, to resolve a relative `<base>` and, with
+    /// `--absolutize`, `--links` or `-N`, relative URLs in the output against, like in a browser.
+    #[arg(short, long)]
+    url: Option<String>,
+
+    /// Resolve relative URLs in the output against the input's URL, see `--url`, and its
+    /// `<base>`.
+    #[arg(long)]
+    absolutize: bool,
+
     /// Look for the `<base>` tag in input for the base.
     #[arg(short = 'B', long)]
     detect_base: bool,
@@ -40,6 +103,24 @@ struct Config {
     #[arg(short, long = "pretty")]
     pretty_print: bool,
 
+    /// When to highlight HTML output with colours: `auto` does so when writing to a terminal,
+    /// unless the `NO_COLOR` environment variable is set.
+    #[arg(long, value_name = "WHEN", default_value_t = ColorChoice::Auto)]
+    color: ColorChoice,
+
+    /// Spaces per level of indentation when pretty printing.
+    #[arg(long, value_name = "N", default_value_t = 2, requires = "pretty_print")]
+    indent: usize,
+
+    /// Indent pretty printed output with tabs instead of spaces.
+    #[arg(long, requires = "pretty_print", conflicts_with = "indent")]
+    tabs: bool,
+
+    /// Put each attribute on its own line in pretty printed start tags that would be wider
+    /// than this.
+    #[arg(long, value_name = "WIDTH", requires = "pretty_print")]
+    wrap_attributes: Option<usize>,
+
     /// Do not output the nodes matching any of these selectors.
     #[arg(short, long)]
     remove_nodes: Vec<String>,
@@ -48,40 +129,950 @@ struct Config {
     #[arg(short, long)]
     attributes: Vec<String>,
 
-    /// Remove all whitespace from output.
+    /// Minify HTML output and compact JSON output, removing whitespace that doesn't matter.
     #[arg(short, long)]
     compact: bool,
+
+    /// Leave comments out of minified HTML.
+    #[arg(long, requires = "compact")]
+    remove_comments: bool,
+
+    /// Leave optional end tags like `</li>` and `</p>` out of minified HTML.
+    #[arg(long, requires = "compact")]
+    omit_end_tags: bool,
+
+    /// Write attribute values in minified HTML without quotes where possible.
+    #[arg(long, requires = "compact")]
+    unquote_attributes: bool,
+
+    /// Write boolean attributes like `checked="checked"` in minified HTML as just their name.
+    #[arg(long, requires = "compact")]
+    collapse_boolean_attributes: bool,
+
+    /// Query the extracted JSON with a jq-like or JSONPath expression, e.g. `.hiringOrganization.name`.
+    #[arg(short = 'j', long)]
+    json_path: Option<String>,
+
+    /// Repair mojibake (e.g. `â€™` instead of `’`) in text and attribute output.
+    #[arg(long)]
+    fix_encoding: bool,
+
+    /// Decode input with this encoding instead of detecting it, e.g. `shift_jis`. A byte order
+    /// mark still takes precedence, as in browsers.
+    #[arg(short, long)]
+    encoding: Option<String>,
+
+    /// Report details such as the detected input encoding on stderr.
+    #[arg(short, long)]
+    verbose: bool,
+
+    /// Read input as WARC files and run the query on each HTML `response` record.
+    #[arg(short = 'W', long)]
+    warc: bool,
+
+    /// Byte offset of a single WARC record to read, e.g. from a CDX index.
+    #[arg(long, requires_all = ["warc", "length"])]
+    offset: Option<u64>,
+
+    /// Compressed length of the single WARC record to read at `--offset`.
+    #[arg(long, requires_all = ["warc", "offset"])]
+    length: Option<u64>,
+
+    /// Look up the newest capture of this URL in the `--cdx` index and query its WARC record.
+    #[arg(long, requires = "cdx", conflicts_with_all = ["files", "input_paths", "offset"])]
+    lookup: Option<String>,
+
+    /// CDXJ index to use for `--lookup`, as written by `hq index`.
+    #[arg(long, requires = "lookup")]
+    cdx: Option<String>,
+
+    /// Output the hyperlinks in the matched elements as JSON lines, with their absolute URL,
+    /// text, title, rel and whether they are internal or nofollow.
+    #[arg(long, conflicts_with_all = ["attributes", "text_only", "pretty_print"])]
+    links: bool,
+
+    /// Output each link only once, by its URL as normalized by `-N`.
+    #[arg(long, requires = "links")]
+    dedupe: bool,
+
+    /// Output only links to the document's own host.
+    #[arg(long, requires = "links")]
+    same_host: bool,
+
+    /// Output only links whose path starts with this, e.g. `/jobs/`.
+    #[arg(long, requires = "links")]
+    path_prefix: Option<String>,
+
+    /// Output only links whose URL matches this regular expression.
+    #[arg(long, requires = "links")]
+    link_regex: Option<String>,
+
+    /// Add the SURT form of each link's URL to the `--links` output, as used by CDX indexes.
+    #[arg(long, requires = "links")]
+    surt: bool,
+
+    /// Canonicalize URLs in the output: lowercase the host, drop default ports, fragments,
+    /// session IDs and trailing slashes, strip tracking parameters and sort the others.
+    #[arg(short = 'N', long)]
+    normalize_urls: bool,
+
+    /// Strip this query parameter too when normalizing URLs. Can be given multiple times, and
+    /// end in `*` to strip all parameters starting with it.
+    #[arg(long, value_name = "NAME", requires = "normalize_urls")]
+    strip_param: Vec<String>,
+
+    /// Output a fingerprint of the rendered text of each match as JSON lines: the SHA-1 of its
+    /// normalized words and a SimHash that's similar for similar texts, see `hq dedupe`.
+    #[arg(long, conflicts_with_all = ["attributes", "text_only", "pretty_print", "links"])]
+    fingerprint: bool,
+
+    /// Output an outline of the elements in each match instead of its HTML: tag names, ids,
+    /// classes, key attributes and snippets of text, with runs of similar siblings shown once.
+    #[arg(long, conflicts_with_all = ["attributes", "text_only", "pretty_print", "links", "fingerprint", "compact"])]
+    outline: bool,
+
+    /// How many levels below each match to outline.
+    #[arg(long, value_name = "N", requires = "outline")]
+    depth: Option<usize>,
+
+    /// Parse input as XML, e.g. sitemaps or feeds, and output matches as XML. Selectors can
+    /// use the document's namespace prefixes, as in `image|loc`.
+    #[arg(short = 'x', long, conflicts_with = "pretty_print")]
+    xml: bool,
+
+    /// Output only the first match.
+    #[arg(long)]
+    first: bool,
+
+    /// Match while reading input instead of parsing it into a tree first, for huge documents.
+    /// Queries that need the whole document fall back to the tree.
+    #[arg(long, conflicts_with = "warc")]
+    stream: bool,
+
+    /// Exit with status 1 if nothing was output, e.g. because no element matched.
+    #[arg(long)]
+    exit_status: bool,
 }
 
-fn main() -> Result<(), Box<dyn Error>> {
-    let cli_config = Config::parse();
+#[derive(Debug, Clone, Subcommand)]
+enum Command {
+    /// Write a CDXJ index of the response records in WARC files, for use with `--lookup`.
+    Index {
+        /// WARC files, directories or glob patterns to index.
+        #[arg(required = true)]
+        files: Vec<String>,
 
-    let mut input: Box<dyn Read> = match cli_config.input_path.as_ref() {
-        "-" => Box::new(std::io::stdin()),
-        f => Box::new(File::open(f).expect("should have opened input file")),
-    };
+        /// Read all files below directories given as input.
+        #[arg(short = 'R', long)]
+        recursive: bool,
+
+        /// Where to write the index to.
+        #[arg(short = 'o', long = "output", default_value = "-")]
+        output_path: String,
+    },
+
+    /// Compare two versions of a document, reporting added, removed and moved elements and
+    /// changed attributes and text. Whitespace and the order of attributes don't count.
+    Diff {
+        /// The old version of the document.
+        old: String,
+
+        /// The new version of the document.
+        new: String,
+
+        /// Compare only the elements matching this CSS selector.
+        #[arg(default_value = ":root")]
+        selector: String,
+
+        /// Leave out the nodes matching any of these selectors, e.g. ads or timestamps.
+        #[arg(short, long)]
+        remove_nodes: Vec<String>,
+
+        /// Output each change as a line of JSON.
+        #[arg(long)]
+        json: bool,
+
+        /// When to highlight changes with colours: always, never or auto, which is when
+        /// writing to a terminal, unless the `NO_COLOR` environment variable is set.
+        #[arg(long, value_name = "WHEN", default_value_t = ColorChoice::Auto)]
+        color: ColorChoice,
+    },
+
+    /// Try out selectors on a document interactively, with a preview of their matches,
+    /// completion of the tags, classes and ids in it, and history. Type `:help` for commands.
+    Explore {
+        /// The document to explore.
+        file: String,
+    },
+
+    /// Suggest selectors for the elements containing some example text, preferring ids,
+    /// `itemprop` and meaningful class names over positions and generated class names.
+    Suggest {
+        /// Text to look for, e.g. a value to extract. Give several examples of the same kind
+        /// of value for selectors that match them all.
+        #[arg(short, long = "text", value_name = "TEXT", required = true)]
+        texts: Vec<String>,
+
+        /// The document to look in.
+        file: String,
 
-    let mut html = String::new();
-    input.read_to_string(&mut html)?;
+        /// How many selectors to suggest for each element.
+        #[arg(short = 'n', long, default_value_t = 5)]
+        limit: usize,
+    },
+
+    /// Group documents with the same or nearly the same text, writing the cluster of each one
+    /// as a line of JSON, along with its fingerprint.
+    Dedupe {
+        /// Files, directories or glob patterns of the documents.
+        #[arg(required = true)]
+        files: Vec<String>,
+
+        /// Read all files below directories given as input.
+        #[arg(short = 'R', long)]
+        recursive: bool,
+
+        /// Compare only the text of the elements matching this CSS selector.
+        #[arg(short, long, default_value = ":root")]
+        selector: String,
+
+        /// How similar documents have to be to end up in the same cluster, from 0 to 1 for
+        /// the same words.
+        #[arg(short, long, default_value_t = 0.8)]
+        threshold: f64,
+    },
+}
+
+fn main() -> ExitCode {
+    match run(Config::parse()) {
+        Ok(code) => code,
+        Err(e) => {
+            eprintln!("hq: {e}");
+            exit_code(&e)
+        }
+    }
+}
+
+/// The documented exit status for an error, see [`EXIT_STATUS`].
+fn exit_code(error: &HqError) -> ExitCode {
+    match error {
+        HqError::InvalidArgument(_) | HqError::Encoding(_) => ExitCode::from(2),
+        HqError::Io(_) | HqError::Input(_) | HqError::Json(_) | HqError::Fetch { .. } => {
+            ExitCode::from(3)
+        }
+        HqError::InvalidSelector { .. } => ExitCode::from(4),
+    }
+}
+
+fn run(mut cli_config: Config) -> Result<ExitCode, HqError> {
+    if let Some(command) = &cli_config.command {
+        match command {
+            Command::Index {
+                files,
+                recursive,
+                output_path,
+            } => write_index(files, *recursive, output_path)?,
+            Command::Diff {
+                old,
+                new,
+                selector,
+                remove_nodes,
+                json,
+                color,
+            } => write_diff(old, new, selector, remove_nodes, *json, *color)?,
+            Command::Explore { file } => {
+                let root = Query::compile(&HqConfig::default())?.parse(&read_document(file)?)?;
+                let history =
+                    std::env::var_os("HOME").map(|home| Path::new(&home).join(".hq_history"));
+                explore::run(root, history.as_deref(), &mut io::stdout().lock())?;
+            }
+            Command::Suggest { texts, file, limit } => {
+                let root = Query::compile(&HqConfig::default())?.parse(&read_document(file)?)?;
+                let mut output = io::stdout().lock();
+                suggest::write_suggestions(&root, texts, *limit, &mut output)?;
+                output.flush()?;
+            }
+            Command::Dedupe {
+                files,
+                recursive,
+                selector,
+                threshold,
+            } => write_clusters(files, *recursive, selector, *threshold)?,
+        }
+        return Ok(ExitCode::SUCCESS);
+    }
+
+    if let (Some(url), Some(index)) = (&cli_config.lookup, &cli_config.cdx) {
+        let entry = lookup_capture(url, index)?;
+        cli_config.warc = true;
+        cli_config.offset = Some(entry.offset);
+        cli_config.length = Some(entry.length);
+        cli_config.files = vec![entry.filename];
+    }
 
     let hq_config = HqConfig {
-        selector: cli_config.selector,
-        base: cli_config.base,
+        selector: cli_config.selector.clone(),
+        base: cli_config.base.clone(),
         detect_base: cli_config.detect_base,
+        url: cli_config.url.clone(),
+        absolutize: cli_config.absolutize,
         text_only: cli_config.text_only,
         ignore_whitespace: cli_config.ignore_whitespace,
         pretty_print: cli_config.pretty_print,
-        remove_nodes: cli_config.remove_nodes,
-        attributes: cli_config.attributes,
+        color: use_color(cli_config.color, &cli_config.output_path),
+        pretty_options: PrettyOptions {
+            indent: cli_config.indent,
+            tabs: cli_config.tabs,
+            wrap_attributes: cli_config.wrap_attributes,
+        },
+        remove_nodes: cli_config.remove_nodes.clone(),
+        attributes: cli_config.attributes.clone(),
         compact: cli_config.compact,
+        minify_options: MinifyOptions {
+            remove_comments: cli_config.remove_comments,
+            omit_end_tags: cli_config.omit_end_tags,
+            unquote_attributes: cli_config.unquote_attributes,
+            collapse_boolean_attributes: cli_config.collapse_boolean_attributes,
+        },
+        json_path: cli_config.json_path.clone(),
+        fix_encoding: cli_config.fix_encoding,
+        first: cli_config.first,
+        links: cli_config.links,
+        dedupe_links: cli_config.dedupe,
+        same_host: cli_config.same_host,
+        path_prefix: cli_config.path_prefix.clone(),
+        link_pattern: cli_config.link_regex.clone(),
+        normalize_urls: cli_config.normalize_urls,
+        strip_params: cli_config.strip_param.clone(),
+        surt: cli_config.surt,
+        fingerprint: cli_config.fingerprint,
+        outline: cli_config.outline,
+        outline_depth: cli_config.depth,
+        xml: cli_config.xml,
+    };
+
+    // fail early on invalid queries instead of once per input
+    let query = Query::compile(&hq_config)?;
+
+    let mut paths: Vec<String> = cli_config.input_paths.clone();
+    paths.extend(cli_config.files.iter().cloned());
+    if paths.is_empty() {
+        paths.push("-".to_string());
+    }
+
+    // failed inputs are reported as they happen, the exit status is that of the first one
+    let mut failures = 0;
+    let mut failure_code = None;
+    let mut fail = |name: &str, e: HqError| {
+        eprintln!("hq: {name}: {e}");
+        failures += 1;
+        failure_code.get_or_insert_with(|| exit_code(&e));
+    };
+
+    let mut inputs = Vec::new();
+    for path in &paths {
+        if let Err(e) = expand_input(path, cli_config.recursive, &mut inputs) {
+            fail(path, e);
+        }
+    }
+
+    let with_filename = cli_config.with_filename || (inputs.len() > 1 && !cli_config.no_filename);
+
+    let output: Box<dyn Write> = match cli_config.output_path.as_ref() {
+        "-" => Box::new(io::stdout().lock()),
+        f => Box::new(File::create(f).map_err(|e| with_path(f, e.into()))?),
     };
+    let mut output = CountingWriter {
+        inner: output,
+        written: 0,
+    };
+
+    if cli_config.stream || inputs.len() == 1 {
+        // one input at a time, writing output as soon as it's there
+        for path in &inputs {
+            let result = if with_filename {
+                let prefix = format!("{}:", display_name(path));
+                let mut prefixed = LinePrefixer::new(&mut output, prefix);
+                run_input(path, &cli_config, &query, &mut prefixed)
+                    .and_then(|()| Ok(prefixed.finish()?))
+            } else {
+                run_input(path, &cli_config, &query, &mut output)
+            };
 
-    let result = process_html(&html, &hq_config)?;
+            if let Err(e) = result {
+                fail(display_name(path), e);
+            }
+        }
+    } else {
+        for batch in inputs.chunks(BATCH_SIZE) {
+            let results: Vec<_> = batch
+                .par_iter()
+                .map(|path| {
+                    let mut buffer = Vec::new();
+                    process_input(path, &cli_config, &query, &mut buffer).map(|log| (buffer, log))
+                })
+                .collect();
 
-    match cli_config.output_path.as_ref() {
-        "-" => print!("{}", result),
-        f => std::fs::write(f, result).expect("should have written output file"),
+            for (path, result) in batch.iter().zip(results) {
+                match result {
+                    Ok((buffer, log)) => {
+                        eprint!("{log}");
+                        if with_filename {
+                            let prefix = format!("{}:", display_name(path));
+                            let mut prefixed = LinePrefixer::new(&mut output, prefix);
+                            prefixed.write_all(&buffer)?;
+                            prefixed.finish()?;
+                        } else {
+                            output.write_all(&buffer)?;
+                        }
+                    }
+                    Err(e) => fail(display_name(path), e),
+                }
+            }
+        }
     }
 
+    output.flush()?;
+
+    if let Some(code) = failure_code {
+        if failures > 1 {
+            eprintln!("hq: {failures} inputs could not be processed");
+        }
+        return Ok(code);
+    }
+
+    if cli_config.exit_status && output.written == 0 {
+        return Ok(ExitCode::from(1));
+    }
+
+    Ok(ExitCode::SUCCESS)
+}
+
+/// Whether to highlight output written to `output_path`, see `--color`.
+fn use_color(choice: ColorChoice, output_path: &str) -> bool {
+    match choice {
+        ColorChoice::Always => true,
+        ColorChoice::Never => false,
+        ColorChoice::Auto => {
+            output_path == "-"
+                && io::stdout().is_terminal()
+                && std::env::var_os("NO_COLOR").is_none_or(|value| value.is_empty())
+        }
+    }
+}
+
+/// Index all given WARC files into a single CDXJ file, sorted by SURT and timestamp.
+fn write_index(files: &[String], recursive: bool, output_path: &str) -> Result<(), HqError> {
+    let mut inputs = Vec::new();
+    for path in files {
+        expand_input(path, recursive, &mut inputs).map_err(|e| with_path(path, e))?;
+    }
+
+    let indexes: Vec<_> = inputs
+        .par_iter()
+        .map(|path| {
+            File::open(path)
+                .map_err(HqError::from)
+                .and_then(|file| Ok(cdx::index_warc(BufReader::new(file), path)?))
+                .map_err(|e| with_path(path, e))
+        })
+        .collect::<Result<_, _>>()?;
+
+    let mut entries: Vec<CdxEntry> = indexes.into_iter().flatten().collect();
+    entries.sort_by(|a, b| (&a.surt, &a.timestamp).cmp(&(&b.surt, &b.timestamp)));
+
+    let mut output: Box<dyn Write> = match output_path {
+        "-" => Box::new(io::stdout().lock()),
+        f => Box::new(BufWriter::new(
+            File::create(f).map_err(|e| with_path(f, e.into()))?,
+        )),
+    };
+    for entry in &entries {
+        writeln!(output, "{}", entry.to_cdxj())?;
+    }
+    output.flush()?;
+
     Ok(())
 }
+
+/// Compare the elements matching `selector` in two versions of a document, see `hq diff`.
+fn write_diff(
+    old: &str,
+    new: &str,
+    selector: &str,
+    remove_nodes: &[String],
+    json: bool,
+    color: ColorChoice,
+) -> Result<(), HqError> {
+    let config = HqConfig {
+        selector: selector.to_string(),
+        remove_nodes: remove_nodes.to_vec(),
+        ..Default::default()
+    };
+    let query = Query::compile(&config)?;
+    // the matches only hold on to their ancestors while their document is around
+    let parse = |path: &str| read_document(path).and_then(|html| query.parse(&html));
+    let (old_root, new_root) = (parse(old)?, parse(new)?);
+    let select = |root: &NodeRef| -> Vec<NodeRef> {
+        query.matches(root).map(|m| m.node().clone()).collect()
+    };
+
+    let changes = diff::diff(&select(&old_root), &select(&new_root));
+    let mut output = io::stdout().lock();
+    if json {
+        for change in &changes {
+            writeln!(output, "{}", change.to_json())?;
+        }
+    } else {
+        diff::write_changes(&changes, use_color(color, "-"), &mut output)?;
+    }
+    output.flush()?;
+
+    Ok(())
+}
+
+/// Fingerprint the text of the elements matching `selector` in each document and cluster the
+/// documents by similarity, see `hq dedupe`.
+fn write_clusters(
+    files: &[String],
+    recursive: bool,
+    selector: &str,
+    threshold: f64,
+) -> Result<(), HqError> {
+    if !(0.0..=1.0).contains(&threshold) {
+        return Err(HqError::InvalidArgument(format!(
+            "Invalid threshold {threshold}: must be between 0 and 1"
+        )));
+    }
+
+    let query = Query::compile(&HqConfig {
+        selector: selector.to_string(),
+        ..Default::default()
+    })?;
+
+    let mut inputs = Vec::new();
+    for path in files {
+        expand_input(path, recursive, &mut inputs).map_err(|e| with_path(path, e))?;
+    }
+
+    let fingerprints: Vec<Fingerprint> = inputs
+        .par_iter()
+        .map(|path| {
+            let root = query.parse(&read_document(path)?)?;
+            let text: Vec<String> = query
+                .matches(&root)
+                .map(|found| fingerprint::rendered_text(found.node()))
+                .collect();
+            Ok(Fingerprint::of_text(&text.join(" ")))
+        })
+        .collect::<Result<_, HqError>>()?;
+
+    let clusters = fingerprint::cluster(&fingerprints, threshold);
+    let mut output = io::stdout().lock();
+    for ((path, fingerprint), cluster) in inputs.iter().zip(&fingerprints).zip(clusters) {
+        let mut json = fingerprint.to_json();
+        json["file"] = json!(path);
+        json["cluster"] = json!(cluster);
+        writeln!(output, "{json}")?;
+    }
+    output.flush()?;
+
+    Ok(())
+}
+
+/// Find the newest capture of `url` in a CDXJ index. WARC filenames in the index that don't
+/// exist relative to the working directory are taken relative to the index's directory.
+fn lookup_capture(url: &str, index: &str) -> Result<CdxEntry, HqError> {
+    let contents = fs::read_to_string(index).map_err(|e| with_path(index, e.into()))?;
+    let mut entry = cdx::lookup(&contents, url)
+        .map_err(|e| with_path(index, e.into()))?
+        .ok_or_else(|| HqError::Input(format!("{index}: no capture of {url}")))?;
+
+    if !Path::new(&entry.filename).exists()
+        && let Some(dir) = Path::new(index).parent()
+    {
+        entry.filename = dir.join(&entry.filename).to_string_lossy().into_owned();
+    }
+
+    Ok(entry)
+}
+
+/// Prefix an error's message with the file it happened in, keeping its kind.
+fn with_path(path: &str, error: HqError) -> HqError {
+    match error {
+        HqError::Io(e) => HqError::Io(io::Error::new(e.kind(), format!("{path}: {e}"))),
+        HqError::Input(message) => HqError::Input(format!("{path}: {message}")),
+        other => other,
+    }
+}
+
+fn display_name(path: &str) -> &str {
+    match path {
+        "-" => "(standard input)",
+        path => path,
+    }
+}
+
+/// Resolve one input argument to files, expanding glob patterns and (if `recursive`) directories.
+fn expand_input(path: &str, recursive: bool, inputs: &mut Vec<String>) -> Result<(), HqError> {
+    if path == "-" {
+        inputs.push(path.to_string());
+        return Ok(());
+    }
+
+    let fs_path = Path::new(path);
+    if !fs_path.exists() && path.contains(['*', '?', '[']) {
+        let mut matched = false;
+        let entries = glob::glob(path)
+            .map_err(|e| HqError::InvalidArgument(format!("Invalid glob pattern: {e}")))?;
+        for entry in entries {
+            let entry = entry.map_err(io::Error::from)?;
+            expand_input(&entry.to_string_lossy(), recursive, inputs)?;
+            matched = true;
+        }
+        if !matched {
+            return Err(
+                io::Error::new(io::ErrorKind::NotFound, "no files match this pattern").into(),
+            );
+        }
+    } else if fs_path.is_dir() {
+        if !recursive {
+            return Err(io::Error::new(
+                io::ErrorKind::IsADirectory,
+                "is a directory (use --recursive to read the files in it)",
+            )
+            .into());
+        }
+        walk_dir(fs_path, inputs)?;
+    } else {
+        inputs.push(path.to_string());
+    }
+
+    Ok(())
+}
+
+fn walk_dir(dir: &Path, inputs: &mut Vec<String>) -> io::Result<()> {
+    let mut entries = fs::read_dir(dir)?.collect::<Result<Vec<_>, _>>()?;
+    entries.sort_by_key(|entry| entry.path());
+
+    for entry in entries {
+        let path = entry.path();
+        // don't follow symlinked directories, they might loop
+        if entry.file_type()?.is_dir() {
+            walk_dir(&path, inputs)?;
+        } else if path.is_file() {
+            inputs.push(path.to_string_lossy().into_owned());
+        }
+    }
+
+    Ok(())
+}
+
+fn open_input(path: &str) -> io::Result<Box<dyn BufRead>> {
+    Ok(match path {
+        "-" => Box::new(io::stdin().lock()),
+        f => Box::new(BufReader::new(File::open(f)?)),
+    })
+}
+
+/// Process a single input, streaming it if requested, and write its output to `output`.
+fn run_input(
+    path: &str,
+    cli_config: &Config,
+    query: &Query,
+    output: &mut dyn Write,
+) -> Result<(), HqError> {
+    if cli_config.stream {
+        stream_input(path, cli_config, query, output)
+    } else {
+        eprint!("{}", process_input(path, cli_config, query, output)?);
+        Ok(())
+    }
+}
+
+/// Read, decompress, decode and query a single input, writing its output to `output` and
+/// returning any log lines.
+fn process_input(
+    path: &str,
+    cli_config: &Config,
+    query: &Query,
+    output: &mut dyn Write,
+) -> Result<String, HqError> {
+    if cli_config.warc {
+        return process_warc_input(path, cli_config, query, output);
+    }
+
+    let mut input = open_input(path)?;
+
+    let mut bytes = Vec::new();
+    input.read_to_end(&mut bytes)?;
+
+    let mut log = String::new();
+    query_document(bytes, path, cli_config, query, output, &mut log)?;
+    Ok(log)
+}
+
+/// Read, decompress and decode a whole document.
+fn read_document(path: &str) -> Result<String, HqError> {
+    let mut bytes = Vec::new();
+    open_input(path)
+        .and_then(|mut input| input.read_to_end(&mut bytes))
+        .map_err(|e| with_path(path, e.into()))?;
+    let bytes = decompress::decompress(bytes, Compression::from_path(path))?;
+    Ok(charset::decode_html(&bytes, None, None)?.html)
+}
+
+/// Decompress, decode and query a whole document.
+fn query_document(
+    bytes: Vec<u8>,
+    path: &str,
+    cli_config: &Config,
+    query: &Query,
+    output: &mut dyn Write,
+    log: &mut String,
+) -> Result<(), HqError> {
+    let name = display_name(path);
+
+    let hint = Compression::from_path(path);
+    if cli_config.verbose
+        && let Some(format) = Compression::sniff(&bytes).or(hint)
+    {
+        log.push_str(&format!("hq: {name}: decompressing {format} input\n"));
+    }
+    let bytes = decompress::decompress(bytes, hint)?;
+
+    decode_and_query(&bytes, name, cli_config, query, output, log)
+}
+
+/// Decode and query a whole document that's already decompressed.
+fn decode_and_query(
+    bytes: &[u8],
+    name: &str,
+    cli_config: &Config,
+    query: &Query,
+    output: &mut dyn Write,
+    log: &mut String,
+) -> Result<(), HqError> {
+    let decoded = charset::decode_html(bytes, cli_config.encoding.as_deref(), None)?;
+    if cli_config.verbose {
+        log.push_str(&format!(
+            "hq: {name}: decoding input as {} ({})\n",
+            decoded.encoding.name(),
+            decoded.source
+        ));
+    }
+
+    query.run_into(&decoded.html, output)
+}
+
+/// Query a single input while reading it, writing matches to `output` as soon as they're
+/// complete. Queries that can't be streamed are run on the whole document instead.
+fn stream_input(
+    path: &str,
+    cli_config: &Config,
+    query: &Query,
+    output: &mut dyn Write,
+) -> Result<(), HqError> {
+    let name = display_name(path);
+
+    // check what doesn't depend on the input before reading any of it
+    if let Some(reason) = stream::unsupported_reason(query.config(), encoding_rs::UTF_8) {
+        eprintln!("hq: {name}: {reason}, using the tree engine instead");
+        eprint!("{}", process_input(path, cli_config, query, output)?);
+        return Ok(());
+    }
+
+    let hint = Compression::from_path(path);
+    let mut input = open_input(path)?;
+    if cli_config.verbose
+        && let Some(format) = Compression::sniff(input.fill_buf()?).or(hint)
+    {
+        eprintln!("hq: {name}: decompressing {format} input");
+    }
+    let mut input = decompress::reader(input, hint)?;
+
+    // the encoding has to be known upfront, so it's sniffed from the start of the input only
+    let decoded = charset::decode_html(input.fill_buf()?, cli_config.encoding.as_deref(), None)?;
+
+    if let Some(reason) = stream::unsupported_reason(query.config(), decoded.encoding) {
+        eprintln!("hq: {name}: {reason}, using the tree engine instead");
+        // what's read is decompressed already, so only decoding is left to do
+        let mut bytes = Vec::new();
+        input.read_to_end(&mut bytes)?;
+        let mut log = String::new();
+        decode_and_query(&bytes, name, cli_config, query, output, &mut log)?;
+        eprint!("{log}");
+        return Ok(());
+    }
+
+    if cli_config.verbose {
+        eprintln!(
+            "hq: {name}: decoding input as {} ({})",
+            decoded.encoding.name(),
+            decoded.source
+        );
+    }
+
+    match stream::stream_html(&mut input, decoded.encoding, query, output)? {
+        Streamed::Matches(matches) => {
+            if cli_config.verbose {
+                eprintln!("hq: {name}: {matches} matches streamed");
+            }
+        }
+        Streamed::NeedsTree { reason, mut read } => {
+            eprintln!("hq: {name}: {reason}, using the tree engine instead");
+            input.read_to_end(&mut read)?;
+            let mut log = String::new();
+            decode_and_query(&read, name, cli_config, query, output, &mut log)?;
+            eprint!("{log}");
+        }
+    }
+
+    Ok(())
+}
+
+/// Counts the bytes written through it, for `--exit-status`.
+struct CountingWriter<W> {
+    inner: W,
+    written: usize,
+}
+
+impl<W: Write> Write for CountingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.written += written;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Prefixes every line written through it, like `grep -H`.
+struct LinePrefixer<'a> {
+    inner: &'a mut dyn Write,
+    prefix: String,
+    at_line_start: bool,
+}
+
+impl<'a> LinePrefixer<'a> {
+    fn new(inner: &'a mut dyn Write, prefix: String) -> Self {
+        Self {
+            inner,
+            prefix,
+            at_line_start: true,
+        }
+    }
+
+    /// End the last line, if output didn't end with a newline.
+    fn finish(&mut self) -> io::Result<()> {
+        if !self.at_line_start {
+            self.inner.write_all(b"\n")?;
+            self.at_line_start = true;
+        }
+        Ok(())
+    }
+}
+
+impl Write for LinePrefixer<'_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        for line in buf.split_inclusive(|&b| b == b'\n') {
+            if self.at_line_start {
+                self.inner.write_all(self.prefix.as_bytes())?;
+            }
+            self.inner.write_all(line)?;
+            self.at_line_start = line.ends_with(b"\n");
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Run the query on every HTML response record of a WARC file, or on the single record at
+/// `--offset`. Output lines are prefixed with the record's target URI, date and ID.
+fn process_warc_input(
+    path: &str,
+    cli_config: &Config,
+    query: &Query,
+    output: &mut dyn Write,
+) -> Result<String, HqError> {
+    let single_record = cli_config.offset.zip(cli_config.length);
+
+    let reader: Box<dyn BufRead> = match single_record {
+        Some((offset, length)) => {
+            let mut file = File::open(path)?;
+            file.seek(SeekFrom::Start(offset))?;
+            let mut data = Vec::new();
+            file.take(length).read_to_end(&mut data)?;
+            Box::new(Cursor::new(decompress::decompress(data, None)?))
+        }
+        None => decompress::reader(open_input(path)?, Compression::from_path(path))?,
+    };
+
+    let name = display_name(path);
+    let mut log = String::new();
+    let (mut processed, mut skipped) = (0, 0);
+
+    for record in WarcReader::new(reader) {
+        let record = record?;
+
+        let prefix = format!(
+            "{}\t{}\t{}\t",
+            record.target_uri().unwrap_or_default(),
+            record.date().unwrap_or_default(),
+            record.record_id().unwrap_or_default(),
+        );
+        let mut prefixed = LinePrefixer::new(output, prefix);
+
+        match process_warc_record(&record, cli_config, query, &mut prefixed) {
+            Ok(true) => processed += 1,
+            Ok(false) => skipped += 1,
+            Err(e) => log.push_str(&format!(
+                "hq: {name}: record {}: {e}\n",
+                record.record_id().unwrap_or_default()
+            )),
+        }
+        prefixed.finish()?;
+
+        if single_record.is_some() {
+            break;
+        }
+    }
+
+    if cli_config.verbose {
+        log.push_str(&format!(
+            "hq: {name}: {processed} HTML response records processed, {skipped} other records skipped\n"
+        ));
+    }
+
+    Ok(log)
+}
+
+/// Query a single WARC record, returning `false` if it's not an HTML response.
+fn process_warc_record(
+    record: &WarcRecord,
+    cli_config: &Config,
+    query: &Query,
+    output: &mut dyn Write,
+) -> Result<bool, HqError> {
+    if !record.is_response() {
+        return Ok(false);
+    }
+
+    let response = record.http_response()?;
+    if !response.is_html() {
+        return Ok(false);
+    }
+
+    let decoded = response.decode_body(cli_config.encoding.as_deref())?;
+    match record.target_uri().and_then(|uri| Url::parse(uri).ok()) {
+        Some(url) => query.run_at_into(&decoded.html, &url, output)?,
+        None => query.run_into(&decoded.html, output)?,
+    }
+
+    Ok(true)
+}
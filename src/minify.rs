@@ -0,0 +1,507 @@
+use crate::pretty_print::{RAW_TEXT_ELEMENTS, attribute_name, escape, is_verbatim, is_void};
+use html5ever::serialize::AttrRef;
+use html5ever::serialize::Serialize;
+use html5ever::serialize::Serializer;
+use html5ever::serialize::TraversalScope;
+use html5ever::{LocalName, QualName, namespace_url, ns};
+use kuchikiki::NodeRef;
+use std::io;
+use std::io::Write;
+use std::str;
+
+/// Elements that are laid out as blocks, so the whitespace around them doesn't show. Anything
+/// else, including unknown elements, is taken to be inline and keeps a space around it.
+const BLOCK_ELEMENTS: [&str; 59] = [
+    "address",
+    "article",
+    "aside",
+    "base",
+    "blockquote",
+    "body",
+    "caption",
+    "col",
+    "colgroup",
+    "dd",
+    "details",
+    "dialog",
+    "dir",
+    "div",
+    "dl",
+    "dt",
+    "fieldset",
+    "figcaption",
+    "figure",
+    "footer",
+    "form",
+    "h1",
+    "h2",
+    "h3",
+    "h4",
+    "h5",
+    "h6",
+    "head",
+    "header",
+    "hgroup",
+    "hr",
+    "html",
+    "legend",
+    "li",
+    "link",
+    "main",
+    "menu",
+    "meta",
+    "nav",
+    "ol",
+    "optgroup",
+    "option",
+    "p",
+    "pre",
+    "search",
+    "section",
+    "source",
+    "summary",
+    "table",
+    "tbody",
+    "td",
+    "template",
+    "tfoot",
+    "th",
+    "thead",
+    "title",
+    "tr",
+    "track",
+    "ul",
+];
+
+/// Elements whose start tag closes an open `<p>`.
+const CLOSE_P_ELEMENTS: [&str; 30] = [
+    "address",
+    "article",
+    "aside",
+    "blockquote",
+    "details",
+    "dialog",
+    "div",
+    "dl",
+    "fieldset",
+    "figcaption",
+    "figure",
+    "footer",
+    "form",
+    "h1",
+    "h2",
+    "h3",
+    "h4",
+    "h5",
+    "h6",
+    "header",
+    "hgroup",
+    "hr",
+    "main",
+    "menu",
+    "nav",
+    "ol",
+    "p",
+    "pre",
+    "section",
+    "table",
+];
+
+/// Attributes that are only ever present or absent, whatever their value.
+const BOOLEAN_ATTRIBUTES: [&str; 26] = [
+    "allowfullscreen",
+    "async",
+    "autofocus",
+    "autoplay",
+    "checked",
+    "controls",
+    "default",
+    "defer",
+    "disabled",
+    "formnovalidate",
+    "hidden",
+    "inert",
+    "ismap",
+    "itemscope",
+    "loop",
+    "multiple",
+    "muted",
+    "nomodule",
+    "novalidate",
+    "open",
+    "playsinline",
+    "readonly",
+    "required",
+    "reversed",
+    "selected",
+    "shadowrootdelegatesfocus",
+];
+
+fn is_block(name: &str) -> bool {
+    BLOCK_ELEMENTS.contains(&name)
+}
+
+/// Whitespace as HTML sees it, which unlike [`char::is_whitespace`] leaves `&nbsp;` alone.
+fn is_html_whitespace(c: char) -> bool {
+    matches!(c, ' ' | '\t' | '\n' | '\x0c' | '\r')
+}
+
+/// Whether an attribute value can be written without quotes.
+fn is_unquotable(value: &str) -> bool {
+    !value.is_empty()
+        && !value.contains(|c: char| {
+            is_html_whitespace(c) || matches!(c, '"' | '\'' | '=' | '<' | '>' | '`')
+        })
+}
+
+/// Which optional steps to take when minifying, on top of collapsing whitespace.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct MinifyOptions {
+    /// Leave out comments.
+    pub remove_comments: bool,
+    /// Leave out end tags the parser puts back, like `</li>` before another `<li>`.
+    pub omit_end_tags: bool,
+    /// Write attribute values without quotes where that doesn't change them.
+    pub unquote_attributes: bool,
+    /// Write boolean attributes like `disabled="disabled"` as just their name.
+    pub collapse_boolean_attributes: bool,
+}
+
+/// What follows an end tag, to decide whether it can be left out.
+#[derive(Clone, Copy)]
+enum Next<'a> {
+    Start(&'a str),
+    ParentEnd(&'a str),
+    EndOfOutput,
+    Other,
+}
+
+/// Whether the end tag of `name` can be left out when followed by `next`, as per
+/// <https://html.spec.whatwg.org/multipage/syntax.html#optional-tags>.
+fn is_optional_end_tag(name: &str, next: Next) -> bool {
+    use Next::*;
+    match (name, next) {
+        ("html", EndOfOutput) => true,
+        ("body", ParentEnd(_)) => true,
+        ("head", Start(_)) => true,
+        ("li", Start("li") | ParentEnd(_)) => true,
+        ("dt", Start("dt" | "dd")) => true,
+        ("dd", Start("dt" | "dd") | ParentEnd(_)) => true,
+        ("p", Start(next)) => CLOSE_P_ELEMENTS.contains(&next),
+        ("p", ParentEnd(parent)) => !matches!(
+            parent,
+            "a" | "audio" | "del" | "ins" | "map" | "noscript" | "video"
+        ),
+        ("rt" | "rp", Start("rt" | "rp") | ParentEnd(_)) => true,
+        ("optgroup", Start("optgroup") | ParentEnd(_)) => true,
+        ("option", Start("option" | "optgroup") | ParentEnd(_)) => true,
+        ("thead", Start("tbody" | "tfoot")) => true,
+        ("tbody", Start("tbody" | "tfoot") | ParentEnd(_)) => true,
+        ("tfoot", ParentEnd(_)) => true,
+        ("tr", Start("tr") | ParentEnd(_)) => true,
+        ("td" | "th", Start("td" | "th") | ParentEnd(_)) => true,
+        _ => false,
+    }
+}
+
+struct Minify<W: Write> {
+    writer: W,
+    options: MinifyOptions,
+    /// How deep we are in elements whose contents are written exactly as they are.
+    verbatim: usize,
+    /// Whether the innermost verbatim element holds unescaped text.
+    raw_text: bool,
+    /// Whether a leading newline in a `<pre>` has to be written twice to survive parsing.
+    leading_newline_dropped: bool,
+    /// Whitespace was collapsed and is written as a space if more inline content follows.
+    pending_space: bool,
+    /// Whether we're at the start or end of a block, where whitespace doesn't show.
+    at_block_boundary: bool,
+    /// An end tag that's only written once it's known whether it can be left out.
+    pending_end_tag: Option<LocalName>,
+}
+
+impl<W: Write> Minify<W> {
+    fn new(writer: W, options: MinifyOptions) -> Self {
+        Minify {
+            writer,
+            options,
+            verbatim: 0,
+            raw_text: false,
+            leading_newline_dropped: false,
+            pending_space: false,
+            at_block_boundary: true,
+            pending_end_tag: None,
+        }
+    }
+
+    fn write(&mut self, text: &str) -> io::Result<()> {
+        self.writer.write_all(text.as_bytes())
+    }
+
+    /// Write the pending end tag, unless `next` makes it optional and it can be left out.
+    fn flush_end_tag(&mut self, next: Next) -> io::Result<()> {
+        if let Some(name) = self.pending_end_tag.take()
+            && !(self.options.omit_end_tags && is_optional_end_tag(&name, next))
+        {
+            self.write(&format!("</{}>", &*name))?;
+        }
+        Ok(())
+    }
+
+    /// Write a space for collapsed whitespace before inline content, or drop it before a block.
+    fn flush_space(&mut self, block: bool) -> io::Result<()> {
+        if std::mem::take(&mut self.pending_space) && !block {
+            self.flush_end_tag(Next::Other)?;
+            self.write(" ")?;
+        }
+        Ok(())
+    }
+
+    fn write_start_tag<'a>(
+        &mut self,
+        name: &str,
+        attrs: impl Iterator<Item = AttrRef<'a>>,
+    ) -> io::Result<()> {
+        let mut tag = format!("<{name}");
+        for (name, value) in attrs {
+            tag.push(' ');
+            tag.push_str(&attribute_name(name));
+
+            // the contents of verbatim elements are left as they are, tags included
+            if self.verbatim > 0 {
+                tag.push_str(&format!("=\"{}\"", escape(value, true)));
+            } else if self.options.collapse_boolean_attributes
+                && name.ns == ns!()
+                && BOOLEAN_ATTRIBUTES.contains(&&*name.local)
+                && (value.is_empty() || value.eq_ignore_ascii_case(&name.local))
+            {
+                continue;
+            } else if self.options.unquote_attributes && is_unquotable(value) {
+                tag.push_str(&format!("={}", escape(value, true)));
+            } else {
+                tag.push_str(&format!("=\"{}\"", escape(value, true)));
+            }
+        }
+        tag.push('>');
+        self.write(&tag)
+    }
+
+    /// Write whatever was held back once the whole node is serialized.
+    fn finish(&mut self) -> io::Result<()> {
+        self.flush_end_tag(Next::EndOfOutput)
+    }
+}
+
+impl<W: Write> Serializer for Minify<W> {
+    fn start_elem<'a, AttrIter>(&mut self, name: QualName, attrs: AttrIter) -> io::Result<()>
+    where
+        AttrIter: Iterator<Item = AttrRef<'a>>,
+    {
+        let local = &*name.local;
+
+        if self.verbatim > 0 {
+            if !is_void(local) {
+                self.verbatim += 1;
+                self.raw_text = RAW_TEXT_ELEMENTS.contains(&local);
+            }
+            self.leading_newline_dropped = false;
+            return self.write_start_tag(local, attrs);
+        }
+
+        let block = is_block(local);
+        self.flush_space(block)?;
+        self.flush_end_tag(Next::Start(local))?;
+        self.write_start_tag(local, attrs)?;
+        self.at_block_boundary = block;
+
+        if is_verbatim(local) {
+            self.verbatim = 1;
+            self.raw_text = RAW_TEXT_ELEMENTS.contains(&local);
+            self.leading_newline_dropped = matches!(local, "pre" | "textarea" | "listing");
+        }
+        Ok(())
+    }
+
+    fn end_elem(&mut self, name: QualName) -> io::Result<()> {
+        let local = &*name.local;
+        if is_void(local) {
+            return Ok(());
+        }
+
+        if self.verbatim > 0 {
+            self.verbatim -= 1;
+            self.raw_text = false;
+            self.leading_newline_dropped = false;
+            self.write(&format!("</{local}>"))?;
+            if self.verbatim == 0 {
+                self.at_block_boundary = is_block(local);
+            }
+            return Ok(());
+        }
+
+        let block = is_block(local);
+        self.flush_space(block)?;
+        self.flush_end_tag(Next::ParentEnd(local))?;
+        self.pending_end_tag = Some(name.local);
+        self.at_block_boundary = block;
+        Ok(())
+    }
+
+    fn write_text(&mut self, text: &str) -> io::Result<()> {
+        if self.verbatim > 0 {
+            if std::mem::take(&mut self.leading_newline_dropped) && text.starts_with('\n') {
+                self.write("\n")?;
+            }
+            return if self.raw_text {
+                self.write(text)
+            } else {
+                self.write(&escape(text, false))
+            };
+        }
+
+        let words: Vec<&str> = text
+            .split(is_html_whitespace)
+            .filter(|word| !word.is_empty())
+            .collect();
+        let leading_space = text.starts_with(is_html_whitespace) && !self.at_block_boundary;
+        if words.is_empty() {
+            self.pending_space |= leading_space;
+            return Ok(());
+        }
+
+        self.pending_space |= leading_space;
+        self.flush_space(false)?;
+        self.flush_end_tag(Next::Other)?;
+        self.write(&escape(&words.join(" "), false))?;
+
+        self.pending_space = text.ends_with(is_html_whitespace);
+        self.at_block_boundary = false;
+        Ok(())
+    }
+
+    fn write_comment(&mut self, text: &str) -> io::Result<()> {
+        if self.options.remove_comments {
+            return Ok(());
+        }
+        self.leading_newline_dropped = false;
+        self.flush_end_tag(Next::Other)?;
+        self.write(&format!("<!--{text}-->"))
+    }
+
+    fn write_doctype(&mut self, name: &str) -> io::Result<()> {
+        self.flush_end_tag(Next::Other)?;
+        self.write(&format!("<!DOCTYPE {name}>"))
+    }
+
+    fn write_processing_instruction(&mut self, target: &str, data: &str) -> io::Result<()> {
+        self.leading_newline_dropped = false;
+        self.flush_end_tag(Next::Other)?;
+        self.write(&format!("<?{target} {data}>"))
+    }
+}
+
+/// Serialize `node` and what's in it minified: whitespace is collapsed, and dropped where it
+/// doesn't show, next to blocks. Preformatted and raw text elements are written as they are.
+pub fn write_minified(
+    node: &NodeRef,
+    options: MinifyOptions,
+    output: &mut dyn Write,
+) -> io::Result<()> {
+    let mut minify = Minify::new(output, options);
+    Serialize::serialize(node, &mut minify, TraversalScope::IncludeNode)?;
+    minify.finish()
+}
+
+pub fn minify(node: &NodeRef, options: MinifyOptions) -> String {
+    let mut content: Vec<u8> = Vec::new();
+    write_minified(node, options, &mut content).unwrap();
+    str::from_utf8(content.as_ref()).unwrap().to_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use kuchikiki::traits::TendrilSink;
+
+    fn minify_body(html: &str, options: MinifyOptions) -> String {
+        let document = kuchikiki::parse_html().one(html);
+        minify(
+            &document.select_first("body").unwrap().as_node().clone(),
+            options,
+        )
+    }
+
+    #[test]
+    fn test_whitespace() {
+        let html = "<body>\n  <div>\n    <p>Some   <b>bold</b> <i>and</i>\n italic  text </p>\n  </div>\n  <span> a </span>  <custom-element>b</custom-element>\n</body>";
+        assert_eq!(
+            minify_body(html, MinifyOptions::default()),
+            "<body><div><p>Some <b>bold</b> <i>and</i> italic text</p></div><span> a </span> <custom-element>b</custom-element></body>"
+        );
+
+        // non-breaking spaces aren't whitespace, and `>` in text stays escaped
+        assert_eq!(
+            minify_body("<p> a&nbsp; &gt; b </p>", MinifyOptions::default()),
+            "<body><p>a&nbsp; &gt; b</p></body>"
+        );
+    }
+
+    #[test]
+    fn test_verbatim_elements() {
+        let html = "<pre>\n\n  keep  <b> this </b>\n</pre>  <script> if (a > b) { }  </script>  <textarea>  x  </textarea>";
+        assert_eq!(
+            minify_body(html, MinifyOptions::default()),
+            "<body><pre>\n\n  keep  <b> this </b>\n</pre><script> if (a > b) { }  </script> <textarea>  x  </textarea></body>"
+        );
+    }
+
+    #[test]
+    fn test_options() {
+        let html = r#"<!-- c --><ul><li>One</li> <li>Two <!-- c --></li></ul><p>Para</p><div>x</div><p>Last</p>
+            <table><tbody><tr><td>1</td><td>2</td></tr></tbody></table>
+            <input type="checkbox" checked="checked" disabled="" value="a b" data-x="">"#;
+        let options = MinifyOptions {
+            remove_comments: true,
+            omit_end_tags: true,
+            unquote_attributes: true,
+            collapse_boolean_attributes: true,
+        };
+        assert_eq!(
+            minify_body(html, options),
+            r#"<body><ul><li>One<li>Two</ul><p>Para<div>x</div><p>Last<table><tbody><tr><td>1<td>2</table><input type=checkbox checked disabled value="a b" data-x=""></body>"#
+        );
+
+        // a `<p>` in an `<a>` keeps its end tag, and so does anything followed by text
+        let options = MinifyOptions {
+            omit_end_tags: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            minify_body("<a><p>x</p></a><ul><li>a</li>b</ul>", options),
+            "<body><a><p>x</p></a><ul><li>a</li>b</ul></body>"
+        );
+        assert_eq!(
+            minify_body("<ul><li>a</li><!--c--><li>b</li></ul>", options),
+            "<body><ul><li>a</li><!--c--><li>b</ul></body>"
+        );
+    }
+
+    #[test]
+    fn test_document() {
+        let document = kuchikiki::parse_html()
+            .one("<!DOCTYPE html>\n<html>\n<head>\n<title> Test </title>\n</head>\n<body>\n<p>Hi</p>\n</body>\n</html>\n");
+        assert_eq!(
+            minify(&document, MinifyOptions::default()),
+            "<!DOCTYPE html><html><head><title>Test</title></head><body><p>Hi</p></body></html>"
+        );
+        let options = MinifyOptions {
+            omit_end_tags: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            minify(&document, options),
+            "<!DOCTYPE html><html><head><title>Test</title><body><p>Hi"
+        );
+    }
+}
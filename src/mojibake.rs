@@ -0,0 +1,140 @@
+/// Characters Windows-1252 assigns to bytes 0x80..=0x9F. Unassigned bytes map to the
+/// C1 control at the same code point, the way browsers and most sloppy decoders do.
+const WINDOWS_1252_HIGH: [char; 32] = [
+    '€', '\u{81}', '‚', 'ƒ', '„', '…', '†', '‡', 'ˆ', '‰', 'Š', '‹', 'Œ', '\u{8D}', 'Ž', '\u{8F}',
+    '\u{90}', '‘', '’', '“', '”', '•', '–', '—', '˜', '™', 'š', '›', 'œ', '\u{9D}', 'ž', 'Ÿ',
+];
+
+/// How often to repeat the repair, for text that was double (or triple) encoded.
+const MAX_PASSES: usize = 3;
+
+/// Map a char back to the byte it came from, if UTF-8 input was wrongly decoded as
+/// Latin-1 or Windows-1252.
+fn to_single_byte(c: char) -> Option<u8> {
+    if let Some(pos) = WINDOWS_1252_HIGH.iter().position(|&w| w == c) {
+        return Some(0x80 + pos as u8);
+    }
+    u8::try_from(u32::from(c)).ok()
+}
+
+/// How many bytes the UTF-8 sequence starting with `lead` should have, if it's a valid lead byte.
+fn sequence_len(lead: u8) -> Option<usize> {
+    match lead {
+        0xC2..=0xDF => Some(2),
+        0xE0..=0xEF => Some(3),
+        0xF0..=0xF4 => Some(4),
+        _ => None,
+    }
+}
+
+/// Repair mojibake: UTF-8 text that was decoded as Latin-1 or Windows-1252 somewhere along
+/// the way, such as `â€™` for `’` or `Ã©` for `é`.
+///
+/// Only the runs of characters that form a valid UTF-8 sequence when mapped back to bytes
+/// are replaced, so correctly encoded text (including text mixing real Unicode with
+/// mojibake) is left untouched. Double-encoded text is repaired by repeating the process.
+pub fn fix_encoding(input: &str) -> String {
+    let mut text = input.to_string();
+
+    for _ in 0..MAX_PASSES {
+        let fixed = fix_pass(&text);
+        if fixed == text {
+            break;
+        }
+        text = fixed;
+    }
+
+    text
+}
+
+fn fix_pass(input: &str) -> String {
+    let chars: Vec<char> = input.chars().collect();
+    let mut result = String::with_capacity(input.len());
+    let mut i = 0;
+
+    while i < chars.len() {
+        match decode_sequence(&chars[i..]) {
+            Some((decoded, consumed)) => {
+                result.push(decoded);
+                i += consumed;
+            }
+            None => {
+                result.push(chars[i]);
+                i += 1;
+            }
+        }
+    }
+
+    result
+}
+
+/// Try to read one mojibake sequence at the start of `chars`, returning the repaired
+/// char and how many chars it replaces.
+fn decode_sequence(chars: &[char]) -> Option<(char, usize)> {
+    let lead = to_single_byte(*chars.first()?)?;
+    let len = sequence_len(lead)?;
+
+    let mut bytes = vec![lead];
+    for &c in chars.get(1..len)? {
+        let byte = to_single_byte(c)?;
+        if !(0x80..=0xBF).contains(&byte) {
+            return None;
+        }
+        bytes.push(byte);
+    }
+
+    let decoded = std::str::from_utf8(&bytes).ok()?.chars().next()?;
+
+    // a control character is far more likely to be a real "Â" followed by punctuation
+    if decoded.is_control() {
+        return None;
+    }
+
+    Some((decoded, len))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_latin1_mojibake() {
+        assert_eq!(fix_encoding("FranÃ§ais"), "Français");
+        assert_eq!(fix_encoding("MÃ¼nchen"), "München");
+    }
+
+    #[test]
+    fn test_windows_1252_mojibake() {
+        assert_eq!(fix_encoding("Weâ€™re hiring"), "We’re hiring");
+        assert_eq!(fix_encoding("Rustâ„¢ â€“ Engineer"), "Rust™ – Engineer");
+        assert_eq!(fix_encoding("50 000 â‚¬"), "50 000 €");
+    }
+
+    #[test]
+    fn test_mixed_with_real_unicode() {
+        assert_eq!(
+            fix_encoding("東京 – Senior Engineer, CafÃ© â€œBarâ€\u{9d}"),
+            "東京 – Senior Engineer, Café “Bar”"
+        );
+    }
+
+    #[test]
+    fn test_double_encoding() {
+        assert_eq!(fix_encoding("Ã¢â‚¬â„¢"), "’");
+        assert_eq!(fix_encoding("FranÃƒÂ§ais"), "Français");
+    }
+
+    #[test]
+    fn test_leaves_correct_text_untouched() {
+        for text in [
+            "Français",
+            "naïve café",
+            "Straße in Köln",
+            "Ångström — “quoted”",
+            "© 2024 ACME™",
+            "plain ascii",
+        ] {
+            assert_eq!(fix_encoding(text), text);
+        }
+    }
+}
@@ -0,0 +1,257 @@
+use url::Url;
+
+/// Query parameters that only track where a visitor came from, or identify their session.
+/// Entries ending in `*` match any parameter starting with the rest.
+pub const TRACKING_PARAMS: [&str; 33] = [
+    "utm_*",
+    "gclid",
+    "gclsrc",
+    "dclid",
+    "gbraid",
+    "wbraid",
+    "fbclid",
+    "msclkid",
+    "yclid",
+    "twclid",
+    "ttclid",
+    "li_fat_id",
+    "igshid",
+    "mc_cid",
+    "mc_eid",
+    "mkt_tok",
+    "_ga",
+    "_gl",
+    "_hsenc",
+    "_hsmi",
+    "hsctatracking",
+    "oly_anon_id",
+    "oly_enc_id",
+    "vero_id",
+    "s_cid",
+    "jsessionid",
+    "phpsessid",
+    "aspsessionid",
+    "sessionid",
+    "session_id",
+    "sid",
+    "cfid",
+    "cftoken",
+];
+
+/// Canonicalizes URLs so that the same page gets the same URL, whichever way it was linked to.
+#[derive(Debug, Clone, Default)]
+pub struct Normalizer {
+    /// Parameters to strip on top of [`TRACKING_PARAMS`], lowercase.
+    strip_params: Vec<String>,
+}
+
+impl Normalizer {
+    /// A normalizer that also strips the given query parameters, which may end in `*` like
+    /// those in [`TRACKING_PARAMS`].
+    pub fn new(strip_params: &[String]) -> Self {
+        Self {
+            strip_params: strip_params
+                .iter()
+                .map(|param| param.to_ascii_lowercase())
+                .collect(),
+        }
+    }
+
+    /// Lowercase the host, drop default ports, the fragment, session IDs in the path and a
+    /// trailing slash, strip tracking parameters and sort the remaining ones by name.
+    pub fn normalize(&self, url: &mut Url) {
+        // mailto: and the like
+        if url.cannot_be_a_base() {
+            return;
+        }
+
+        url.set_fragment(None);
+
+        // parsing already does this for http(s) and other special schemes
+        if let Some(host) = url.host_str()
+            && host.bytes().any(|b| b.is_ascii_uppercase())
+        {
+            let host = host.to_ascii_lowercase();
+            let _ = url.set_host(Some(&host));
+        }
+        if url.port().is_some() && url.port() == default_port(url.scheme()) {
+            let _ = url.set_port(None);
+        }
+
+        let path = normalize_path(url.path());
+        url.set_path(&path);
+
+        if let Some(query) = url.query() {
+            let mut params: Vec<&str> = query
+                .split('&')
+                .filter(|param| !param.is_empty() && !self.is_stripped(&param_name(param)))
+                .collect();
+            // stable, so repeated parameters keep their order
+            params.sort_by_cached_key(|param| param_name(param).to_string());
+
+            let query = params.join("&");
+            url.set_query((!query.is_empty()).then_some(&query));
+        }
+    }
+
+    /// Normalize a URL given as text, or return it as is if it isn't an absolute URL.
+    pub fn normalize_str(&self, url: &str) -> String {
+        match Url::parse(url) {
+            Ok(mut parsed) => {
+                self.normalize(&mut parsed);
+                parsed.to_string()
+            }
+            Err(_) => url.to_string(),
+        }
+    }
+
+    /// Whether the query parameter `name` is stripped.
+    pub fn is_stripped(&self, name: &str) -> bool {
+        let name = name.to_ascii_lowercase();
+        TRACKING_PARAMS
+            .iter()
+            .copied()
+            .chain(self.strip_params.iter().map(String::as_str))
+            .any(|param| match param.strip_suffix('*') {
+                Some(prefix) => name.starts_with(prefix),
+                None => name == param,
+            })
+    }
+}
+
+fn default_port(scheme: &str) -> Option<u16> {
+    match scheme {
+        "http" | "ws" => Some(80),
+        "https" | "wss" => Some(443),
+        "ftp" => Some(21),
+        _ => None,
+    }
+}
+
+/// The decoded name of a `name=value` query parameter.
+fn param_name(param: &str) -> std::borrow::Cow<'_, str> {
+    let name = param.split_once('=').map_or(param, |(name, _)| name);
+    url::form_urlencoded::parse(name.as_bytes())
+        .next()
+        .map_or(std::borrow::Cow::Borrowed(name), |(name, _)| name)
+}
+
+/// Remove `;jsessionid=...` style session IDs from path segments and a trailing slash.
+fn normalize_path(path: &str) -> String {
+    let mut path = path
+        .split('/')
+        .map(
+            |segment| match segment.to_ascii_lowercase().find(";jsessionid=") {
+                Some(start) => &segment[..start],
+                None => segment,
+            },
+        )
+        .collect::<Vec<_>>()
+        .join("/");
+
+    while path.len() > 1 && path.ends_with('/') {
+        path.pop();
+    }
+    path
+}
+
+/// The Sort-friendly URI Reordering Transform of `url`, e.g. `com,example)/jobs?a=1&b=2`
+/// for `https://www.Example.com/jobs?b=2&a=1#top`.
+pub fn surt(url: &str) -> Option<String> {
+    let url = Url::parse(url).ok()?;
+    let host = url.host_str()?.to_ascii_lowercase();
+
+    let host = host.trim_end_matches('.');
+    let host = match host.split_once('.') {
+        Some((first, rest))
+            if first.starts_with("www") && first[3..].chars().all(|c| c.is_ascii_digit()) =>
+        {
+            rest
+        }
+        _ => host,
+    };
+
+    let mut surt: String = host.split('.').rev().collect::<Vec<_>>().join(",");
+    if let Some(port) = url.port() {
+        surt.push_str(&format!(":{port}"));
+    }
+    surt.push(')');
+    surt.push_str(&url.path().to_ascii_lowercase());
+
+    if let Some(query) = url.query() {
+        let mut params: Vec<&str> = query.split('&').filter(|p| !p.is_empty()).collect();
+        params.sort_unstable();
+        if !params.is_empty() {
+            surt.push('?');
+            surt.push_str(&params.join("&").to_ascii_lowercase());
+        }
+    }
+
+    Some(surt)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn normalize(url: &str, strip_params: &[&str]) -> String {
+        let strip_params: Vec<String> = strip_params.iter().map(|p| p.to_string()).collect();
+        Normalizer::new(&strip_params).normalize_str(url)
+    }
+
+    #[test]
+    fn test_normalize() {
+        assert_eq!(
+            normalize(
+                "HTTPS://Jobs.Example.COM:443/Senior-Engineer/?utm_source=x&b=2&UTM_Medium=y&a=1&gclid=z&b=1#apply",
+                &[]
+            ),
+            "https://jobs.example.com/Senior-Engineer?a=1&b=2&b=1"
+        );
+        assert_eq!(
+            normalize("http://example.com:80/jobs;jsessionid=ABC123/42?", &[]),
+            "http://example.com/jobs/42"
+        );
+        assert_eq!(
+            normalize(
+                "https://example.com/?ref=feed&src=home&id=1",
+                &["ref", "s*"]
+            ),
+            "https://example.com/?id=1"
+        );
+        assert_eq!(
+            normalize("https://example.com/?fbclid=1", &[]),
+            "https://example.com/"
+        );
+        assert_eq!(
+            normalize("mailto:Jobs@Example.com", &[]),
+            "mailto:Jobs@Example.com"
+        );
+        assert_eq!(
+            normalize("/relative?utm_source=x", &[]),
+            "/relative?utm_source=x"
+        );
+    }
+
+    #[test]
+    fn test_is_stripped() {
+        let normalizer = Normalizer::new(&["Campaign".to_string()]);
+        assert!(normalizer.is_stripped("utm_campaign"));
+        assert!(normalizer.is_stripped("PHPSESSID"));
+        assert!(normalizer.is_stripped("campaign"));
+        assert!(!normalizer.is_stripped("id"));
+    }
+
+    #[test]
+    fn test_surt() {
+        assert_eq!(
+            surt("https://www.Example.com/Jobs?b=2&a=1#top").as_deref(),
+            Some("com,example)/jobs?a=1&b=2")
+        );
+        assert_eq!(
+            surt("http://jobs.example.co.uk:8080/").as_deref(),
+            Some("uk,co,example,jobs:8080)/")
+        );
+        assert_eq!(surt("not a url"), None);
+    }
+}
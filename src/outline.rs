@@ -0,0 +1,259 @@
+use crate::pretty_print::{Palette, RAW_TEXT_ELEMENTS};
+use anstyle::Style;
+use kuchikiki::iter::NodeIterator;
+use kuchikiki::{ElementData, NodeData, NodeRef};
+use std::io::{self, Write};
+
+/// Attributes shown along with the id and classes.
+const KEY_ATTRIBUTES: [&str; 15] = [
+    "href",
+    "src",
+    "action",
+    "name",
+    "type",
+    "role",
+    "itemprop",
+    "itemtype",
+    "for",
+    "rel",
+    "lang",
+    "charset",
+    "property",
+    "aria-label",
+    "data-testid",
+];
+
+/// How many characters of text snippets and attribute values are shown.
+const SNIPPET_LENGTH: usize = 40;
+const VALUE_LENGTH: usize = 30;
+
+/// How many siblings of the same kind in a row are shown once, as a run. Fewer are all shown.
+const MIN_RUN_LENGTH: usize = 3;
+
+/// `text` cut down to `max` characters, with an ellipsis if anything was cut.
+fn truncate(text: &str, max: usize) -> String {
+    if text.chars().count() <= max {
+        return text.to_string();
+    }
+    let mut truncated: String = text.chars().take(max - 1).collect();
+    truncated.push('…');
+    truncated
+}
+
+/// The text directly in an element, not in its children, with whitespace collapsed.
+fn own_text(node: &NodeRef, element: &ElementData) -> String {
+    if RAW_TEXT_ELEMENTS.contains(&&*element.name.local) {
+        return String::new();
+    }
+    node.children()
+        .text_nodes()
+        .map(|text| text.borrow().clone())
+        .collect::<Vec<_>>()
+        .join(" ")
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Siblings that are shown as one line when they follow each other: the same tag and classes.
+fn same_kind(a: &ElementData, b: &ElementData) -> bool {
+    let attributes = (a.attributes.borrow(), b.attributes.borrow());
+    a.name == b.name && attributes.0.get("class") == attributes.1.get("class")
+}
+
+struct Outline<'a> {
+    output: &'a mut dyn Write,
+    /// How many levels below the outlined node to show, all of them if `None`.
+    depth: Option<usize>,
+    palette: Option<Palette>,
+}
+
+impl Outline<'_> {
+    fn paint(&mut self, text: &str, style: fn(&Palette) -> Style) -> io::Result<()> {
+        match self.palette {
+            Some(palette) => {
+                let style = style(&palette);
+                write!(
+                    self.output,
+                    "{}{text}{}",
+                    style.render(),
+                    style.render_reset()
+                )
+            }
+            None => write!(self.output, "{text}"),
+        }
+    }
+
+    fn write_children(&mut self, node: &NodeRef, level: usize) -> io::Result<()> {
+        let children: Vec<_> = node.children().elements().collect();
+        let mut i = 0;
+        while i < children.len() {
+            let run = children[i..]
+                .iter()
+                .take_while(|sibling| same_kind(&children[i], sibling))
+                .count();
+            let count = if run >= MIN_RUN_LENGTH { run } else { 1 };
+            self.write_element(children[i].as_node(), &children[i], level, count)?;
+            i += count;
+        }
+        Ok(())
+    }
+
+    /// Write the line of an element that stands for `count` siblings, then its children.
+    fn write_element(
+        &mut self,
+        node: &NodeRef,
+        element: &ElementData,
+        level: usize,
+        count: usize,
+    ) -> io::Result<()> {
+        write!(self.output, "{}", "  ".repeat(level))?;
+        self.paint(&element.name.local, |p| p.tag)?;
+
+        // the ids and other attributes of a run differ from one sibling to the next
+        let attributes = element.attributes.borrow();
+        if count == 1
+            && let Some(id) = attributes.get("id")
+        {
+            self.paint(&format!("#{id}"), |p| p.attribute)?;
+        }
+        for class in attributes.get("class").unwrap_or("").split_whitespace() {
+            self.paint(&format!(".{class}"), |p| p.attribute)?;
+        }
+        if count > 1 {
+            return self.write_run(node, level, count);
+        }
+
+        for name in KEY_ATTRIBUTES {
+            if let Some(value) = attributes.get(name) {
+                write!(self.output, "[")?;
+                self.paint(name, |p| p.attribute)?;
+                write!(self.output, "=")?;
+                let value = format!("\"{}\"", truncate(value, VALUE_LENGTH));
+                self.paint(&value, |p| p.value)?;
+                write!(self.output, "]")?;
+            }
+        }
+        drop(attributes);
+
+        let text = own_text(node, element);
+        if !text.is_empty() {
+            write!(self.output, " ")?;
+            self.paint(&format!("\"{}\"", truncate(&text, SNIPPET_LENGTH)), |p| {
+                p.text
+            })?;
+        }
+        self.write_end_of_line(node, level)
+    }
+
+    /// Finish the line of a run of siblings, with the outline of the first one below it.
+    fn write_run(&mut self, node: &NodeRef, level: usize, count: usize) -> io::Result<()> {
+        write!(self.output, " ")?;
+        self.paint(&format!("× {count}"), |p| p.comment)?;
+        self.write_end_of_line(node, level)
+    }
+
+    /// End the line of an element, with an ellipsis if its children are too deep to show.
+    fn write_end_of_line(&mut self, node: &NodeRef, level: usize) -> io::Result<()> {
+        if self.depth.is_some_and(|depth| level >= depth) {
+            if node.children().elements().next().is_some() {
+                write!(self.output, " ")?;
+                self.paint("…", |p| p.comment)?;
+            }
+            return writeln!(self.output);
+        }
+        writeln!(self.output)?;
+        self.write_children(node, level + 1)
+    }
+}
+
+/// Write an outline of `node` and the elements in it, `depth` levels deep if given, and
+/// highlighted with `palette` if given, to find one's way around an unfamiliar page.
+///
+/// Each element is a line, indented by depth, with its tag name, id, classes and the attributes
+/// that say most about what it is, followed by a snippet of its own text. Runs of siblings
+/// with the same tag and classes, like the items of a long list, are shown once.
+pub fn write_outline(
+    node: &NodeRef,
+    depth: Option<usize>,
+    palette: Option<Palette>,
+    output: &mut dyn Write,
+) -> io::Result<()> {
+    let mut outline = Outline {
+        output,
+        depth,
+        palette,
+    };
+    match node.data() {
+        NodeData::Element(element) => outline.write_element(node, element, 0, 1),
+        _ => outline.write_children(node, 0),
+    }
+}
+
+pub fn outline(node: &NodeRef, depth: Option<usize>) -> String {
+    let mut content: Vec<u8> = Vec::new();
+    write_outline(node, depth, None, &mut content).unwrap();
+    String::from_utf8(content).unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use kuchikiki::traits::TendrilSink;
+
+    const PAGE: &str = r#"<!DOCTYPE html>
+<html lang="en">
+<head><meta charset="utf-8"><title>Jobs at Acme</title><script>track()</script></head>
+<body>
+  <nav id="menu" class="nav main"><a href="/">Home</a><a href="/jobs">Jobs</a></nav>
+  <main>
+    <h1 itemprop="title">Open   positions
+    </h1>
+    <ul class="jobs">
+      <li id="job-1"><a href="/jobs/1">Backend Engineer</a></li>
+      <li id="job-2"><a href="/jobs/2">Frontend Engineer</a></li>
+      <li id="job-3"><a href="/jobs/3">Data Engineer</a></li>
+      <li class="more"><a href="/jobs?page=2&amp;sort=newest-first-by-date">More</a></li>
+    </ul>
+    <p>We build tools for people who build the web, and we are hiring.</p>
+  </main>
+</body>
+</html>"#;
+
+    #[test]
+    fn test_outline() {
+        let document = kuchikiki::parse_html().one(PAGE);
+        assert_eq!(
+            outline(&document, None),
+            r#"html[lang="en"]
+  head
+    meta[charset="utf-8"]
+    title "Jobs at Acme"
+    script
+  body
+    nav#menu.nav.main
+      a[href="/"] "Home"
+      a[href="/jobs"] "Jobs"
+    main
+      h1[itemprop="title"] "Open positions"
+      ul.jobs
+        li × 3
+          a[href="/jobs/1"] "Backend Engineer"
+        li.more
+          a[href="/jobs?page=2&sort=newest-firs…"] "More"
+      p "We build tools for people who build the…"
+"#
+        );
+    }
+
+    #[test]
+    fn test_depth() {
+        let document = kuchikiki::parse_html().one(PAGE);
+        let main = document.select_first("main").unwrap();
+        assert_eq!(outline(main.as_node(), Some(0)), "main …\n");
+        assert_eq!(
+            outline(main.as_node(), Some(1)),
+            "main\n  h1[itemprop=\"title\"] \"Open positions\"\n  ul.jobs …\n  p \"We build tools for people who build the…\"\n"
+        );
+    }
+}
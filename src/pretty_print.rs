@@ -1,11 +1,9 @@
-use html5ever::QualName;
+use anstyle::{AnsiColor, Style};
 use html5ever::serialize::AttrRef;
-use html5ever::serialize::HtmlSerializer;
 use html5ever::serialize::Serialize;
-use html5ever::serialize::SerializeOpts;
 use html5ever::serialize::Serializer;
 use html5ever::serialize::TraversalScope;
-// use kuchiki::traits::TendrilSink;
+use html5ever::{LocalName, QualName, namespace_url, ns};
 use kuchikiki::NodeRef;
 use lazy_static::lazy_static;
 use std::collections::HashSet;
@@ -15,25 +13,244 @@ use std::str;
 
 lazy_static! {
     static ref INLINE_ELEMENTS: HashSet<&'static str> = vec![
-        "a", "abbr", "acronym", "audio", "b", "bdi", "bdo", "big", "button", "canvas", "cite",
-        "code", "data", "datalist", "del", "dfn", "em", "embed", "i", "iframe", "img", "input",
-        "ins", "kbd", "label", "map", "mark", "meter", "noscript", "object", "output", "picture",
-        "progress", "q", "ruby", "s", "samp", "script", "select", "slot", "small", "span",
-        "strong", "sub", "sup", "svg", "template", "textarea", "time", "u", "tt", "var", "video",
-        "wbr",
+        "a", "abbr", "acronym", "audio", "b", "bdi", "bdo", "big", "br", "button", "canvas",
+        "cite", "code", "data", "datalist", "del", "dfn", "em", "embed", "i", "iframe", "img",
+        "input", "ins", "kbd", "label", "map", "mark", "meter", "noscript", "object", "output",
+        "picture", "progress", "q", "ruby", "s", "samp", "script", "select", "slot", "small",
+        "span", "strong", "sub", "sup", "svg", "template", "textarea", "time", "u", "tt", "var",
+        "video", "wbr",
     ]
     .into_iter()
     .collect();
 }
 
-fn is_inline(name: &str) -> bool {
+/// Elements without an end tag.
+const VOID_ELEMENTS: [&str; 18] = [
+    "area", "base", "basefont", "bgsound", "br", "col", "embed", "frame", "hr", "img", "input",
+    "keygen", "link", "meta", "param", "source", "track", "wbr",
+];
+
+/// Elements whose contents are text that isn't escaped.
+pub(crate) const RAW_TEXT_ELEMENTS: [&str; 8] = [
+    "style",
+    "script",
+    "xmp",
+    "iframe",
+    "noembed",
+    "noframes",
+    "plaintext",
+    "noscript",
+];
+
+/// Elements whose whitespace is significant, on top of the raw text ones.
+const PREFORMATTED_ELEMENTS: [&str; 4] = ["pre", "textarea", "listing", "code"];
+
+/// How wide a tab is taken to be when wrapping attributes.
+const TAB_WIDTH: usize = 8;
+
+pub(crate) fn is_inline(name: &str) -> bool {
     INLINE_ELEMENTS.contains(name)
 }
 
+pub(crate) fn is_void(name: &str) -> bool {
+    VOID_ELEMENTS.contains(&name)
+}
+
+/// Whether the contents of an element are written exactly as they are.
+pub(crate) fn is_verbatim(name: &str) -> bool {
+    RAW_TEXT_ELEMENTS.contains(&name) || PREFORMATTED_ELEMENTS.contains(&name)
+}
+
+/// How to lay out pretty printed HTML.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PrettyOptions {
+    /// Spaces per level of indentation.
+    pub indent: usize,
+    /// Indent with a tab per level instead of spaces.
+    pub tabs: bool,
+    /// Put each attribute of a block element's start tag on its own line if the tag would end
+    /// past this column.
+    pub wrap_attributes: Option<usize>,
+}
+
+impl Default for PrettyOptions {
+    fn default() -> Self {
+        Self {
+            indent: 2,
+            tabs: false,
+            wrap_attributes: None,
+        }
+    }
+}
+
+/// The ANSI styles to highlight each part of HTML with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Palette {
+    /// Start and end tags, the doctype included.
+    pub tag: Style,
+    pub attribute: Style,
+    /// Attribute values, with their quotes.
+    pub value: Style,
+    /// Comments and processing instructions.
+    pub comment: Style,
+    pub text: Style,
+}
+
+impl Default for Palette {
+    fn default() -> Self {
+        Self {
+            tag: AnsiColor::Blue.on_default(),
+            attribute: AnsiColor::Cyan.on_default(),
+            value: AnsiColor::Green.on_default(),
+            comment: AnsiColor::BrightBlack.on_default(),
+            text: AnsiColor::Yellow.on_default(),
+        }
+    }
+}
+
+/// An element whose end tag hasn't been written yet.
+struct OpenElement {
+    name: LocalName,
+    block: bool,
+    /// Whether a child started on a line of its own, so the end tag has to as well.
+    has_block_children: bool,
+}
+
+/// Serializes HTML, optionally highlighted with ANSI colours.
+///
+/// When pretty printing, block elements go on lines of their own, indented by depth, with inline
+/// elements and text flowing within them. Whitespace between words is collapsed, except in
+/// preformatted and raw text elements, which are written as they are. Otherwise everything is.
 struct PrettyPrint<W: Write> {
-    indent: usize,
+    writer: W,
+    /// How to lay out the HTML, `None` to write it as it is.
+    layout: Option<PrettyOptions>,
+    palette: Option<Palette>,
+    open: Vec<OpenElement>,
+    /// How many elements deep into a verbatim element the serializer is, 0 outside of one.
+    /// Without a layout, the whole output is verbatim.
+    verbatim: usize,
+    /// Whether the current element is a raw text one, whose text isn't escaped.
+    raw_text: bool,
+    /// Whether nothing has been written in a `<pre>`, `<textarea>` or `<listing>` yet. Parsers
+    /// drop a newline right after their start tag, so one starting their text is doubled.
+    leading_newline_dropped: bool,
     previous_was_block: bool,
-    inner: HtmlSerializer<W>,
+    /// Whitespace that was collapsed, to be written as a space if more inline content follows.
+    pending_space: bool,
+    at_start: bool,
+    column: usize,
+}
+
+impl<W: Write> PrettyPrint<W> {
+    fn new(writer: W, layout: Option<PrettyOptions>, palette: Option<Palette>) -> Self {
+        Self {
+            writer,
+            layout,
+            palette,
+            open: Vec::new(),
+            verbatim: usize::from(layout.is_none()),
+            raw_text: false,
+            leading_newline_dropped: false,
+            previous_was_block: false,
+            pending_space: false,
+            at_start: true,
+            column: 0,
+        }
+    }
+
+    fn write(&mut self, text: &str) -> io::Result<()> {
+        let line = match text.rfind('\n') {
+            Some(newline) => {
+                self.column = 0;
+                &text[newline + 1..]
+            }
+            None => text,
+        };
+        self.column += line
+            .chars()
+            .map(|c| if c == '\t' { TAB_WIDTH } else { 1 })
+            .sum::<usize>();
+        self.at_start = false;
+        self.writer.write_all(text.as_bytes())
+    }
+
+    /// Write `text` in the style `style` picks from the palette, if there is one.
+    fn paint(&mut self, text: &str, style: fn(&Palette) -> Style) -> io::Result<()> {
+        match self.palette {
+            Some(palette) if !text.is_empty() => {
+                let style = style(&palette);
+                write!(self.writer, "{}", style.render())?;
+                self.write(text)?;
+                write!(self.writer, "{}", style.render_reset())
+            }
+            _ => self.write(text),
+        }
+    }
+
+    fn indentation(&self, depth: usize) -> String {
+        let options = self.layout.unwrap_or_default();
+        if options.tabs {
+            "\t".repeat(depth)
+        } else {
+            " ".repeat(depth * options.indent)
+        }
+    }
+
+    /// Start a new line at the current depth, for something that goes on a line of its own.
+    fn break_line(&mut self) -> io::Result<()> {
+        self.pending_space = false;
+        if let Some(parent) = self.open.last_mut() {
+            parent.has_block_children = true;
+        }
+        if !self.at_start {
+            self.write("\n")?;
+        }
+        let indentation = self.indentation(self.open.len());
+        self.write(&indentation)
+    }
+
+    /// Write a space for collapsed whitespace before more inline content.
+    fn flush_space(&mut self) -> io::Result<()> {
+        if std::mem::take(&mut self.pending_space) {
+            self.write(" ")?;
+        }
+        Ok(())
+    }
+
+    fn write_start_tag<'a>(
+        &mut self,
+        name: &str,
+        attrs: impl Iterator<Item = AttrRef<'a>>,
+        wrap: bool,
+    ) -> io::Result<()> {
+        let attrs: Vec<(String, String)> = attrs
+            .map(|(name, value)| (attribute_name(name), format!("\"{}\"", escape(value, true))))
+            .collect();
+        let length = name.len()
+            + attrs
+                .iter()
+                .map(|(name, value)| name.len() + value.len() + 2)
+                .sum::<usize>()
+            + 2;
+
+        let wrap = wrap
+            && attrs.len() > 1
+            && self
+                .layout
+                .and_then(|layout| layout.wrap_attributes)
+                .is_some_and(|width| self.column + length > width);
+
+        self.paint(&format!("<{name}"), |p| p.tag)?;
+        let indentation = format!("\n{}", self.indentation(self.open.len() + 1));
+        for (name, value) in &attrs {
+            self.write(if wrap { &indentation } else { " " })?;
+            self.paint(name, |p| p.attribute)?;
+            self.write("=")?;
+            self.paint(value, |p| p.value)?;
+        }
+        self.paint(">", |p| p.tag)
+    }
 }
 
 impl<W: Write> Serializer for PrettyPrint<W> {
@@ -41,72 +258,299 @@ impl<W: Write> Serializer for PrettyPrint<W> {
     where
         AttrIter: Iterator<Item = AttrRef<'a>>,
     {
-        let inline = is_inline(&name.local);
-        if !inline || self.previous_was_block {
-            self.inner.writer.write_all(b"\n")?;
-            self.inner.writer.write_all(&vec![b' '; self.indent])?;
+        let local = &*name.local;
+
+        if self.verbatim > 0 {
+            if !is_void(local) {
+                self.verbatim += 1;
+                self.raw_text = RAW_TEXT_ELEMENTS.contains(&local);
+            }
+            self.leading_newline_dropped = false;
+            return self.write_start_tag(local, attrs, false);
         }
 
-        self.indent += 2;
-        self.inner.start_elem(name, attrs)?;
+        let block = !is_inline(local);
+        if block || self.previous_was_block {
+            self.break_line()?;
+        } else {
+            self.flush_space()?;
+        }
+        self.write_start_tag(local, attrs, block)?;
+
+        if is_void(local) {
+            self.previous_was_block = block;
+            return Ok(());
+        }
 
+        self.open.push(OpenElement {
+            name: name.local.clone(),
+            block,
+            has_block_children: false,
+        });
+        if is_verbatim(local) {
+            self.verbatim = 1;
+            self.raw_text = RAW_TEXT_ELEMENTS.contains(&local);
+            self.leading_newline_dropped = matches!(local, "pre" | "textarea" | "listing");
+        }
+        self.previous_was_block = false;
         Ok(())
     }
 
     fn end_elem(&mut self, name: QualName) -> io::Result<()> {
-        self.indent -= 2;
+        if is_void(&name.local) {
+            return Ok(());
+        }
 
-        if is_inline(&name.local) {
-            self.previous_was_block = false;
-        } else {
-            self.inner.writer.write_all(b"\n")?;
-            self.inner.writer.write_all(&vec![b' '; self.indent])?;
-            self.previous_was_block = true;
+        if self.verbatim > 1 {
+            self.verbatim -= 1;
+            self.raw_text = false;
+            return self.paint(&format!("</{}>", &*name.local), |p| p.tag);
+        }
+        self.verbatim = 0;
+
+        let Some(element) = self.open.pop() else {
+            return Ok(());
+        };
+        if element.block || element.has_block_children {
+            self.pending_space = false;
+        }
+        if element.has_block_children {
+            self.write("\n")?;
+            let indentation = self.indentation(self.open.len());
+            self.write(&indentation)?;
         }
 
-        self.inner.end_elem(name)
+        // an inline element around blocks ends on a line of its own too
+        self.previous_was_block = element.block || element.has_block_children;
+        self.paint(&format!("</{}>", &*element.name), |p| p.tag)
     }
 
     fn write_text(&mut self, text: &str) -> io::Result<()> {
-        if text.trim().is_empty() {
-            Ok(())
-        } else {
-            if self.previous_was_block {
-                self.inner.writer.write_all(b"\n")?;
-                self.inner.writer.write_all(&vec![b' '; self.indent])?;
+        if self.verbatim > 0 {
+            if std::mem::take(&mut self.leading_newline_dropped) && text.starts_with('\n') {
+                self.write("\n")?;
             }
+            return if self.raw_text {
+                self.paint(text, |p| p.text)
+            } else {
+                self.paint(&escape(text, false), |p| p.text)
+            };
+        }
 
-            self.previous_was_block = false;
-            self.inner.write_text(text)
+        let mut words = text.split_whitespace().peekable();
+        if words.peek().is_none() {
+            self.pending_space |= !text.is_empty() && !self.previous_was_block;
+            return Ok(());
         }
+
+        if self.previous_was_block {
+            self.break_line()?;
+        } else {
+            self.pending_space |= text.starts_with(char::is_whitespace);
+            self.flush_space()?;
+        }
+
+        let collapsed = words.collect::<Vec<_>>().join(" ");
+        self.paint(&escape(&collapsed, false), |p| p.text)?;
+
+        self.pending_space = text.ends_with(char::is_whitespace);
+        self.previous_was_block = false;
+        Ok(())
     }
 
     fn write_comment(&mut self, text: &str) -> io::Result<()> {
-        self.inner.write_comment(text)
+        self.leading_newline_dropped = false;
+        if self.verbatim == 0 {
+            self.break_line()?;
+            self.previous_was_block = true;
+        }
+        self.paint(&format!("<!--{text}-->"), |p| p.comment)
     }
 
     fn write_doctype(&mut self, name: &str) -> io::Result<()> {
-        self.inner.write_doctype(name)
+        if self.verbatim == 0 {
+            self.break_line()?;
+            self.previous_was_block = true;
+        }
+        self.paint(&format!("<!DOCTYPE {name}>"), |p| p.tag)
     }
 
     fn write_processing_instruction(&mut self, target: &str, data: &str) -> io::Result<()> {
-        self.inner.write_processing_instruction(target, data)
+        self.leading_newline_dropped = false;
+        if self.verbatim == 0 {
+            self.break_line()?;
+            self.previous_was_block = true;
+        }
+        self.paint(&format!("<?{target} {data}>"), |p| p.comment)
+    }
+}
+
+/// The name of an attribute as it's written in HTML, with the prefix of foreign attributes.
+pub(crate) fn attribute_name(name: &QualName) -> String {
+    match name.ns {
+        ns!(xml) => format!("xml:{}", &*name.local),
+        ns!(xlink) => format!("xlink:{}", &*name.local),
+        ns!(xmlns) if &*name.local != "xmlns" => format!("xmlns:{}", &*name.local),
+        _ => name.local.to_string(),
     }
 }
 
+/// Escape text or an attribute value the way html5ever's serializer does.
+pub(crate) fn escape(text: &str, attribute: bool) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '\u{a0}' => escaped.push_str("&nbsp;"),
+            '"' if attribute => escaped.push_str("&quot;"),
+            '<' if !attribute => escaped.push_str("&lt;"),
+            '>' if !attribute => escaped.push_str("&gt;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Serialize `node` and what's in it, pretty printed as per `layout` if given, and highlighted
+/// with `palette` if given. With neither, that's the same as `node.to_string()`.
+pub fn write_html(
+    node: &NodeRef,
+    layout: Option<PrettyOptions>,
+    palette: Option<Palette>,
+    output: &mut dyn Write,
+) -> io::Result<()> {
+    let mut pp = PrettyPrint::new(output, layout, palette);
+    Serialize::serialize(node, &mut pp, TraversalScope::IncludeNode)
+}
+
+/// Pretty print `node` and what's in it, laid out as per `options`.
+pub fn write_pretty(
+    node: &NodeRef,
+    options: PrettyOptions,
+    output: &mut dyn Write,
+) -> io::Result<()> {
+    write_html(node, Some(options), None, output)
+}
+
 pub fn pretty_print(node: &NodeRef) -> String {
     let mut content: Vec<u8> = Vec::new();
-    let mut pp = PrettyPrint {
-        indent: 0,
-        previous_was_block: false,
-        inner: HtmlSerializer::new(
-            &mut content,
-            SerializeOpts {
-                traversal_scope: TraversalScope::IncludeNode,
-                ..Default::default()
-            },
-        ),
-    };
-    Serialize::serialize(node, &mut pp, TraversalScope::IncludeNode).unwrap();
+    write_pretty(node, PrettyOptions::default(), &mut content).unwrap();
     str::from_utf8(content.as_ref()).unwrap().to_owned()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use kuchikiki::traits::TendrilSink;
+
+    fn pretty(html: &str, selector: &str, options: PrettyOptions) -> String {
+        let document = kuchikiki::parse_html().one(html);
+        let node = document.select_first(selector).unwrap();
+        let mut output = Vec::new();
+        write_pretty(node.as_node(), options, &mut output).unwrap();
+        String::from_utf8(output).unwrap()
+    }
+
+    #[test]
+    fn test_layout() {
+        assert_eq!(
+            pretty(
+                "<div class=post>\n  <h2>Senior   <b>Rust</b> <i>Engineer</i></h2><!-- salary -->\n<ul><li>One<li>Two<br>lines</ul>\n<p></p></div>",
+                "div",
+                PrettyOptions::default()
+            ),
+            "<div class=\"post\">
+  <h2>Senior <b>Rust</b> <i>Engineer</i></h2>
+  <!-- salary -->
+  <ul>
+    <li>One</li>
+    <li>Two<br>lines</li>
+  </ul>
+  <p></p>
+</div>"
+        );
+    }
+
+    #[test]
+    fn test_verbatim_elements() {
+        assert_eq!(
+            pretty(
+                "<div><pre>\n\n  fn main() {\n    <b>x</b> &lt; 1\n  }</pre><p>Use <code>a  &amp;&amp;  b</code></p><script>if (a < b) {\n  go();\n}</script></div>",
+                "div",
+                PrettyOptions::default()
+            ),
+            "<div>
+  <pre>\n\n  fn main() {
+    <b>x</b> &lt; 1
+  }</pre>
+  <p>Use <code>a  &amp;&amp;  b</code></p>
+  <script>if (a < b) {
+  go();
+}</script>
+</div>"
+        );
+    }
+
+    #[test]
+    fn test_options() {
+        let html = "<!DOCTYPE html><html><head><meta charset=utf-8></head><body><form action=/apply method=post class=application-form><input name=email required></form></body></html>";
+        let document = kuchikiki::parse_html().one(html);
+
+        let mut output = Vec::new();
+        let options = PrettyOptions {
+            tabs: true,
+            wrap_attributes: Some(40),
+            ..PrettyOptions::default()
+        };
+        write_pretty(&document, options, &mut output).unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "<!DOCTYPE html>
+<html>
+\t<head>
+\t\t<meta charset=\"utf-8\">
+\t</head>
+\t<body>
+\t\t<form
+\t\t\taction=\"/apply\"
+\t\t\tmethod=\"post\"
+\t\t\tclass=\"application-form\"><input name=\"email\" required=\"\"></form>
+\t</body>
+</html>"
+        );
+
+        assert_eq!(
+            pretty(
+                "<ul><li>A</li></ul>",
+                "ul",
+                PrettyOptions {
+                    indent: 4,
+                    ..PrettyOptions::default()
+                }
+            ),
+            "<ul>\n    <li>A</li>\n</ul>"
+        );
+    }
+
+    #[test]
+    fn test_unformatted() {
+        let document = kuchikiki::parse_html().one(
+            "<!DOCTYPE html><html><head><script>if (a < b && c) {}</script></head><body>\n<p class=\"x\" title='a \"b\" &amp; c'>A&nbsp;&lt;b&gt;<br><!-- c --> <svg xlink:href=#a></svg></p><pre>\n\nx</pre></body></html>",
+        );
+        let mut output = Vec::new();
+        write_html(&document, None, None, &mut output).unwrap();
+        assert_eq!(String::from_utf8(output).unwrap(), document.to_string());
+    }
+
+    #[test]
+    fn test_highlight() {
+        let document = kuchikiki::parse_html().one("<p class=x>Hi<!--c--></p>");
+        let p = document.select_first("p").unwrap();
+        let mut output = Vec::new();
+        write_html(p.as_node(), None, Some(Palette::default()), &mut output).unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "\x1b[34m<p\x1b[0m \x1b[36mclass\x1b[0m=\x1b[32m\"x\"\x1b[0m\x1b[34m>\x1b[0m\x1b[33mHi\x1b[0m\x1b[90m<!--c-->\x1b[0m\x1b[34m</p>\x1b[0m"
+        );
+    }
+}
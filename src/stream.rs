@@ -0,0 +1,510 @@
+use crate::{HqConfig, HqError, Query};
+use encoding_rs::Encoding;
+use html5ever::{QualName, local_name, namespace_url, ns};
+use kuchikiki::traits::{NodeIterator, TendrilSink};
+use lol_html::html_content::{ContentType, Element};
+use lol_html::{
+    AsciiCompatibleEncoding, ElementContentHandlers, HtmlRewriter, Selector, Settings, end_tag,
+};
+use std::borrow::Cow;
+use std::cell::RefCell;
+use std::io::{Read, Write};
+use url::Url;
+
+// NUL can't occur in the markup lol_html writes for elements, so these won't clash with input
+const MATCH_START: &str = "\0hq-match-start\0";
+const MATCH_END: &str = "\0hq-match-end\0";
+
+const CHUNK_SIZE: usize = 64 * 1024;
+
+/// Tags that the fragment parser drops, so matching them needs the whole document anyway.
+const DOCUMENT_TAGS: [&str; 3] = ["html", "head", "body"];
+
+/// Elements that belong in the `<head>`, before which `<html>`, `<head>` and `<body>` can
+/// still turn up.
+const HEAD_TAGS: [&str; 9] = [
+    "html", "head", "title", "meta", "link", "style", "script", "base", "noscript",
+];
+
+/// Explain why `config` can't be run in streaming mode, or `None` if it can.
+pub fn unsupported_reason(config: &HqConfig, encoding: &'static Encoding) -> Option<String> {
+    if let Err(e) = config.selector.parse::<Selector>() {
+        return Some(format!(
+            "selector `{}` can't be matched while streaming ({})",
+            config.selector,
+            e.to_string().trim_end_matches('.').to_lowercase()
+        ));
+    }
+
+    if !config.remove_nodes.is_empty() {
+        return Some("--remove-nodes needs the whole document".to_string());
+    }
+
+    if config.json_path.is_some() || config.compact {
+        return Some("--json-path and --compact need the whole output".to_string());
+    }
+
+    if config.links {
+        return Some("--links needs the whole document".to_string());
+    }
+
+    if config.xml {
+        return Some("XML input is parsed as a whole".to_string());
+    }
+
+    if AsciiCompatibleEncoding::new(encoding).is_none() {
+        return Some(format!("{} input can't be streamed", encoding.name()));
+    }
+
+    None
+}
+
+/// How streaming a document ended.
+#[derive(Debug)]
+pub enum Streamed {
+    /// This many elements matched, and were written.
+    Matches(usize),
+    /// A match turned out to need the whole document before anything was written, with the
+    /// reason why and the input read so far, for the tree engine to carry on with.
+    NeedsTree { reason: String, read: Vec<u8> },
+}
+
+/// What's known about the start of the document, where `<html>`, `<head>` and `<body>` are.
+#[derive(Default)]
+struct Prologue {
+    /// Whether the document's content has started, after which matches are written as they
+    /// complete and the input read so far is let go of.
+    past_head: bool,
+    /// The document tag that matched, if any.
+    document_tag: Option<String>,
+}
+
+/// Run the query on HTML read from `input` in chunks, writing each match to `output` as soon
+/// as its end tag has been read. Only the source of matched elements is buffered, and each one
+/// is parsed on its own and written like the tree engine would.
+///
+/// Memory use is bounded by the size of the largest match rather than the whole document.
+/// Check [`unsupported_reason`] first, this fails for anything it reports. Matching `<html>`,
+/// `<head>` or `<body>` needs the whole document too, which only shows while streaming, so
+/// matches in the `<head>` are held back until it's over, in case [`Streamed::NeedsTree`].
+pub fn stream_html(
+    mut input: impl Read,
+    encoding: &'static Encoding,
+    query: &Query,
+    output: &mut dyn Write,
+) -> Result<Streamed, HqError> {
+    let config = query.config();
+    if let Some(reason) = unsupported_reason(config, encoding) {
+        return Err(HqError::InvalidArgument(reason));
+    }
+
+    // until a `<base>` turns up, the base is what it would be in a document without one
+    let url = query.url.as_ref().filter(|_| query.rewrites_urls());
+    let base = RefCell::new(query.base.clone().or_else(|| url.cloned()));
+    let captures = RefCell::new(Captures::default());
+    let prologue = RefCell::new(Prologue::default());
+
+    let mut element_content_handlers = vec![
+        (
+            Cow::Owned(parse_selector(&config.selector)?),
+            ElementContentHandlers::default().element(|el: &mut Element<'_, '_>| {
+                let tag = el.tag_name();
+                if DOCUMENT_TAGS.contains(&tag.as_str()) {
+                    prologue.borrow_mut().document_tag = Some(tag);
+                    return Err("document tag matched".into());
+                }
+
+                el.before(MATCH_START, ContentType::Html);
+                if el.can_have_content() {
+                    el.on_end_tag(end_tag!(|end| {
+                        end.after(MATCH_END, ContentType::Html);
+                        Ok(())
+                    }))?;
+                } else {
+                    el.after(MATCH_END, ContentType::Html);
+                }
+                Ok(())
+            }),
+        ),
+        (
+            Cow::Owned(parse_selector("*")?),
+            ElementContentHandlers::default().element(|el: &mut Element<'_, '_>| {
+                let mut prologue = prologue.borrow_mut();
+                if !prologue.past_head && !HEAD_TAGS.contains(&el.tag_name().as_str()) {
+                    prologue.past_head = true;
+                }
+                Ok(())
+            }),
+        ),
+    ];
+
+    if query.uses_base_element(url) {
+        // like the tree engine, a `<base>` in the document wins over the configured base
+        let resolve_against = url.or(query.base.as_ref());
+        let (base, mut detected) = (&base, false);
+        element_content_handlers.push((
+            Cow::Owned(parse_selector("base[href]")?),
+            ElementContentHandlers::default().element(move |el: &mut Element<'_, '_>| {
+                if !detected
+                    && let Some(href) = el.get_attribute("href")
+                    && let Some(url) = match resolve_against {
+                        Some(url) => url.join(&href).ok(),
+                        None => Url::parse(&href).ok(),
+                    }
+                {
+                    *base.borrow_mut() = Some(url);
+                    detected = true;
+                }
+                Ok(())
+            }),
+        ));
+    }
+
+    let mut rewriter = HtmlRewriter::new(
+        Settings {
+            element_content_handlers,
+            encoding: AsciiCompatibleEncoding::new(encoding).ok_or_else(|| {
+                HqError::Encoding(format!("{} input can't be streamed", encoding.name()))
+            })?,
+            // don't bail out on markup whose parse would depend on the full tree
+            strict: false,
+            ..Settings::new()
+        },
+        |chunk: &[u8]| captures.borrow_mut().feed(chunk),
+    );
+
+    let mut matches = 0;
+    let mut buffer = vec![0; CHUNK_SIZE];
+    let mut read_so_far = Some(Vec::new());
+    loop {
+        let read = input.read(&mut buffer)?;
+        if let Some(read_so_far) = &mut read_so_far {
+            read_so_far.extend_from_slice(&buffer[..read]);
+        }
+
+        if read == 0 {
+            if let Err(e) = rewriter.end() {
+                return needs_tree(&prologue, read_so_far, e);
+            }
+            captures.borrow_mut().finish();
+            matches += write_matches(&captures, &base, encoding, query, output)?;
+            break;
+        }
+
+        if let Err(e) = rewriter.write(&buffer[..read]) {
+            return needs_tree(&prologue, read_so_far, e);
+        }
+
+        // until the content starts, a match of `<body>` would still mean starting over
+        if prologue.borrow().past_head {
+            read_so_far = None;
+            matches += write_matches(&captures, &base, encoding, query, output)?;
+        }
+
+        if config.first && matches > 0 {
+            break;
+        }
+    }
+
+    Ok(Streamed::Matches(matches))
+}
+
+/// Hand over to the tree engine if the rewriter stopped at a document tag while the input is
+/// still there to do so, or fail.
+fn needs_tree(
+    prologue: &RefCell<Prologue>,
+    read_so_far: Option<Vec<u8>>,
+    e: lol_html::errors::RewritingError,
+) -> Result<Streamed, HqError> {
+    let Some(tag) = prologue.borrow_mut().document_tag.take() else {
+        return Err(rewriting_error(e));
+    };
+    let reason = format!("matching <{tag}> needs the whole document");
+    match read_so_far {
+        Some(read) => Ok(Streamed::NeedsTree { reason, read }),
+        None => Err(HqError::InvalidArgument(format!(
+            "{reason}, run without --stream"
+        ))),
+    }
+}
+
+fn parse_selector(selector: &str) -> Result<Selector, HqError> {
+    selector
+        .parse()
+        .map_err(|e| HqError::InvalidArgument(format!("{selector} can't be streamed: {e}")))
+}
+
+fn rewriting_error(e: lol_html::errors::RewritingError) -> HqError {
+    HqError::Input(format!("Failed to stream HTML: {e}"))
+}
+
+/// Parse and write all completed matches, returning how many were written.
+fn write_matches(
+    captures: &RefCell<Captures>,
+    base: &RefCell<Option<Url>>,
+    encoding: &'static Encoding,
+    query: &Query,
+    output: &mut dyn Write,
+) -> Result<usize, HqError> {
+    let complete = std::mem::take(&mut captures.borrow_mut().complete);
+    let mut written = 0;
+
+    for source in complete {
+        if query.config().first && written > 0 {
+            break;
+        }
+
+        let (html, _) = encoding.decode_without_bom_handling(&source);
+
+        // parsing in a <template> context keeps elements such as <tr> or <li> as they are
+        let fragment = kuchikiki::parse_fragment(
+            QualName::new(None, ns!(html), local_name!("template")),
+            Vec::new(),
+        )
+        .one(html.as_ref());
+
+        // the fragment's root is an <html> element, the match is its first child element;
+        // anything after it was only captured because an end tag was missing
+        let Some(node) = fragment
+            .descendants()
+            .elements()
+            .nth(1)
+            .map(|el| el.as_node().clone())
+        else {
+            continue;
+        };
+
+        query.prepare(&node, base.borrow().as_ref());
+        query.write_node(&node, output)?;
+        written += 1;
+    }
+
+    output.flush()?;
+    Ok(written)
+}
+
+/// Collects the source between match markers in the rewriter's output.
+#[derive(Default)]
+struct Captures {
+    buffer: Vec<u8>,
+    /// Offsets into `buffer` of the matches whose end hasn't been seen yet.
+    open: Vec<usize>,
+    /// Ranges of matches nested inside a match that's still open.
+    closed: Vec<(usize, usize)>,
+    /// The start of a marker that was split across output chunks.
+    pending: Vec<u8>,
+    /// Sources of matches ready to be written, in document order.
+    complete: Vec<Vec<u8>>,
+}
+
+impl Captures {
+    fn feed(&mut self, chunk: &[u8]) {
+        let mut data = std::mem::take(&mut self.pending);
+        data.extend_from_slice(chunk);
+        let mut rest = &data[..];
+
+        while let Some(nul) = rest.iter().position(|&b| b == 0) {
+            self.push(&rest[..nul]);
+            rest = &rest[nul..];
+
+            if let Some(after) = rest.strip_prefix(MATCH_START.as_bytes()) {
+                self.open.push(self.buffer.len());
+                rest = after;
+            } else if let Some(after) = rest.strip_prefix(MATCH_END.as_bytes()) {
+                self.close();
+                rest = after;
+            } else if MATCH_START.as_bytes().starts_with(rest)
+                || MATCH_END.as_bytes().starts_with(rest)
+            {
+                self.pending = rest.to_vec();
+                return;
+            } else {
+                self.push(&rest[..1]);
+                rest = &rest[1..];
+            }
+        }
+
+        self.push(rest);
+    }
+
+    fn push(&mut self, data: &[u8]) {
+        if !self.open.is_empty() {
+            self.buffer.extend_from_slice(data);
+        }
+    }
+
+    fn close(&mut self) {
+        let Some(start) = self.open.pop() else {
+            return;
+        };
+        self.closed.push((start, self.buffer.len()));
+
+        // nested matches are only written once the outermost one is done, to keep document order
+        if self.open.is_empty() {
+            self.closed.sort_unstable();
+            for (start, end) in self.closed.drain(..) {
+                self.complete.push(self.buffer[start..end].to_vec());
+            }
+            self.buffer.clear();
+        }
+    }
+
+    /// Close matches still open at the end of input, i.e. whose end tags were left out.
+    fn finish(&mut self) {
+        let pending = std::mem::take(&mut self.pending);
+        self.push(&pending);
+        while !self.open.is_empty() {
+            self.close();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use encoding_rs::{UTF_8, UTF_16LE, WINDOWS_1252};
+
+    /// Stream `html` in tiny chunks, so markers get split across output chunks.
+    fn stream(html: &[u8], config: &HqConfig) -> String {
+        let mut output = Vec::new();
+        let input = ChunkedReader(html);
+        let query = Query::compile(config).unwrap();
+        let streamed = stream_html(input, UTF_8, &query, &mut output).unwrap();
+        assert!(matches!(streamed, Streamed::Matches(_)), "{streamed:?}");
+        String::from_utf8(output).unwrap()
+    }
+
+    struct ChunkedReader<'a>(&'a [u8]);
+
+    impl Read for ChunkedReader<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            let n = buf.len().min(3).min(self.0.len());
+            buf[..n].copy_from_slice(&self.0[..n]);
+            self.0 = &self.0[n..];
+            Ok(n)
+        }
+    }
+
+    fn config(selector: &str) -> HqConfig {
+        HqConfig {
+            selector: selector.to_string(),
+            ..HqConfig::default()
+        }
+    }
+
+    #[test]
+    fn test_matches_in_document_order() {
+        let html =
+            b"<ul><li class=a>One <b>1</b></li><li>Two<ul><li class=a>Three</li></ul></li></ul>";
+        assert_eq!(
+            stream(html, &config("li")),
+            "<li class=\"a\">One <b>1</b></li>\n\
+             <li>Two<ul><li class=\"a\">Three</li></ul></li>\n\
+             <li class=\"a\">Three</li>\n"
+        );
+    }
+
+    #[test]
+    fn test_text_attributes_and_void_elements() {
+        let html = b"<table><tr><td>A</td><td>B</td></tr></table><img src=x.png><p>C";
+
+        let text = HqConfig {
+            text_only: true,
+            ..config("td, p")
+        };
+        assert_eq!(stream(html, &text), "A\nB\nC\n");
+
+        let attributes = HqConfig {
+            attributes: vec!["src".to_string()],
+            ..config("img")
+        };
+        assert_eq!(stream(html, &attributes), "x.png\n");
+    }
+
+    #[test]
+    fn test_missing_end_tags() {
+        let html = b"<div><p>One<p>Two</div>";
+        let text = HqConfig {
+            text_only: true,
+            ..config("p")
+        };
+        assert_eq!(stream(html, &text), "One\nTwo\n");
+    }
+
+    #[test]
+    fn test_first() {
+        let html = b"<p>One</p><p>Two</p><p>Three</p>";
+        let first = HqConfig {
+            first: true,
+            ..config("p")
+        };
+        assert_eq!(stream(html, &first), "<p>One</p>\n");
+    }
+
+    #[test]
+    fn test_detect_base() {
+        let html = b"<head><base href=\"https://example.com/jobs/\"></head><a href=\"1\">One</a>";
+        let base = HqConfig {
+            detect_base: true,
+            attributes: vec!["href".to_string()],
+            ..config("a")
+        };
+        assert_eq!(stream(html, &base), "https://example.com/jobs/1\n");
+
+        let html = b"<head><base href=\"/jobs/\"></head><a href=\"1\">One</a>";
+        let url = HqConfig {
+            url: Some("https://example.com/about".to_string()),
+            absolutize: true,
+            attributes: vec!["href".to_string()],
+            ..config("a")
+        };
+        assert_eq!(stream(html, &url), "https://example.com/jobs/1\n");
+    }
+
+    #[test]
+    fn test_document_tags() {
+        let html =
+            b"<html lang=en><head><title>t</title></head><body class=b><p>x</p></body></html>";
+        for selector in ["[lang]", ".b", "*", "body"] {
+            let mut output = Vec::new();
+            let query = Query::compile(&config(selector)).unwrap();
+            match stream_html(ChunkedReader(html), UTF_8, &query, &mut output).unwrap() {
+                Streamed::NeedsTree { read, .. } => assert!(html.starts_with(&read)),
+                matches => panic!("{selector}: {matches:?}"),
+            }
+            assert!(output.is_empty(), "{selector}");
+        }
+
+        // matches in the <head> are only written once it's over
+        assert_eq!(
+            stream(html, &config("title, p")),
+            "<title>t</title>\n<p>x</p>\n"
+        );
+    }
+
+    #[test]
+    fn test_legacy_encoding() {
+        let mut output = Vec::new();
+        let query = Query::compile(&config("p")).unwrap();
+        stream_html(&b"<p>Caf\xe9</p>"[..], WINDOWS_1252, &query, &mut output).unwrap();
+        assert_eq!(output, "<p>Café</p>\n".as_bytes());
+    }
+
+    #[test]
+    fn test_unsupported() {
+        assert!(unsupported_reason(&config("li > a[href]"), UTF_8).is_none());
+        assert!(unsupported_reason(&config(":root"), UTF_8).is_some());
+        assert!(unsupported_reason(&config("div:has(p)"), UTF_8).is_some());
+        let remove = HqConfig {
+            remove_nodes: vec!["section span".to_string()],
+            ..config("div")
+        };
+        assert!(unsupported_reason(&remove, UTF_8).is_some());
+        assert!(unsupported_reason(&config("p"), UTF_16LE).is_some());
+        let json = HqConfig {
+            json_path: Some(".name".to_string()),
+            ..config("script")
+        };
+        assert!(unsupported_reason(&json, UTF_8).is_some());
+    }
+}
@@ -0,0 +1,478 @@
+use crate::{HqConfig, Query};
+use kuchikiki::iter::NodeIterator;
+use kuchikiki::{ElementData, NodeRef};
+use lazy_static::lazy_static;
+use regex::Regex;
+use std::collections::HashMap;
+use std::io::{self, Write};
+
+/// Attributes that identify an element on purpose, with their score.
+const IDENTIFYING_ATTRIBUTES: [(&str, u32); 5] = [
+    ("itemprop", 2),
+    ("data-testid", 2),
+    ("data-test", 2),
+    ("data-qa", 2),
+    ("name", 3),
+];
+
+/// Elements whose text isn't shown, so examples aren't looked for in them.
+const HIDDEN_ELEMENTS: [&str; 4] = ["script", "style", "template", "head"];
+
+/// How many ancestors are tried as anchors for a selector.
+const MAX_ANCHOR_DEPTH: usize = 6;
+
+lazy_static! {
+    /// Class names of utility CSS frameworks, which say how something looks, not what it is.
+    static ref UTILITY_CLASS: Regex = Regex::new(
+        r"^-?(?:[mp][trblxyse]?|w|h|min-w|min-h|max-w|max-h|gap|space-[xy]|z|top|left|right|bottom|inset|order|col|row|basis|grow|shrink|d|float|align|justify|items|content|self|place|font|text|leading|tracking|bg|border|rounded|shadow|opacity|ring|outline|overflow|cursor|select|transition|duration|ease|transform|scale|rotate|translate|sm|md|lg|xl|2xl|hover|focus|dark)-|^(?:flex|grid|block|inline|inline-block|inline-flex|hidden|relative|absolute|fixed|sticky|static|container|clearfix|row|col|truncate|uppercase|lowercase|capitalize|italic|underline|visible|invisible|sr-only|active|show|fade|in|open|selected|disabled|is-active|current)$"
+    )
+    .unwrap();
+
+    /// Prefixes of class names generated by CSS-in-JS libraries.
+    static ref GENERATED_PREFIX: Regex = Regex::new(r"^(?:css|sc|jsx|emotion|svelte|ember)-").unwrap();
+}
+
+/// Whether `name` can be used in a selector as is, without escaping.
+fn is_identifier(name: &str) -> bool {
+    let mut chars = name.chars();
+    let first_ok = match chars.next() {
+        Some('-') => chars
+            .clone()
+            .next()
+            .is_some_and(|c| c.is_ascii_alphabetic() || c == '_'),
+        Some(c) => c.is_ascii_alphabetic() || c == '_',
+        None => false,
+    };
+    first_ok && chars.all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+/// Whether an id or class name looks like it was written by a person rather than generated,
+/// and so is likely to stay the same.
+fn is_stable_name(name: &str) -> bool {
+    is_identifier(name)
+        && !name.contains(|c: char| c.is_ascii_digit())
+        && !GENERATED_PREFIX.is_match(name)
+        && !is_css_module_name(name)
+}
+
+/// Whether `name` ends in the hash CSS modules add, as in `Title_title__aBxYz`.
+fn is_css_module_name(name: &str) -> bool {
+    name.rsplit_once("__").is_some_and(|(_, hash)| {
+        hash.chars().any(|c| c.is_ascii_uppercase()) && hash.chars().any(|c| c.is_ascii_lowercase())
+    })
+}
+
+fn is_semantic_class(name: &str) -> bool {
+    is_stable_name(name) && !UTILITY_CLASS.is_match(name)
+}
+
+/// `value` as a quoted attribute value in a selector.
+fn quote(value: &str) -> String {
+    format!("\"{}\"", value.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+fn collapse_whitespace(text: &str) -> String {
+    text.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+/// The elements whose text contains `text` (ignoring case and whitespace), without those that
+/// only contain it because an element in them does.
+pub fn find_elements(root: &NodeRef, text: &str) -> Vec<NodeRef> {
+    let needle = collapse_whitespace(text).to_lowercase();
+    if needle.is_empty() {
+        return Vec::new();
+    }
+    let contains = |node: &NodeRef| {
+        collapse_whitespace(&node.text_contents())
+            .to_lowercase()
+            .contains(&needle)
+    };
+
+    root.inclusive_descendants()
+        .elements()
+        .filter(|element| {
+            !element
+                .as_node()
+                .inclusive_ancestors()
+                .elements()
+                .any(|ancestor| HIDDEN_ELEMENTS.contains(&&*ancestor.name.local))
+        })
+        .map(|element| element.as_node().clone())
+        .filter(|node| {
+            contains(node)
+                && !node
+                    .children()
+                    .elements()
+                    .any(|child| contains(child.as_node()))
+        })
+        .collect()
+}
+
+/// A selector with its score, lower being better.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Candidate {
+    selector: String,
+    score: u32,
+}
+
+impl Candidate {
+    fn new(selector: String, score: u32) -> Self {
+        Candidate { selector, score }
+    }
+}
+
+/// Selectors made of the element alone. With `bare_tag`, the tag on its own is one of them.
+fn own_candidates(element: &ElementData, bare_tag: bool) -> Vec<Candidate> {
+    let tag = &*element.name.local;
+    let attributes = element.attributes.borrow();
+    let mut candidates = Vec::new();
+
+    if let Some(id) = attributes.get("id").filter(|id| is_stable_name(id)) {
+        candidates.push(Candidate::new(format!("#{id}"), 1));
+    }
+
+    for (name, score) in IDENTIFYING_ATTRIBUTES {
+        if let Some(value) = attributes.get(name).filter(|value| !value.is_empty()) {
+            candidates.push(Candidate::new(format!("[{name}={}]", quote(value)), score));
+            candidates.push(Candidate::new(
+                format!("{tag}[{name}={}]", quote(value)),
+                score + 1,
+            ));
+        }
+    }
+
+    let classes: Vec<&str> = attributes
+        .get("class")
+        .unwrap_or_default()
+        .split_whitespace()
+        .filter(|class| is_semantic_class(class))
+        .collect();
+    for class in &classes {
+        candidates.push(Candidate::new(format!(".{class}"), 4));
+        candidates.push(Candidate::new(format!("{tag}.{class}"), 5));
+    }
+    for (i, first) in classes.iter().enumerate() {
+        for second in &classes[i + 1..] {
+            candidates.push(Candidate::new(format!("{tag}.{first}.{second}"), 7));
+        }
+    }
+
+    if bare_tag {
+        candidates.push(Candidate::new(tag.to_string(), 8));
+    }
+    candidates
+}
+
+/// A path of child combinators to `node` from its nearest ancestor that can be identified by
+/// itself, or from the root, with positions only where siblings have the same tag.
+fn positional_candidate(node: &NodeRef) -> Option<Candidate> {
+    let mut steps = Vec::new();
+    // any change to the structure of the page breaks these
+    let mut score = 3;
+    let mut current = node.clone();
+    loop {
+        let element = current.as_element()?;
+        let tag = &*element.name.local;
+        if !steps.is_empty()
+            && let Some(anchor) = rank(own_candidates(element, false)).into_iter().next()
+        {
+            steps.push(anchor.selector);
+            score += anchor.score;
+            break;
+        }
+
+        let same_tag = |sibling: &NodeRef| {
+            sibling
+                .as_element()
+                .is_some_and(|sibling| sibling.name.local == element.name.local)
+        };
+        if current.preceding_siblings().any(|s| same_tag(&s))
+            || current.following_siblings().any(|s| same_tag(&s))
+        {
+            let position = current.preceding_siblings().elements().count() + 1;
+            steps.push(format!("{tag}:nth-child({position})"));
+            score += 10;
+        } else {
+            steps.push(tag.to_string());
+            score += 1;
+        }
+
+        match current.parent() {
+            Some(parent) if parent.as_element().is_some() => current = parent,
+            _ => break,
+        }
+    }
+    steps.reverse();
+    Some(Candidate::new(steps.join(" > "), score))
+}
+
+/// All candidate selectors for `node`, whether they're unique or not.
+fn candidates(node: &NodeRef) -> Vec<Candidate> {
+    let Some(element) = node.as_element() else {
+        return Vec::new();
+    };
+    let own = own_candidates(element, true);
+    let mut candidates = own.clone();
+
+    // anchored to an ancestor that's identifiable by itself
+    for (depth, ancestor) in node
+        .ancestors()
+        .elements()
+        .take(MAX_ANCHOR_DEPTH)
+        .enumerate()
+    {
+        for anchor in own_candidates(&ancestor, false) {
+            for target in &own {
+                let depth = u32::try_from(depth).unwrap_or(u32::MAX);
+                candidates.push(Candidate::new(
+                    format!("{} {}", anchor.selector, target.selector),
+                    anchor.score + target.score + 2 + depth,
+                ));
+            }
+        }
+    }
+
+    candidates.extend(positional_candidate(node));
+    candidates
+}
+
+/// Which elements `selector` matches, or `None` if it isn't valid.
+struct Matcher<'a> {
+    root: &'a NodeRef,
+    cache: HashMap<String, Option<Vec<NodeRef>>>,
+}
+
+impl<'a> Matcher<'a> {
+    fn new(root: &'a NodeRef) -> Self {
+        Matcher {
+            root,
+            cache: HashMap::new(),
+        }
+    }
+
+    fn matches(&mut self, selector: &str) -> Option<&Vec<NodeRef>> {
+        let root = self.root;
+        self.cache
+            .entry(selector.to_string())
+            .or_insert_with(|| {
+                let query = Query::compile(&HqConfig {
+                    selector: selector.to_string(),
+                    ..Default::default()
+                })
+                .ok()?;
+                Some(
+                    query
+                        .matches(root)
+                        .map(|found| found.node().clone())
+                        .collect(),
+                )
+            })
+            .as_ref()
+    }
+}
+
+/// Sort candidates by score, then length, and drop repeated selectors.
+fn rank(mut candidates: Vec<Candidate>) -> Vec<Candidate> {
+    candidates.sort_by(|a, b| {
+        (a.score, a.selector.len(), &a.selector).cmp(&(b.score, b.selector.len(), &b.selector))
+    });
+    candidates.dedup_by(|a, b| a.selector == b.selector);
+    candidates
+}
+
+/// Up to `limit` selectors that match `node` and nothing else in `root`, best first.
+///
+/// Candidates are built from what tends to survive redesigns, ranked by a score: ids,
+/// `itemprop` and test attributes first, then meaningful class names, tags, and positions
+/// only as a last resort. Ids and classes that look generated (`css-1x2y3z`, `post-4521`) or
+/// are utility classes (`mt-4`, `flex`) are left out.
+pub fn suggest(root: &NodeRef, node: &NodeRef, limit: usize) -> Vec<String> {
+    let mut matcher = Matcher::new(root);
+    rank(candidates(node))
+        .into_iter()
+        .filter(|candidate| {
+            matcher
+                .matches(&candidate.selector)
+                .is_some_and(|matches| matches.len() == 1 && &matches[0] == node)
+        })
+        .take(limit)
+        .map(|candidate| candidate.selector)
+        .collect()
+}
+
+/// Up to `limit` selectors that match all of `nodes`, with how many elements they match in
+/// all, best first: those matching the fewest other elements, then by score.
+pub fn generalize(root: &NodeRef, nodes: &[NodeRef], limit: usize) -> Vec<(String, usize)> {
+    let Some(first) = nodes.first() else {
+        return Vec::new();
+    };
+    let mut matcher = Matcher::new(root);
+    let mut general: Vec<(Candidate, usize)> = rank(candidates(first))
+        .into_iter()
+        .filter_map(|candidate| {
+            let matches = matcher.matches(&candidate.selector)?;
+            nodes
+                .iter()
+                .all(|node| matches.contains(node))
+                .then(|| (candidate.clone(), matches.len()))
+        })
+        .collect();
+    // a stable sort keeps the ranking by score among those matching as many
+    general.sort_by_key(|(_, count)| *count);
+    general
+        .into_iter()
+        .take(limit)
+        .map(|(candidate, count)| (candidate.selector, count))
+        .collect()
+}
+
+/// The start tag of an element, to show which one suggestions are for.
+fn start_tag(element: &ElementData) -> String {
+    let mut tag = format!("<{}", &*element.name.local);
+    for (name, attribute) in &element.attributes.borrow().map {
+        tag.push_str(&format!(" {}=\"{}\"", &*name.local, attribute.value));
+    }
+    tag.push('>');
+    tag
+}
+
+/// Write up to `limit` selectors for each element containing one of `texts`, and if there are
+/// several, selectors that match the elements of all of them.
+pub fn write_suggestions(
+    root: &NodeRef,
+    texts: &[String],
+    limit: usize,
+    output: &mut dyn Write,
+) -> io::Result<()> {
+    let mut examples = Vec::new();
+    for text in texts {
+        let found = find_elements(root, text);
+        if found.is_empty() {
+            writeln!(output, "{text:?}: no element contains it")?;
+        }
+        for node in found {
+            let Some(element) = node.as_element() else {
+                continue;
+            };
+            writeln!(output, "{text:?} in {}:", start_tag(element))?;
+            let selectors = suggest(root, &node, limit);
+            if selectors.is_empty() {
+                writeln!(output, "  (no selector matches it alone)")?;
+            }
+            for selector in selectors {
+                writeln!(output, "  {selector}")?;
+            }
+            examples.push(node);
+        }
+    }
+
+    if texts.len() > 1 && examples.len() > 1 {
+        writeln!(output, "All examples:")?;
+        let general = generalize(root, &examples, limit);
+        if general.is_empty() {
+            writeln!(output, "  (no selector matches them all)")?;
+        }
+        for (selector, count) in general {
+            let noun = if count == 1 { "match" } else { "matches" };
+            writeln!(output, "  {selector} ({count} {noun})")?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use kuchikiki::traits::TendrilSink;
+
+    const PAGE: &str = r#"<html><body>
+        <div class="css-1x2y3z mt-4" id="ember123">
+          <article id="posting" itemscope itemtype="https://schema.org/JobPosting">
+            <h1 class="job-title text-xl" itemprop="title">Senior  Backend Engineer</h1>
+            <div class="flex"><span>Berlin</span><span>Remote</span></div>
+          </article>
+        </div>
+        <h3>Similar jobs</h3>
+        <ul class="related">
+          <li><a href="/1"><h3 class="Title_title__aBxYz">Frontend Engineer</h3></a></li>
+          <li><a href="/2"><h3 class="Title_title__aBxYz">Data Engineer</h3></a></li>
+          <li><a href="/3"><h3 class="Title_title__aBxYz">Designer</h3></a></li>
+        </ul>
+        <script>var title = "Senior Backend Engineer";</script>
+        </body></html>"#;
+
+    #[test]
+    fn test_names() {
+        for stable in [
+            "job-title",
+            "posting",
+            "_private",
+            "related",
+            "-webkit-thing",
+        ] {
+            assert!(is_semantic_class(stable), "{stable}");
+        }
+        for unstable in [
+            "css-1x2y3z",
+            "mt-4",
+            "flex",
+            "text-xl",
+            "ember123",
+            "Title_title__aBxYz",
+            "1st",
+            "md:flex",
+            "active",
+        ] {
+            assert!(!is_semantic_class(unstable), "{unstable}");
+        }
+    }
+
+    #[test]
+    fn test_find_elements() {
+        let document = kuchikiki::parse_html().one(PAGE);
+        let found = find_elements(&document, "senior backend   engineer");
+        assert_eq!(found.len(), 1);
+        assert_eq!(&*found[0].as_element().unwrap().name.local, "h1");
+
+        assert_eq!(find_elements(&document, "Engineer").len(), 3);
+        assert!(find_elements(&document, "Nowhere").is_empty());
+    }
+
+    #[test]
+    fn test_suggest() {
+        let document = kuchikiki::parse_html().one(PAGE);
+        let title = &find_elements(&document, "Senior Backend Engineer")[0];
+        assert_eq!(
+            suggest(&document, title, 4),
+            [
+                "[itemprop=\"title\"]",
+                "h1[itemprop=\"title\"]",
+                ".job-title",
+                "h1.job-title"
+            ]
+        );
+
+        // only positions tell these apart
+        let remote = &find_elements(&document, "Remote")[0];
+        assert_eq!(
+            suggest(&document, remote, 2),
+            ["#posting > div > span:nth-child(2)"]
+        );
+    }
+
+    #[test]
+    fn test_generalize() {
+        let document = kuchikiki::parse_html().one(PAGE);
+        let examples: Vec<NodeRef> = ["Frontend Engineer", "Data Engineer"]
+            .iter()
+            .flat_map(|text| find_elements(&document, text))
+            .collect();
+        assert_eq!(
+            generalize(&document, &examples, 2),
+            [
+                (".related h3".to_string(), 3),
+                ("ul.related h3".to_string(), 3)
+            ]
+        );
+    }
+}
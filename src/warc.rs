@@ -0,0 +1,351 @@
+use crate::HqError;
+use crate::charset::{self, Decoded};
+use crate::decompress;
+use std::error::Error;
+use std::io::{self, BufRead, Read};
+
+/// A single record from a WARC file, with its header block parsed and its content block as is.
+#[derive(Debug, Clone)]
+pub struct WarcRecord {
+    pub version: String,
+    pub headers: Vec<(String, String)>,
+    pub content: Vec<u8>,
+}
+
+impl WarcRecord {
+    /// Look up a header by name, ignoring case.
+    pub fn header(&self, name: &str) -> Option<&str> {
+        find_header(&self.headers, name)
+    }
+
+    pub fn record_type(&self) -> Option<&str> {
+        self.header("WARC-Type")
+    }
+
+    pub fn target_uri(&self) -> Option<&str> {
+        // WARC/1.0 as written by some tools wraps the URI in angle brackets
+        self.header("WARC-Target-URI")
+            .map(|uri| uri.trim_start_matches('<').trim_end_matches('>'))
+    }
+
+    pub fn date(&self) -> Option<&str> {
+        self.header("WARC-Date")
+    }
+
+    pub fn record_id(&self) -> Option<&str> {
+        self.header("WARC-Record-ID")
+    }
+
+    pub fn is_response(&self) -> bool {
+        self.record_type()
+            .is_some_and(|t| t.eq_ignore_ascii_case("response"))
+    }
+
+    /// Parse the content block of a `response` record as an HTTP response.
+    pub fn http_response(&self) -> Result<HttpResponse, Box<dyn Error>> {
+        HttpResponse::parse(&self.content)
+    }
+}
+
+/// An HTTP response as stored in a WARC `response` record.
+#[derive(Debug, Clone)]
+pub struct HttpResponse {
+    pub status: u16,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl HttpResponse {
+    pub fn parse(data: &[u8]) -> Result<Self, Box<dyn Error>> {
+        let mut reader = data;
+
+        let status_line = read_line(&mut reader)?.ok_or("Missing HTTP status line")?;
+        let status = status_line
+            .strip_prefix("HTTP/")
+            .and_then(|rest| rest.split_whitespace().nth(1))
+            .and_then(|code| code.parse().ok())
+            .ok_or_else(|| format!("Invalid HTTP status line: {status_line}"))?;
+
+        let headers = read_headers(&mut reader)?;
+
+        Ok(Self {
+            status,
+            headers,
+            body: reader.to_vec(),
+        })
+    }
+
+    /// Look up a header by name, ignoring case.
+    pub fn header(&self, name: &str) -> Option<&str> {
+        find_header(&self.headers, name)
+    }
+
+    /// Whether the response is HTML, assuming so if it has no `Content-Type`.
+    pub fn is_html(&self) -> bool {
+        self.header("Content-Type")
+            .is_none_or(|ct| ct.to_ascii_lowercase().contains("html"))
+    }
+
+    /// Decode the body to a string, using the charset from the `Content-Type` header unless
+    /// `override_label` is given.
+    pub fn decode_body(&self, override_label: Option<&str>) -> Result<Decoded, HqError> {
+        charset::decode_html(
+            &self.decoded_body(),
+            override_label,
+            self.header("Content-Type"),
+        )
+    }
+
+    /// The body with any chunked transfer coding and `Content-Encoding` undone.
+    ///
+    /// Crawlers don't always keep the headers in sync with what they stored, so if the body
+    /// doesn't decode as advertised, it is returned without decoding.
+    pub fn decoded_body(&self) -> Vec<u8> {
+        let mut body = self.body.clone();
+
+        if self
+            .header("Transfer-Encoding")
+            .is_some_and(|te| te.to_ascii_lowercase().contains("chunked"))
+            && let Ok(dechunked) = dechunk(&body)
+        {
+            body = dechunked;
+        }
+
+        if let Some(content_encoding) = self.header("Content-Encoding")
+            && let Ok(decoded) = decompress::decode_content_encoding(body.clone(), content_encoding)
+        {
+            body = decoded;
+        }
+
+        body
+    }
+}
+
+/// Whether `data` looks like it starts with a WARC record.
+pub fn is_warc(data: &[u8]) -> bool {
+    data.starts_with(b"WARC/")
+}
+
+/// Iterates over the records of an uncompressed WARC stream.
+///
+/// For `.warc.gz` files, wrap the input in [`decompress::reader`] first, which handles the
+/// per-record gzip members.
+pub struct WarcReader<R: BufRead> {
+    inner: R,
+}
+
+impl<R: BufRead> WarcReader<R> {
+    pub fn new(inner: R) -> Self {
+        Self { inner }
+    }
+
+    /// The underlying reader, positioned at the start of the next record.
+    pub fn get_mut(&mut self) -> &mut R {
+        &mut self.inner
+    }
+
+    fn read_record(&mut self) -> Result<Option<WarcRecord>, Box<dyn Error>> {
+        // records are separated by blank lines
+        let version = loop {
+            match read_line(&mut self.inner)? {
+                None => return Ok(None),
+                Some(line) if line.is_empty() => continue,
+                Some(line) if line.starts_with("WARC/") => break line,
+                Some(line) => return Err(format!("Expected WARC version line, got: {line}").into()),
+            }
+        };
+
+        let headers = read_headers(&mut self.inner)?;
+
+        let length: u64 = find_header(&headers, "Content-Length")
+            .ok_or("WARC record without Content-Length")?
+            .trim()
+            .parse()
+            .map_err(|_| "Invalid WARC Content-Length")?;
+
+        let mut content = Vec::new();
+        (&mut self.inner).take(length).read_to_end(&mut content)?;
+        if (content.len() as u64) < length {
+            return Err("Truncated WARC record".into());
+        }
+
+        // consume the two line endings after the content, so the next record starts right away
+        for _ in 0..2 {
+            let buf = self.inner.fill_buf()?;
+            if buf.starts_with(b"\r\n") {
+                self.inner.consume(2);
+            } else if buf.starts_with(b"\n") {
+                self.inner.consume(1);
+            } else {
+                break;
+            }
+        }
+
+        Ok(Some(WarcRecord {
+            version,
+            headers,
+            content,
+        }))
+    }
+}
+
+impl<R: BufRead> Iterator for WarcReader<R> {
+    type Item = Result<WarcRecord, Box<dyn Error>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.read_record().transpose()
+    }
+}
+
+fn find_header<'a>(headers: &'a [(String, String)], name: &str) -> Option<&'a str> {
+    headers
+        .iter()
+        .find(|(key, _)| key.eq_ignore_ascii_case(name))
+        .map(|(_, value)| value.as_str())
+}
+
+/// Read a line without its line ending, or `None` at the end of input.
+fn read_line(reader: &mut impl BufRead) -> io::Result<Option<String>> {
+    let mut line = Vec::new();
+    if reader.read_until(b'\n', &mut line)? == 0 {
+        return Ok(None);
+    }
+
+    while line.last().is_some_and(|&b| b == b'\n' || b == b'\r') {
+        line.pop();
+    }
+
+    Ok(Some(String::from_utf8_lossy(&line).into_owned()))
+}
+
+/// Read `Name: value` lines up to and including the blank line ending a header block.
+fn read_headers(reader: &mut impl BufRead) -> io::Result<Vec<(String, String)>> {
+    let mut headers: Vec<(String, String)> = Vec::new();
+
+    while let Some(line) = read_line(reader)? {
+        if line.is_empty() {
+            break;
+        }
+
+        if line.starts_with([' ', '\t']) {
+            // continuation of the previous header's value
+            if let Some((_, value)) = headers.last_mut() {
+                value.push(' ');
+                value.push_str(line.trim());
+            }
+        } else if let Some((name, value)) = line.split_once(':') {
+            headers.push((name.trim().to_string(), value.trim().to_string()));
+        }
+    }
+
+    Ok(headers)
+}
+
+/// Undo `Transfer-Encoding: chunked`.
+fn dechunk(mut data: &[u8]) -> Result<Vec<u8>, Box<dyn Error>> {
+    let mut body = Vec::new();
+
+    loop {
+        let size_line = read_line(&mut data)?.ok_or("Truncated chunked body")?;
+        let size = size_line.split(';').next().unwrap_or_default().trim();
+        let size = usize::from_str_radix(size, 16).map_err(|_| "Invalid chunk size")?;
+
+        if size == 0 {
+            return Ok(body);
+        }
+
+        let chunk = data.get(..size).ok_or("Truncated chunked body")?;
+        body.extend_from_slice(chunk);
+        data = &data[size..];
+        read_line(&mut data)?;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    fn record(warc_type: &str, uri: &str, content: &str) -> String {
+        format!(
+            "WARC/1.0\r\nWARC-Type: {warc_type}\r\nWARC-Target-URI: {uri}\r\nWARC-Date: 2024-03-01T12:00:00Z\r\nWARC-Record-ID: <urn:uuid:{warc_type}>\r\nContent-Length: {}\r\n\r\n{content}\r\n\r\n",
+            content.len()
+        )
+    }
+
+    #[test]
+    fn test_read_records() {
+        let http =
+            "HTTP/1.1 200 OK\r\nContent-Type: text/html; charset=utf-8\r\n\r\n<title>Hi</title>";
+        let warc = format!(
+            "{}{}",
+            record("request", "https://example.com/", "GET / HTTP/1.1\r\n\r\n"),
+            record("response", "https://example.com/", http)
+        );
+
+        let records: Vec<_> = WarcReader::new(warc.as_bytes())
+            .collect::<Result<_, _>>()
+            .unwrap();
+
+        assert_eq!(records.len(), 2);
+        assert!(!records[0].is_response());
+        assert!(records[1].is_response());
+        assert_eq!(records[1].target_uri(), Some("https://example.com/"));
+        assert_eq!(records[1].record_id(), Some("<urn:uuid:response>"));
+
+        let response = records[1].http_response().unwrap();
+        assert_eq!(response.status, 200);
+        assert_eq!(
+            response.header("content-type"),
+            Some("text/html; charset=utf-8")
+        );
+        assert_eq!(response.body, b"<title>Hi</title>");
+    }
+
+    #[test]
+    fn test_gzipped_records() {
+        let mut warc = Vec::new();
+        for uri in ["https://a.example/", "https://b.example/"] {
+            let mut encoder =
+                flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+            encoder
+                .write_all(record("response", uri, "HTTP/1.1 200 OK\r\n\r\nx").as_bytes())
+                .unwrap();
+            warc.extend(encoder.finish().unwrap());
+        }
+
+        let reader = decompress::reader(Box::new(&warc[..]), None).unwrap();
+        let uris: Vec<_> = WarcReader::new(reader)
+            .map(|r| r.unwrap().target_uri().unwrap().to_string())
+            .collect();
+        assert_eq!(uris, ["https://a.example/", "https://b.example/"]);
+    }
+
+    #[test]
+    fn test_decoded_body() {
+        let mut gzipped = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        gzipped.write_all(b"<p>Hello</p>").unwrap();
+        let gzipped = gzipped.finish().unwrap();
+
+        let mut http = format!(
+            "HTTP/1.1 200 OK\r\nContent-Encoding: gzip\r\nTransfer-Encoding: chunked\r\n\r\n{:x}\r\n",
+            gzipped.len()
+        )
+        .into_bytes();
+        http.extend(&gzipped);
+        http.extend(b"\r\n0\r\n\r\n");
+
+        let response = HttpResponse::parse(&http).unwrap();
+        assert_eq!(response.decoded_body(), b"<p>Hello</p>");
+    }
+
+    #[test]
+    fn test_invalid_records() {
+        let mut reader = WarcReader::new(&b"<html></html>"[..]);
+        assert!(reader.next().unwrap().is_err());
+
+        let truncated = "WARC/1.0\r\nContent-Length: 100\r\n\r\nshort";
+        let mut reader = WarcReader::new(truncated.as_bytes());
+        assert!(reader.next().unwrap().is_err());
+    }
+}
@@ -0,0 +1,429 @@
+use crate::HqError;
+use html5ever::{LocalName, Namespace, QualName, namespace_url, ns};
+use kuchikiki::{Attribute, ExpandedName, NodeData, NodeRef};
+use quick_xml::NsReader;
+use quick_xml::escape::{escape, partial_escape, resolve_predefined_entity};
+use quick_xml::events::{BytesStart, Event};
+use quick_xml::name::ResolveResult;
+use std::fmt;
+use std::io::{self, Write};
+
+/// Parse a well-formed XML document into the same kind of tree HTML is parsed into, so it can
+/// be queried and serialized the same way.
+///
+/// Elements keep their namespace, and their prefix as part of their name: `<image:loc>` is an
+/// element named `image:loc`, which a selector matches as `image|loc` after
+/// [`qualify_selector`]. Attributes are named as written, namespace declarations included.
+/// CDATA sections become text.
+pub fn parse(xml: &str) -> Result<NodeRef, HqError> {
+    let mut reader = NsReader::from_str(xml);
+    let document = NodeRef::new_document();
+    let mut open = vec![document.clone()];
+
+    loop {
+        let position = reader.buffer_position();
+        let (namespace, event) = match reader.read_resolved_event() {
+            Ok((namespace, event)) => (namespace_url(namespace), event),
+            Err(e) => return Err(syntax_error(xml, reader.error_position(), e)),
+        };
+        let parent = open.last().unwrap_or(&document).clone();
+
+        match event {
+            Event::Start(_) | Event::Empty(_) if parent == document && has_root(&parent) => {
+                return Err(syntax_error(xml, position, "more than one root element"));
+            }
+            Event::Start(start) => {
+                let element = element(&start, namespace, xml, position)?;
+                parent.append(element.clone());
+                open.push(element);
+            }
+            Event::Empty(start) => {
+                parent.append(element(&start, namespace, xml, position)?);
+            }
+            Event::End(_) => {
+                open.pop();
+            }
+            Event::Text(text) => {
+                let text = text
+                    .xml_content()
+                    .map_err(|e| syntax_error(xml, position, e))?;
+                append_text(&parent, &text, xml, position)?;
+            }
+            Event::CData(cdata) => {
+                let text = cdata
+                    .xml_content()
+                    .map_err(|e| syntax_error(xml, position, e))?;
+                append_text(&parent, &text, xml, position)?;
+            }
+            Event::GeneralRef(reference) => {
+                let text = match reference.resolve_char_ref() {
+                    Ok(Some(c)) => c.to_string(),
+                    Ok(None) => {
+                        let name = reference
+                            .decode()
+                            .map_err(|e| syntax_error(xml, position, e))?;
+                        resolve_predefined_entity(&name)
+                            .ok_or_else(|| {
+                                syntax_error(xml, position, format!("unknown entity &{name};"))
+                            })?
+                            .to_string()
+                    }
+                    Err(e) => return Err(syntax_error(xml, position, e)),
+                };
+                append_text(&parent, &text, xml, position)?;
+            }
+            Event::Comment(comment) => {
+                let comment = comment
+                    .decode()
+                    .map_err(|e| syntax_error(xml, position, e))?;
+                parent.append(NodeRef::new_comment(comment));
+            }
+            Event::PI(pi) => {
+                parent.append(NodeRef::new_processing_instruction(
+                    String::from_utf8_lossy(pi.target()),
+                    String::from_utf8_lossy(pi.content()).trim(),
+                ));
+            }
+            Event::DocType(doctype) => {
+                let doctype = doctype
+                    .decode()
+                    .map_err(|e| syntax_error(xml, position, e))?;
+                parent.append(NodeRef::new_doctype(doctype.trim(), "", ""));
+            }
+            Event::Decl(_) => {}
+            Event::Eof => break,
+        }
+    }
+
+    if let Some(unclosed) = open.last().and_then(|node| node.as_element()) {
+        return Err(syntax_error(
+            xml,
+            xml.len() as u64,
+            format!("unclosed element <{}>", &*unclosed.name.local),
+        ));
+    }
+    if !has_root(&document) {
+        return Err(syntax_error(xml, 0, "no root element"));
+    }
+
+    Ok(document)
+}
+
+fn has_root(document: &NodeRef) -> bool {
+    document
+        .children()
+        .any(|child| child.as_element().is_some())
+}
+
+/// The URL of a resolved namespace, or why a prefix couldn't be resolved.
+fn namespace_url(namespace: ResolveResult<'_>) -> Result<Namespace, String> {
+    match namespace {
+        ResolveResult::Bound(namespace) => Ok(Namespace::from(&*String::from_utf8_lossy(
+            namespace.as_ref(),
+        ))),
+        ResolveResult::Unbound => Ok(ns!()),
+        ResolveResult::Unknown(prefix) => Err(format!(
+            "undeclared namespace prefix `{}`",
+            String::from_utf8_lossy(&prefix)
+        )),
+    }
+}
+
+fn element(
+    start: &BytesStart<'_>,
+    namespace: Result<Namespace, String>,
+    xml: &str,
+    position: u64,
+) -> Result<NodeRef, HqError> {
+    let namespace = namespace.map_err(|e| syntax_error(xml, position, e))?;
+    let name = String::from_utf8_lossy(start.name().as_ref()).into_owned();
+
+    let mut attributes = Vec::new();
+    for attribute in start.attributes() {
+        let attribute = attribute.map_err(|e| syntax_error(xml, position, e))?;
+        let value = attribute
+            .unescape_value()
+            .map_err(|e| syntax_error(xml, position, e))?;
+        attributes.push((
+            ExpandedName::new(
+                ns!(),
+                LocalName::from(&*String::from_utf8_lossy(attribute.key.as_ref())),
+            ),
+            Attribute {
+                prefix: None,
+                value: value.into_owned(),
+            },
+        ));
+    }
+
+    Ok(NodeRef::new_element(
+        QualName::new(None, namespace, LocalName::from(name)),
+        attributes,
+    ))
+}
+
+/// Append text to `parent`, joining it to any text right before it: entities and CDATA sections
+/// are read separately from the text around them.
+fn append_text(parent: &NodeRef, text: &str, xml: &str, position: u64) -> Result<(), HqError> {
+    if parent.as_document().is_some() {
+        if text.trim().is_empty() {
+            return Ok(());
+        }
+        return Err(syntax_error(xml, position, "text outside the root element"));
+    }
+
+    match parent.last_child() {
+        Some(last) if last.as_text().is_some() => {
+            last.as_text().unwrap().borrow_mut().push_str(text);
+        }
+        _ => parent.append(NodeRef::new_text(text)),
+    }
+    Ok(())
+}
+
+fn syntax_error(xml: &str, position: u64, error: impl fmt::Display) -> HqError {
+    let before = &xml.as_bytes()[..(position as usize).min(xml.len())];
+    let line = before.iter().filter(|&&b| b == b'\n').count() + 1;
+    let column = before.len()
+        - before
+            .iter()
+            .rposition(|&b| b == b'\n')
+            .map_or(0, |i| i + 1)
+        + 1;
+    HqError::Input(format!(
+        "Invalid XML at line {line}, column {column}: {error}"
+    ))
+}
+
+/// Rewrite namespaced type and attribute selectors like `image|loc` to match the prefixed names
+/// elements and attributes get in [`parse`]: `image\:loc`.
+pub fn qualify_selector(selector: &str) -> String {
+    let mut qualified = String::with_capacity(selector.len());
+    let mut chars = selector.chars().peekable();
+    let mut quote = None;
+    let mut after_name = false;
+
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => {
+                qualified.push(c);
+                qualified.extend(chars.next());
+                after_name = true;
+                continue;
+            }
+            '"' | '\'' if quote.is_none() => quote = Some(c),
+            c if quote == Some(c) => quote = None,
+            // `[lang|=en]` and `*|loc` stay as they are
+            '|' if quote.is_none()
+                && after_name
+                && chars
+                    .peek()
+                    .is_some_and(|&next| next.is_alphabetic() || next == '_') =>
+            {
+                qualified.push_str("\\:");
+                after_name = false;
+                continue;
+            }
+            _ => {}
+        }
+        qualified.push(c);
+        after_name = quote.is_none() && (c.is_alphanumeric() || c == '-' || c == '_');
+    }
+
+    qualified
+}
+
+/// Serialize `node` as XML. Namespaces declared on its ancestors are declared on it, so the
+/// output is well-formed on its own.
+pub fn serialize(node: &NodeRef, output: &mut dyn Write) -> io::Result<()> {
+    write_node(node, &inherited_namespaces(node), output)
+}
+
+/// The namespace declarations in scope at `node` that it doesn't make itself.
+fn inherited_namespaces(node: &NodeRef) -> Vec<(String, String)> {
+    let mut declarations: Vec<(String, String)> = Vec::new();
+    if node.as_element().is_none() {
+        return declarations;
+    }
+
+    for element in node
+        .inclusive_ancestors()
+        .filter_map(|n| n.as_element().cloned())
+    {
+        for (name, attribute) in &element.attributes.borrow().map {
+            let name = &*name.local;
+            if (name == "xmlns" || name.starts_with("xmlns:"))
+                && !declarations.iter().any(|(declared, _)| declared == name)
+            {
+                declarations.push((name.to_string(), attribute.value.clone()));
+            }
+        }
+    }
+
+    // those made by the node itself are written along with its other attributes
+    let own = &node.as_element().unwrap().attributes;
+    declarations.retain(|(name, _)| !own.borrow().contains(name.as_str()));
+    declarations
+}
+
+fn write_node(
+    node: &NodeRef,
+    extra_attributes: &[(String, String)],
+    output: &mut dyn Write,
+) -> io::Result<()> {
+    match node.data() {
+        NodeData::Element(element) => {
+            let name = &*element.name.local;
+            write!(output, "<{name}")?;
+            for (attribute, value) in element
+                .attributes
+                .borrow()
+                .map
+                .iter()
+                .map(|(name, attribute)| (&*name.local, attribute.value.as_str()))
+                .chain(
+                    extra_attributes
+                        .iter()
+                        .map(|(n, v)| (n.as_str(), v.as_str())),
+                )
+            {
+                write!(output, " {attribute}=\"{}\"", escape(value))?;
+            }
+
+            if node.first_child().is_none() {
+                return write!(output, "/>");
+            }
+            write!(output, ">")?;
+            for child in node.children() {
+                write_node(&child, &[], output)?;
+            }
+            write!(output, "</{name}>")
+        }
+        NodeData::Text(text) => write!(output, "{}", partial_escape(text.borrow().as_str())),
+        NodeData::Comment(comment) => write!(output, "<!--{}-->", comment.borrow()),
+        NodeData::ProcessingInstruction(pi) => match &*pi.borrow() {
+            (target, data) if data.is_empty() => write!(output, "<?{target}?>"),
+            (target, data) => write!(output, "<?{target} {data}?>"),
+        },
+        NodeData::Doctype(doctype) => write!(output, "<!DOCTYPE {}>", doctype.name),
+        NodeData::Document(_) | NodeData::DocumentFragment => {
+            for (i, child) in node.children().enumerate() {
+                if i > 0 {
+                    writeln!(output)?;
+                }
+                write_node(&child, &[], output)?;
+            }
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn to_xml(node: &NodeRef) -> String {
+        let mut output = Vec::new();
+        serialize(node, &mut output).unwrap();
+        String::from_utf8(output).unwrap()
+    }
+
+    const SITEMAP: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<urlset xmlns="http://www.sitemaps.org/schemas/sitemap/0.9"
+        xmlns:image="http://www.google.com/schemas/sitemap-image/1.1">
+  <url>
+    <loc>https://example.com/?a=1&amp;b=2</loc>
+    <image:image><image:loc>https://example.com/a.png</image:loc></image:image>
+  </url>
+</urlset>"#;
+
+    #[test]
+    fn test_parse() {
+        let document = parse(SITEMAP).unwrap();
+        let urlset = document.first_child().unwrap();
+        let urlset = urlset.as_element().unwrap();
+        assert_eq!(&*urlset.name.local, "urlset");
+        assert_eq!(
+            &*urlset.name.ns,
+            "http://www.sitemaps.org/schemas/sitemap/0.9"
+        );
+
+        let loc = document.select_first("url > loc").unwrap();
+        assert_eq!(loc.text_contents(), "https://example.com/?a=1&b=2");
+
+        let image = document.select_first(r"image\:loc").unwrap();
+        assert_eq!(
+            &*image.name.ns,
+            "http://www.google.com/schemas/sitemap-image/1.1"
+        );
+    }
+
+    #[test]
+    fn test_cdata() {
+        let document = parse("<a><b><![CDATA[1 < 2 & <c>]]> and 3 &gt; 2</b></a>").unwrap();
+        let b = document.select_first("b").unwrap();
+        assert_eq!(b.text_contents(), "1 < 2 & <c> and 3 > 2");
+        assert_eq!(
+            to_xml(b.as_node()),
+            "<b>1 &lt; 2 &amp; &lt;c&gt; and 3 &gt; 2</b>"
+        );
+    }
+
+    #[test]
+    fn test_serialize() {
+        let document = parse(SITEMAP).unwrap();
+        let image = document.select_first(r"image\:image").unwrap();
+        assert_eq!(
+            to_xml(image.as_node()),
+            r#"<image:image xmlns="http://www.sitemaps.org/schemas/sitemap/0.9" xmlns:image="http://www.google.com/schemas/sitemap-image/1.1"><image:loc>https://example.com/a.png</image:loc></image:image>"#
+        );
+
+        let document = parse(r#"<a x="&quot;1&quot;"><!--c--><?pi data?><b/></a>"#).unwrap();
+        assert_eq!(
+            to_xml(&document.first_child().unwrap()),
+            r#"<a x="&quot;1&quot;"><!--c--><?pi data?><b/></a>"#
+        );
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        for (xml, error) in [
+            (
+                "<a><b></a>",
+                "Invalid XML at line 1, column 7: ill-formed document: expected `</b>`, but `</a>` was found",
+            ),
+            (
+                "<a>\n<b>",
+                "Invalid XML at line 2, column 4: unclosed element <b>",
+            ),
+            (
+                "<a/><b/>",
+                "Invalid XML at line 1, column 5: more than one root element",
+            ),
+            (
+                "<x:a/>",
+                "Invalid XML at line 1, column 1: undeclared namespace prefix `x`",
+            ),
+            (
+                "<a>&nbsp;</a>",
+                "Invalid XML at line 1, column 4: unknown entity &nbsp;",
+            ),
+            ("", "Invalid XML at line 1, column 1: no root element"),
+        ] {
+            assert_eq!(parse(xml).unwrap_err().to_string(), error, "{xml}");
+        }
+    }
+
+    #[test]
+    fn test_qualify_selector() {
+        assert_eq!(qualify_selector("url > loc"), "url > loc");
+        assert_eq!(
+            qualify_selector("image|image > image|loc"),
+            r"image\:image > image\:loc"
+        );
+        assert_eq!(qualify_selector("[xlink|href]"), r"[xlink\:href]");
+        assert_eq!(qualify_selector("[lang|=en]"), "[lang|=en]");
+        assert_eq!(qualify_selector("*|loc"), "*|loc");
+        assert_eq!(qualify_selector(r#"[title="a|b"]"#), r#"[title="a|b"]"#);
+    }
+}
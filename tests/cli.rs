@@ -30,6 +30,67 @@ cmd_success_tests!(
         ["#my-id"],
         "<div id=\"my-id\"><a href=\"/foo/bar\">Hello</a></div>\n"
     ),
+    base_rewrites_nested_urls: (
+        "<html><body><div><a href=\"/a\">A</a><img src=\"b.png\" srcset=\"b.png 1x, //cdn.example/b2.png 2x\"></div></body></html>",
+        ["body", "--base", "https://example.com/dir/"],
+        "<body><div><a href=\"https://example.com/a\">A</a><img src=\"https://example.com/dir/b.png\" srcset=\"https://example.com/dir/b.png 1x, https://cdn.example/b2.png 2x\"></div></body>\n",
+    ),
+    url_resolves_relative_base: (
+        "<html><head><base href=\"../jobs/\"></head><body><a href=\"42\">Job</a></body></html>",
+        ["a", "--url", "https://example.com/en/about", "--absolutize", "-a", "href"],
+        "https://example.com/jobs/42\n",
+    ),
+    url_alone_keeps_relative_urls: (
+        "<html><head><base href=\"../jobs/\"></head><body><a href=\"42\">Job</a></body></html>",
+        ["a", "--url", "https://example.com/en/about"],
+        "<a href=\"42\">Job</a>\n",
+    ),
+    links_deduped_by_normalized_url: (
+        "<main><a href=\"/a/\">A</a><a href=\"/a\">A again</a><a href=\"/a?utm_source=x\">A tracked</a><a href=\"/b\">B</a></main>",
+        ["main", "--links", "--dedupe", "--url", "https://e.com/"],
+        concat!(
+            "{\"internal\":true,\"nofollow\":false,\"rel\":[],\"text\":\"A\",\"title\":null,\"url\":\"https://e.com/a/\"}\n",
+            "{\"internal\":true,\"nofollow\":false,\"rel\":[],\"text\":\"B\",\"title\":null,\"url\":\"https://e.com/b\"}\n",
+        ),
+    ),
+    links_deduped: (
+        "<html><body><nav><a href=\"/\">Home</a></nav><main><a href=\"jobs/1#apply\" rel=\"nofollow\">Job</a><a href=\"jobs/1\">Job again</a><a href=\"https://other.example/\" title=\"Other\">Other</a></main></body></html>",
+        ["main", "--links", "--dedupe", "--url", "https://example.com/"],
+        concat!(
+            "{\"internal\":true,\"nofollow\":true,\"rel\":[\"nofollow\"],\"text\":\"Job\",\"title\":null,\"url\":\"https://example.com/jobs/1#apply\"}\n",
+            "{\"internal\":false,\"nofollow\":false,\"rel\":[],\"text\":\"Other\",\"title\":\"Other\",\"url\":\"https://other.example/\"}\n",
+        ),
+    ),
+    links_filtered: (
+        "<a href=\"/jobs/1\">1</a><a href=\"/about\">About</a><a href=\"https://other.example/jobs/2\">2</a>",
+        ["--links", "--same-host", "--path-prefix", "/jobs/", "-u", "https://example.com/", "-j", ".url"],
+        "https://example.com/jobs/1\n",
+    ),
+    links_normalized: (
+        "<a href=\"/jobs/42/?utm_source=feed&amp;session=1&amp;lang=en#apply\">A</a><a href=\"https://EXAMPLE.com:443/jobs/42?lang=en\">B</a>",
+        ["--links", "--dedupe", "-N", "--strip-param", "session", "--surt", "-u", "https://example.com/"],
+        "{\"internal\":true,\"nofollow\":false,\"rel\":[],\"surt\":\"com,example)/jobs/42?lang=en\",\"text\":\"A\",\"title\":null,\"url\":\"https://example.com/jobs/42?lang=en\"}\n",
+    ),
+    xml_namespaced_selector: (
+        "<?xml version=\"1.0\"?><urlset xmlns=\"http://www.sitemaps.org/schemas/sitemap/0.9\" xmlns:image=\"http://www.google.com/schemas/sitemap-image/1.1\"><url><loc>https://example.com/</loc><image:image><image:loc><![CDATA[https://example.com/a.png?x=1&y=2]]></image:loc></image:image></url></urlset>",
+        ["image|image", "--xml"],
+        "<image:image xmlns=\"http://www.sitemaps.org/schemas/sitemap/0.9\" xmlns:image=\"http://www.google.com/schemas/sitemap-image/1.1\"><image:loc>https://example.com/a.png?x=1&amp;y=2</image:loc></image:image>\n",
+    ),
+    pretty_print_layout: (
+        "<html><body><div class=\"job\" id=\"job-42\" data-source=\"ats\"><h2>Senior   <b>Rust</b> <i>Engineer</i></h2><pre>cargo  build\n  --release</pre><br><img src=\"a.png\"></div></body></html>",
+        ["div", "-p", "--indent", "4", "--wrap-attributes", "30"],
+        "<div\n    class=\"job\"\n    id=\"job-42\"\n    data-source=\"ats\">\n    <h2>Senior <b>Rust</b> <i>Engineer</i></h2>\n    <pre>cargo  build\n  --release</pre>\n    <br><img src=\"a.png\">\n</div>\n",
+    ),
+    color_always: (
+        "<html><body><p class=\"intro\">Hi <!-- note --></p></body></html>",
+        ["p", "--color", "always"],
+        "\x1b[34m<p\x1b[0m \x1b[36mclass\x1b[0m=\x1b[32m\"intro\"\x1b[0m\x1b[34m>\x1b[0m\x1b[33mHi \x1b[0m\x1b[90m<!-- note -->\x1b[0m\x1b[34m</p>\x1b[0m\n",
+    ),
+    color_never_when_compacted: (
+        "<html><body><p>Hi</p></body></html>",
+        ["p", "--color", "always", "-c"],
+        "<p>Hi</p>",
+    ),
     remove_links: (
         "<html><head></head><body><div id=\"my-id\"><a href=\"/foo/bar\">Hello</a></div></body></html>",
         ["#my-id", "--remove-nodes", "a"],
@@ -60,6 +121,26 @@ cmd_success_tests!(
         ["body", "-c"],
         "<body><div><p>Text</p></div></body>"
     ),
+    compact_html_keeps_inline_spaces: (
+        "<p>Tom  &amp;\n <b>Jerry</b> <i>&gt; 1</i></p>\n<pre>  a\n  b</pre>",
+        ["body", "-c"],
+        "<body><p>Tom &amp; <b>Jerry</b> <i>&gt; 1</i></p><pre>  a\n  b</pre></body>"
+    ),
+    compact_html_optional_steps: (
+        "<ul>\n  <li class=\"a\">One</li>\n  <!-- c -->\n  <li>Two</li>\n</ul><input disabled=\"disabled\">",
+        ["body", "-c", "--remove-comments", "--omit-end-tags", "--unquote-attributes", "--collapse-boolean-attributes"],
+        "<body><ul><li class=a>One<li>Two</ul><input disabled></body>"
+    ),
+    outline: (
+        "<ul id=\"jobs\">\n  <li class=\"job\"><a href=\"/1\">Rust</a></li>\n  <li class=\"job\"><a href=\"/2\">Go</a></li>\n  <li class=\"job\"><a href=\"/3\">Zig</a></li>\n  <li>More</li>\n</ul>",
+        ["ul", "--outline"],
+        "ul#jobs\n  li.job × 3\n    a[href=\"/1\"] \"Rust\"\n  li \"More\"\n"
+    ),
+    outline_depth: (
+        "<main><h1>Jobs</h1><ul><li>Rust</li></ul></main>",
+        ["main", "--outline", "--depth", "1"],
+        "main\n  h1 \"Jobs\"\n  ul …\n"
+    ),
     compact_text_plain: (
         "<html><body><div>  \n  Hello World  \n  </div></body></html>",
         ["div", "-t", "-c"],
@@ -70,4 +151,611 @@ cmd_success_tests!(
         ["script", "-t"],
         "\n{\n  \"title\": \"Test\"\n}\n\n"
     ),
+    json_path_field: (
+        "<html><body><script type=\"application/ld+json\">{\"hiringOrganization\": {\"name\": \"Acme Corp\"}}</script></body></html>",
+        ["script", "-t", "--json-path", ".hiringOrganization.name"],
+        "Acme Corp\n"
+    ),
+    json_path_each_value: (
+        "<html><body><script>{\"id\": 1, \"tags\": [\"a\"]}</script><script>{\"id\": 2, \"tags\": [\"b\", \"c\"]}</script></body></html>",
+        ["script", "-t", "-c", "--json-path", "$.tags[*]"],
+        "a\nb\nc\n"
+    ),
+    fix_encoding_text: (
+        "<html><body><h1>Weâ€™re hiring in MÃ¼nchen</h1></body></html>",
+        ["h1", "-t", "--fix-encoding"],
+        "We’re hiring in München\n"
+    ),
+    fix_encoding_attribute: (
+        "<html><body><a title=\"CafÃ© â€“ Paris\">x</a></body></html>",
+        ["a", "-a", "title", "--fix-encoding"],
+        "Café – Paris\n"
+    ),
+    first_match: (
+        "<html><body><p>One</p><p>Two</p></body></html>",
+        ["p", "--first"],
+        "<p>One</p>\n"
+    ),
+    stream_matches: (
+        "<html><body><ul><li>One</li><li>Two<ul><li>Three</li></ul></li></ul></body></html>",
+        ["li > ul > li, body > ul > li", "--stream", "-t"],
+        "One\nTwoThree\nThree\n"
+    ),
+    stream_first_match: (
+        "<html><body><p>One</p><p>Two</p></body></html>",
+        ["p", "--stream", "--first"],
+        "<p>One</p>\n"
+    ),
+    stream_falls_back_to_tree: (
+        "<html><body><p>One</p></body></html>",
+        ["body", "--stream", "-t"],
+        "One\n"
+    ),
+    stream_falls_back_for_attribute_on_html: (
+        "<html lang=en><head><title>t</title></head><body class=b><p>x</p></body></html>",
+        ["[lang]", "--stream"],
+        "<html lang=\"en\"><head><title>t</title></head><body class=\"b\"><p>x</p></body></html>\n"
+    ),
+    stream_falls_back_for_class_on_body: (
+        "<html lang=en><head><title>t</title></head><body class=b><p>x</p></body></html>",
+        [".b", "--stream"],
+        "<body class=\"b\"><p>x</p></body>\n"
+    ),
+    stream_falls_back_for_universal_selector: (
+        "<html lang=en><head><title>t</title></head><body class=b><p>x</p></body></html>",
+        ["*", "--stream", "-t"],
+        "tx\nt\nt\nx\nx\n"
+    ),
+    stream_falls_back_for_remove_nodes: (
+        "<section><div><span>y</span>z</div></section>",
+        ["div", "--stream", "-r", "section span"],
+        "<div>z</div>\n"
+    ),
 );
+
+#[test]
+fn decodes_meta_charset() {
+    // "求人" in Shift_JIS
+    let mut html = b"<html><head><meta charset=\"shift_jis\"><title>".to_vec();
+    html.extend_from_slice(&[0x8b, 0x81, 0x90, 0x6c]);
+    html.extend_from_slice(b"</title></head></html>");
+
+    Command::cargo_bin("hq")
+        .unwrap()
+        .args(["title", "-t", "--verbose"])
+        .write_stdin(html)
+        .assert()
+        .success()
+        .stdout(predicate::str::diff("求人\n"))
+        .stderr(predicate::str::contains("Shift_JIS (<meta> charset)"));
+}
+
+#[test]
+fn encoding_override() {
+    Command::cargo_bin("hq")
+        .unwrap()
+        .args(["p", "-t", "--encoding", "iso-8859-1"])
+        .write_stdin(b"<p>Caf\xe9</p>".to_vec())
+        .assert()
+        .success()
+        .stdout(predicate::str::diff("Café\n"));
+}
+
+#[test]
+fn decompresses_gzip_input() {
+    use std::io::Write;
+
+    let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+    encoder
+        .write_all(b"<html><body><p>Compressed</p></body></html>")
+        .unwrap();
+
+    Command::cargo_bin("hq")
+        .unwrap()
+        .args(["p", "-t"])
+        .write_stdin(encoder.finish().unwrap())
+        .assert()
+        .success()
+        .stdout(predicate::str::diff("Compressed\n"));
+}
+
+#[test]
+fn stream_falls_back_to_tree_for_compressed_input() {
+    use std::io::Write;
+
+    // UTF-16 can't be streamed, so the whole decompressed input goes to the tree engine
+    let html: Vec<u8> = "\u{feff}<html><body><p>Café</p></body></html>"
+        .encode_utf16()
+        .flat_map(u16::to_le_bytes)
+        .collect();
+    let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+    encoder.write_all(&html).unwrap();
+
+    let dir = temp_dir("stream_compressed");
+    std::fs::write(dir.join("page.html.gz"), encoder.finish().unwrap()).unwrap();
+
+    Command::cargo_bin("hq")
+        .unwrap()
+        .current_dir(&dir)
+        .args(["--stream", "-t", "p", "page.html.gz"])
+        .assert()
+        .success()
+        .stdout(predicate::str::diff("Café\n"));
+}
+
+#[test]
+fn gzipped_xml_sitemap_index() {
+    use std::io::Write;
+
+    let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+    encoder
+        .write_all(
+            br#"<?xml version="1.0" encoding="UTF-8"?>
+<sitemapindex xmlns="http://www.sitemaps.org/schemas/sitemap/0.9">
+  <sitemap><loc>https://example.com/sitemap-1.xml.gz</loc></sitemap>
+  <sitemap><loc>https://example.com/sitemap-2.xml.gz</loc></sitemap>
+</sitemapindex>"#,
+        )
+        .unwrap();
+
+    Command::cargo_bin("hq")
+        .unwrap()
+        .args(["sitemap > loc", "--xml", "-t"])
+        .write_stdin(encoder.finish().unwrap())
+        .assert()
+        .success()
+        .stdout(predicate::str::diff(
+            "https://example.com/sitemap-1.xml.gz\nhttps://example.com/sitemap-2.xml.gz\n",
+        ));
+
+    Command::cargo_bin("hq")
+        .unwrap()
+        .args(["loc", "--xml"])
+        .write_stdin("<sitemapindex><sitemap><loc>x</sitemap></sitemapindex>")
+        .assert()
+        .code(3)
+        .stderr(predicate::str::contains("Invalid XML at line 1, column 30"));
+}
+
+/// An empty directory for a test's files, without any left over from earlier runs.
+fn temp_dir(name: &str) -> std::path::PathBuf {
+    let dir = std::path::Path::new(env!("CARGO_TARGET_TMPDIR")).join(name);
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+fn fixture_dir(name: &str) -> std::path::PathBuf {
+    let dir = temp_dir(name);
+    std::fs::create_dir_all(dir.join("nested")).unwrap();
+    for (file, title) in [("b.html", "B"), ("a.html", "A"), ("nested/c.html", "C")] {
+        std::fs::write(
+            dir.join(file),
+            format!("<html><head><title>{title}</title></head></html>"),
+        )
+        .unwrap();
+    }
+    dir
+}
+
+#[test]
+fn multiple_files_with_filenames() {
+    let dir = fixture_dir("multiple_files");
+    let a = dir.join("a.html");
+    let b = dir.join("b.html");
+    let missing = dir.join("missing.html");
+
+    Command::cargo_bin("hq")
+        .unwrap()
+        .args(["title", "-t"])
+        .arg(&b)
+        .arg(&missing)
+        .arg(&a)
+        .assert()
+        .failure()
+        .stdout(predicate::str::diff(format!(
+            "{}:B\n{}:A\n",
+            b.display(),
+            a.display()
+        )))
+        .stderr(predicate::str::contains("missing.html"));
+}
+
+#[test]
+fn recursive_directory() {
+    let dir = fixture_dir("recursive_directory");
+
+    Command::cargo_bin("hq")
+        .unwrap()
+        .args(["title", "-t", "--recursive", "--no-filename"])
+        .arg(&dir)
+        .assert()
+        .success()
+        .stdout(predicate::str::diff("A\nB\nC\n"));
+}
+
+#[test]
+fn glob_pattern() {
+    let dir = fixture_dir("glob_pattern");
+
+    Command::cargo_bin("hq")
+        .unwrap()
+        .args(["title", "-t"])
+        .arg(dir.join("*.html"))
+        .assert()
+        .success()
+        .stdout(predicate::str::diff(format!(
+            "{}:A\n{}:B\n",
+            dir.join("a.html").display(),
+            dir.join("b.html").display()
+        )));
+}
+
+fn warc_record(warc_type: &str, uri: &str, id: &str, content: &[u8]) -> Vec<u8> {
+    use std::io::Write;
+
+    let mut record = format!(
+        "WARC/1.0\r\nWARC-Type: {warc_type}\r\nWARC-Target-URI: {uri}\r\nWARC-Date: 2024-03-01T12:00:00Z\r\nWARC-Record-ID: <urn:uuid:{id}>\r\nContent-Length: {}\r\n\r\n",
+        content.len()
+    )
+    .into_bytes();
+    record.extend_from_slice(content);
+    record.extend_from_slice(b"\r\n\r\n");
+
+    let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+    encoder.write_all(&record).unwrap();
+    encoder.finish().unwrap()
+}
+
+#[test]
+fn warc_records() {
+    let dir = temp_dir("warc_records");
+    let path = dir.join("crawl.warc.gz");
+
+    let request = warc_record(
+        "request",
+        "https://a.example/",
+        "1",
+        b"GET / HTTP/1.1\r\n\r\n",
+    );
+    let first = warc_record(
+        "response",
+        "https://a.example/",
+        "2",
+        b"HTTP/1.1 200 OK\r\nContent-Type: text/html\r\n\r\n<title>First</title>",
+    );
+    let image = warc_record(
+        "response",
+        "https://a.example/logo.png",
+        "3",
+        b"HTTP/1.1 200 OK\r\nContent-Type: image/png\r\n\r\n<title>Not HTML</title>",
+    );
+    let second = warc_record(
+        "response",
+        "https://b.example/",
+        "4",
+        b"HTTP/1.1 200 OK\r\nContent-Type: text/html; charset=iso-8859-1\r\n\r\n<title>Caf\xe9</title>",
+    );
+    let second_offset = request.len() + first.len() + image.len();
+    std::fs::write(&path, [request, first, image, second.clone()].concat()).unwrap();
+
+    Command::cargo_bin("hq")
+        .unwrap()
+        .args(["title", "-t", "--warc"])
+        .arg(&path)
+        .assert()
+        .success()
+        .stdout(predicate::str::diff(
+            "https://a.example/\t2024-03-01T12:00:00Z\t<urn:uuid:2>\tFirst\n\
+             https://b.example/\t2024-03-01T12:00:00Z\t<urn:uuid:4>\tCafé\n",
+        ));
+
+    Command::cargo_bin("hq")
+        .unwrap()
+        .args(["title", "-t", "--warc"])
+        .args(["--offset", &second_offset.to_string()])
+        .args(["--length", &second.len().to_string()])
+        .arg(&path)
+        .assert()
+        .success()
+        .stdout(predicate::str::diff(
+            "https://b.example/\t2024-03-01T12:00:00Z\t<urn:uuid:4>\tCafé\n",
+        ));
+}
+
+#[test]
+fn warc_index_and_lookup() {
+    let dir = temp_dir("warc_index");
+
+    let records = [
+        warc_record(
+            "response",
+            "https://b.example/jobs",
+            "1",
+            b"HTTP/1.1 200 OK\r\nContent-Type: text/html\r\n\r\n<title>B</title>",
+        ),
+        warc_record(
+            "response",
+            "https://a.example/",
+            "2",
+            b"HTTP/1.1 200 OK\r\nContent-Type: text/html\r\n\r\n<title>A</title>",
+        ),
+    ];
+    let first_length = records[0].len();
+    std::fs::write(dir.join("crawl.warc.gz"), records.concat()).unwrap();
+
+    Command::cargo_bin("hq")
+        .unwrap()
+        .current_dir(&dir)
+        .args(["index", "crawl.warc.gz", "-o", "index.cdxj"])
+        .assert()
+        .success();
+
+    let index = std::fs::read_to_string(dir.join("index.cdxj")).unwrap();
+    let lines: Vec<_> = index.lines().collect();
+    assert_eq!(lines.len(), 2);
+    assert!(lines[0].starts_with("example,a)/ 20240301120000 {"));
+    assert!(lines[0].contains(&format!("\"offset\":\"{first_length}\"")));
+    assert!(lines[1].starts_with("example,b)/jobs 20240301120000 {"));
+
+    Command::cargo_bin("hq")
+        .unwrap()
+        .args([
+            "title",
+            "-t",
+            "--lookup",
+            "https://www.b.example/jobs",
+            "--cdx",
+        ])
+        .arg(dir.join("index.cdxj"))
+        .assert()
+        .success()
+        .stdout(predicate::str::diff(
+            "https://b.example/jobs\t2024-03-01T12:00:00Z\t<urn:uuid:1>\tB\n",
+        ));
+
+    Command::cargo_bin("hq")
+        .unwrap()
+        .args(["title", "--lookup", "https://c.example/", "--cdx"])
+        .arg(dir.join("index.cdxj"))
+        .assert()
+        .failure();
+}
+
+#[test]
+fn diff_documents() {
+    let dir = temp_dir("diff");
+    std::fs::write(
+        dir.join("old.html"),
+        "<div id=\"job\" class=\"posting\">\n  <h1>Engineer</h1>\n  <ul><li>Pension</li></ul>\n  <a href=\"/apply?ref=1\">Apply</a>\n  <time>Monday</time>\n</div>",
+    )
+    .unwrap();
+    std::fs::write(
+        dir.join("new.html"),
+        "<div class=\"posting\" id=\"job\"><h1>Senior Engineer</h1><ul><li>Pension</li><li>Remote</li></ul><a href=\"/apply?ref=2\">Apply</a><time>Tuesday</time></div>",
+    )
+    .unwrap();
+
+    Command::cargo_bin("hq")
+        .unwrap()
+        .current_dir(&dir)
+        .args(["diff", "old.html", "new.html", "#job", "-r", "time"])
+        .assert()
+        .success()
+        .stdout(predicate::str::diff(concat!(
+            "~ html > body > div#job > h1: \"Engineer\" -> \"Senior Engineer\"\n",
+            "+ html > body > div#job > ul > li:nth-of-type(2): <li>Remote</li>\n",
+            "~ html > body > div#job > a[href]: \"/apply?ref=1\" -> \"/apply?ref=2\"\n",
+        )));
+
+    Command::cargo_bin("hq")
+        .unwrap()
+        .current_dir(&dir)
+        .args(["diff", "--json", "old.html", "new.html", "h1"])
+        .assert()
+        .success()
+        .stdout(predicate::str::diff(
+            "{\"change\":\"text\",\"new\":\"Senior Engineer\",\"old\":\"Engineer\",\"path\":\"html > body > div#job > h1\"}\n",
+        ));
+
+    Command::cargo_bin("hq")
+        .unwrap()
+        .current_dir(&dir)
+        .args(["diff", "old.html", "old.html"])
+        .assert()
+        .success()
+        .stdout(predicate::str::is_empty());
+}
+
+#[test]
+fn fingerprint_and_dedupe() {
+    let dir = temp_dir("dedupe");
+    let posting = "We are looking for an experienced engineer to build our crawling infrastructure. \
+        You will work on parsers, storage and distributed scheduling. Remote work is possible \
+        within Europe, and we offer a pension plan, training budget and flexible hours.";
+    std::fs::write(
+        dir.join("a.html"),
+        format!("<nav>Acme</nav><div class=job><h1>Rust Engineer</h1><p>{posting}</p></div>"),
+    )
+    .unwrap();
+    std::fs::write(
+        dir.join("b.html"),
+        format!(
+            "<div class=job><script>track()</script><h2>Rust engineer</h2><div>{}</div></div>",
+            posting.replace("flexible hours", "free snacks")
+        ),
+    )
+    .unwrap();
+    std::fs::write(
+        dir.join("c.html"),
+        "<div class=job>Bakery assistant wanted for early shifts, serving customers.</div>",
+    )
+    .unwrap();
+
+    Command::cargo_bin("hq")
+        .unwrap()
+        .args(["--fingerprint", "h1"])
+        .write_stdin("<h1>Senior <b>Rust</b> engineer!</h1><h1>senior rust engineer, remote</h1>")
+        .assert()
+        .success()
+        .stdout(predicate::str::starts_with(
+            "{\"sha1\":\"3ce149d960370f70993c98476dbd28b51b3ef51d\",\"simhash\":\"",
+        ))
+        .stdout(predicate::str::contains(
+            "\"sha1\":\"e80f01de8568cb7c63ff298595aee841ed303678\"",
+        ));
+
+    let output = Command::cargo_bin("hq")
+        .unwrap()
+        .current_dir(&dir)
+        .args(["dedupe", "--selector", ".job", "a.html", "b.html", "c.html"])
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let clusters: Vec<(String, u64)> = String::from_utf8(output.stdout)
+        .unwrap()
+        .lines()
+        .map(|line| {
+            let json: serde_json::Value = serde_json::from_str(line).unwrap();
+            (
+                json["file"].as_str().unwrap().to_string(),
+                json["cluster"].as_u64().unwrap(),
+            )
+        })
+        .collect();
+    assert_eq!(
+        clusters,
+        [
+            ("a.html".to_string(), 0),
+            ("b.html".to_string(), 0),
+            ("c.html".to_string(), 1)
+        ]
+    );
+
+    Command::cargo_bin("hq")
+        .unwrap()
+        .current_dir(&dir)
+        .args(["dedupe", "--threshold", "1.5", "a.html"])
+        .assert()
+        .code(2);
+}
+
+#[test]
+fn explore_session() {
+    let dir = temp_dir("explore");
+    std::fs::write(
+        dir.join("page.html"),
+        "<ul><li class=\"job\"><a href=\"/1\">Rust engineer</a></li><li class=\"job\"><a href=\"/2\">Designer</a></li></ul>",
+    )
+    .unwrap();
+
+    // without a terminal, lines are read from stdin
+    Command::cargo_bin("hq")
+        .unwrap()
+        .current_dir(&dir)
+        .env("HOME", &dir)
+        .args(["explore", "page.html"])
+        .write_stdin("li.job a\n:attr href\n:parent 2\n:quit\n")
+        .assert()
+        .success()
+        .stdout(predicate::str::diff(concat!(
+            "2 matches\n[1] <a href=\"/1\">Rust engineer</a>\n[2] <a href=\"/2\">Designer</a>\n",
+            "2 matches\n[1] /1\n[2] /2\n",
+            "1 match\n[1] (none)\n",
+        )));
+    assert!(
+        std::fs::read_to_string(dir.join(".hq_history"))
+            .unwrap()
+            .contains(":attr href")
+    );
+}
+
+#[test]
+fn suggest_selectors() {
+    let dir = temp_dir("suggest");
+    std::fs::write(
+        dir.join("job.html"),
+        "<div class=\"css-1x2y3z\"><h1 class=\"job-title mt-4\">Senior Backend Engineer</h1></div>\
+        <h3>Similar jobs</h3><ul class=\"related\"><li><h3>Frontend Engineer</h3></li><li><h3>Data Engineer</h3></li></ul>",
+    )
+    .unwrap();
+
+    Command::cargo_bin("hq")
+        .unwrap()
+        .current_dir(&dir)
+        .args([
+            "suggest",
+            "--text",
+            "senior backend engineer",
+            "-n",
+            "2",
+            "job.html",
+        ])
+        .assert()
+        .success()
+        .stdout(predicate::str::diff(concat!(
+            "\"senior backend engineer\" in <h1 class=\"job-title mt-4\">:\n",
+            "  .job-title\n",
+            "  h1.job-title\n",
+        )));
+
+    Command::cargo_bin("hq")
+        .unwrap()
+        .current_dir(&dir)
+        .args([
+            "suggest",
+            "-t",
+            "Frontend Engineer",
+            "-t",
+            "Data Engineer",
+            "-n",
+            "1",
+        ])
+        .arg("job.html")
+        .assert()
+        .success()
+        .stdout(predicate::str::diff(concat!(
+            "\"Frontend Engineer\" in <h3>:\n",
+            "  .related > li:nth-child(1) > h3\n",
+            "\"Data Engineer\" in <h3>:\n",
+            "  .related > li:nth-child(2) > h3\n",
+            "All examples:\n",
+            "  .related h3 (2 matches)\n",
+        )));
+}
+
+#[test]
+fn exit_codes() {
+    Command::cargo_bin("hq")
+        .unwrap()
+        .args(["--exit-status", "table"])
+        .write_stdin("<p>Text</p>")
+        .assert()
+        .code(1)
+        .stdout("");
+
+    Command::cargo_bin("hq")
+        .unwrap()
+        .args(["p", "--encoding", "nope"])
+        .write_stdin("<p>Text</p>")
+        .assert()
+        .code(2)
+        .stderr(predicate::str::contains("Unknown encoding: nope"));
+
+    Command::cargo_bin("hq")
+        .unwrap()
+        .args(["p", "missing.html"])
+        .assert()
+        .code(3)
+        .stderr(predicate::str::contains("missing.html"));
+
+    Command::cargo_bin("hq")
+        .unwrap()
+        .args(["div > > p"])
+        .write_stdin("<p>Text</p>")
+        .assert()
+        .code(4)
+        .stderr(predicate::str::diff(
+            "hq: Invalid CSS selector: combinator without a selector after it\n  div > > p\n        ^\n",
+        ));
+}